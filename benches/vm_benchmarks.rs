@@ -1,5 +1,5 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use zkvm::{ZKVM, vm::Value};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use zkvm::{ZKVM, vm::{self, GasTable, Value, VM}};
 
 fn create_benchmark_program(size: usize) -> Vec<u8> {
     let mut program = Vec::with_capacity(size * 3);
@@ -13,6 +13,150 @@ fn create_benchmark_program(size: usize) -> Vec<u8> {
     program
 }
 
+/// PUSH 0 followed by `size` (PUSH i, ADD) pairs, then STOP: 2 * size + 2 steps.
+fn create_arithmetic_program(size: usize) -> Vec<u8> {
+    let mut program = Vec::with_capacity(size * 3 + 3);
+    program.extend_from_slice(&[0x01, 0x00]);
+    for i in 0..size {
+        program.extend_from_slice(&[
+            0x01, i as u8,  // PUSH i
+            0x02,           // ADD
+        ]);
+    }
+    program.push(0xFF);
+    program
+}
+
+/// `size` STORE/LOAD round trips through memory, each loaded value added to
+/// a running sum so the stack stays two deep: 6 * size + 2 steps.
+fn create_memory_program(size: usize) -> Vec<u8> {
+    let mut program = Vec::with_capacity(size * 9 + 3);
+    program.extend_from_slice(&[0x01, 0x00]); // PUSH 0 (sum)
+    for i in 0..size {
+        program.extend_from_slice(&[
+            0x01, i as u8,  // PUSH i (value)
+            0x01, i as u8,  // PUSH i (address)
+            0x04,           // STORE
+            0x01, i as u8,  // PUSH i (address)
+            0x05,           // LOAD
+            0x02,           // ADD
+        ]);
+    }
+    program.push(0xFF);
+    program
+}
+
+/// The interpreter loop as it was before pre-decoding, kept as the baseline
+/// the throughput benchmarks compare against: every step re-reads the
+/// bytecode, looks its gas cost up in the `GasConfig` map and goes through
+/// a `HashMap` memory. Only the opcodes of the benchmark programs are
+/// implemented.
+mod legacy {
+    use parking_lot::RwLock;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use zkvm::vm::{GasConfig, Value};
+
+    struct Context {
+        stack: Vec<Value>,
+        memory: HashMap<usize, Value>,
+        program_counter: usize,
+        gas_remaining: u64,
+        gas_config: GasConfig,
+    }
+
+    pub fn execute(program: &[u8]) {
+        let context = Arc::new(RwLock::new(Context {
+            stack: Vec::with_capacity(1024),
+            memory: HashMap::new(),
+            program_counter: 0,
+            gas_remaining: 1_000_000,
+            gas_config: GasConfig::default(),
+        }));
+        let mut context = context.write();
+
+        while context.program_counter < program.len() {
+            let opcode = program[context.program_counter];
+            let gas_cost = context.gas_config.op_cost.get(&opcode)
+                .copied()
+                .unwrap_or(context.gas_config.base);
+            context.gas_remaining = context.gas_remaining.checked_sub(gas_cost).expect("out of gas");
+
+            match opcode {
+                0x01 => {
+                    let value = program[context.program_counter + 1];
+                    context.stack.push(Value::Int(value as i64));
+                    context.program_counter += 2;
+                }
+                0x02 => {
+                    let (Some(Value::Int(b)), Some(Value::Int(a))) = (context.stack.pop(), context.stack.pop()) else {
+                        panic!("stack underflow");
+                    };
+                    context.stack.push(Value::Int(a.wrapping_add(b)));
+                    context.program_counter += 1;
+                }
+                0x04 => {
+                    let (Some(Value::Int(addr)), Some(value)) = (context.stack.pop(), context.stack.pop()) else {
+                        panic!("stack underflow");
+                    };
+                    context.memory.insert(addr as usize, value);
+                    context.program_counter += 1;
+                }
+                0x05 => {
+                    let Some(Value::Int(addr)) = context.stack.pop() else {
+                        panic!("stack underflow");
+                    };
+                    let value = context.memory.get(&(addr as usize)).expect("address not found").clone();
+                    context.stack.push(value);
+                    context.program_counter += 1;
+                }
+                0xFF => break,
+                other => panic!("opcode {:#04x} not in the baseline", other),
+            }
+        }
+    }
+}
+
+/// Reports interpreter throughput in executed steps per second, excluding
+/// proof system setup, next to the pre-decoding baseline in `legacy`.
+fn bench_interpreter_throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("interpreter_throughput");
+
+    for size in [100, 1000, 10000].iter() {
+        let programs = [
+            ("arithmetic", create_arithmetic_program(*size), 2 * size + 2),
+            ("memory", create_memory_program(*size), 6 * size + 2),
+        ];
+        for (name, program, steps) in programs {
+            group.throughput(Throughput::Elements(steps as u64));
+            group.bench_with_input(BenchmarkId::new(name, size), &program, |b, program| {
+                b.iter(|| {
+                    let vm = VM::new(black_box(program.clone()));
+                    vm.execute().unwrap();
+                });
+            });
+            group.bench_with_input(BenchmarkId::new(format!("{}_legacy", name), size), &program, |b, program| {
+                b.iter(|| legacy::execute(black_box(program)));
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    let gas_table = GasTable::default();
+
+    for size in [1000, 10000].iter() {
+        let program = create_arithmetic_program(*size);
+        group.throughput(Throughput::Bytes(program.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &program, |b, program| {
+            b.iter(|| vm::decode(black_box(program), &gas_table));
+        });
+    }
+    group.finish();
+}
+
 fn bench_vm_execution(c: &mut Criterion) {
     let mut group = c.benchmark_group("vm_execution");
     
@@ -63,6 +207,8 @@ fn bench_proof_verification(c: &mut Criterion) {
 
 criterion_group!(
     benches,
+    bench_interpreter_throughput,
+    bench_decode,
     bench_vm_execution,
     bench_proof_generation,
     bench_proof_verification
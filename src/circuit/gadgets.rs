@@ -0,0 +1,399 @@
+//! Reusable R1CS gadgets: booleans, bit decompositions and range checks,
+//! fixed-width words with carrying arithmetic and shifts, comparisons, and
+//! conditional selection.
+//!
+//! Gadgets take the values they constrain as linear combinations together
+//! with their assignment, which is `None` while synthesizing without a
+//! witness. Each allocates under fixed names, so callers give every use its
+//! own namespace.
+
+use bellman::{ConstraintSystem, LinearCombination, SynthesisError, Variable};
+use ff::PrimeField;
+
+/// Widest `Word`, so that the product of two words stays below the modulus.
+pub const MAX_WORD_BITS: usize = 64;
+
+pub fn bit<F: PrimeField>(set: bool) -> F {
+    if set { F::one() } else { F::zero() }
+}
+
+fn pow2<F: PrimeField>(exponent: usize) -> F {
+    (0..exponent).fold(F::one(), |acc, _| acc.double())
+}
+
+/// Low 64 bits of the canonical little-endian representation of `value`.
+pub fn low_u64<F: PrimeField>(value: F) -> u64 {
+    let repr = value.to_repr();
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&repr.as_ref()[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+pub fn enforce_boolean<F: PrimeField, CS: ConstraintSystem<F>>(cs: &mut CS, name: &str, var: Variable) {
+    cs.enforce(
+        || format!("{}_boolean", name),
+        |lc| lc + var,
+        |lc| lc + CS::one() - var,
+        |lc| lc,
+    );
+}
+
+/// Allocates a bit that is one exactly when `value` is zero.
+pub fn alloc_is_zero<F: PrimeField, CS: ConstraintSystem<F>>(
+    cs: &mut CS,
+    value: LinearCombination<F>,
+    assignment: Option<F>,
+) -> Result<Variable, SynthesisError> {
+    let is_zero = cs.alloc(
+        || "is_zero",
+        || assignment.map(|v| bit(bool::from(v.is_zero()))).ok_or(SynthesisError::AssignmentMissing),
+    )?;
+    let inverse = cs.alloc(
+        || "inverse",
+        || assignment.map(|v| v.invert().unwrap_or(F::zero())).ok_or(SynthesisError::AssignmentMissing),
+    )?;
+    cs.enforce(
+        || "nonzero_is_invertible",
+        |lc| lc + &value,
+        |lc| lc + inverse,
+        |lc| lc + CS::one() - is_zero,
+    );
+    cs.enforce(
+        || "zero_when_flagged",
+        |lc| lc + &value,
+        |lc| lc + is_zero,
+        |lc| lc,
+    );
+    Ok(is_zero)
+}
+
+/// An unsigned integer held as boolean variables, least significant first.
+#[derive(Clone, Debug)]
+pub struct Word {
+    bits: Vec<Variable>,
+    value: Option<u64>,
+}
+
+impl Word {
+    pub fn width(&self) -> usize {
+        self.bits.len()
+    }
+
+    pub fn bits(&self) -> &[Variable] {
+        &self.bits
+    }
+
+    pub fn value(&self) -> Option<u64> {
+        self.value
+    }
+
+    /// The packed unsigned value.
+    pub fn lc<F: PrimeField>(&self) -> LinearCombination<F> {
+        self.shr_lc(0)
+    }
+
+    /// The value read as two's complement: the packed value, less
+    /// `2^width` when the top bit is set.
+    pub fn signed_lc<F: PrimeField>(&self) -> LinearCombination<F> {
+        match self.bits.last() {
+            Some(&top) => self.lc::<F>() - (pow2::<F>(self.width()), top),
+            None => LinearCombination::zero(),
+        }
+    }
+
+    /// The value shifted left by `shift` bits, dropping the bits that leave
+    /// the word.
+    pub fn shl_lc<F: PrimeField>(&self, shift: usize) -> LinearCombination<F> {
+        let kept = self.width().saturating_sub(shift);
+        self.bits[..kept].iter().enumerate()
+            .fold(LinearCombination::zero(), |lc, (i, &b)| lc + (pow2::<F>(i + shift), b))
+    }
+
+    /// The value shifted right by `shift` bits, filling with zeros.
+    pub fn shr_lc<F: PrimeField>(&self, shift: usize) -> LinearCombination<F> {
+        self.bits.iter().skip(shift).enumerate()
+            .fold(LinearCombination::zero(), |lc, (i, &b)| lc + (pow2::<F>(i), b))
+    }
+}
+
+/// Allocates a `width`-bit word, range checked by its boolean bits. A
+/// `value` that does not fit is truncated and will not satisfy whatever
+/// constrains the word.
+pub fn alloc_word<F: PrimeField, CS: ConstraintSystem<F>>(
+    cs: &mut CS,
+    value: Option<u64>,
+    width: usize,
+) -> Result<Word, SynthesisError> {
+    assert!(width <= MAX_WORD_BITS, "words are at most {} bits", MAX_WORD_BITS);
+
+    let mut bits = Vec::with_capacity(width);
+    for i in 0..width {
+        let name = format!("bit_{}", i);
+        let b = cs.alloc(
+            || name.clone(),
+            || value.map(|v| bit((v >> i) & 1 == 1)).ok_or(SynthesisError::AssignmentMissing),
+        )?;
+        enforce_boolean(cs, &name, b);
+        bits.push(b);
+    }
+
+    Ok(Word { bits, value })
+}
+
+/// Decomposes `value` into `width` bits, which proves it is below
+/// `2^width`.
+pub fn decompose<F: PrimeField, CS: ConstraintSystem<F>>(
+    cs: &mut CS,
+    value: LinearCombination<F>,
+    assignment: Option<F>,
+    width: usize,
+) -> Result<Word, SynthesisError> {
+    let word = alloc_word(cs, assignment.map(low_u64), width)?;
+    cs.enforce(
+        || "packed",
+        |_| word.lc(),
+        |lc| lc + CS::one(),
+        |_| value,
+    );
+    Ok(word)
+}
+
+/// Constrains `value` to fit in `width` bits.
+pub fn enforce_range<F: PrimeField, CS: ConstraintSystem<F>>(
+    cs: &mut CS,
+    value: LinearCombination<F>,
+    assignment: Option<F>,
+    width: usize,
+) -> Result<(), SynthesisError> {
+    decompose(cs, value, assignment, width).map(|_| ())
+}
+
+/// Decomposes `value` into the `F::NUM_BITS` bits of its canonical
+/// little-endian representation, least significant first. Unlike
+/// `decompose`, which stops well short of the modulus, this checks the bits
+/// read as a number below the modulus, so they are unique.
+pub fn decompose_field<F: PrimeField, CS: ConstraintSystem<F>>(
+    cs: &mut CS,
+    value: LinearCombination<F>,
+    assignment: Option<F>,
+) -> Result<Vec<Variable>, SynthesisError> {
+    let width = F::NUM_BITS as usize;
+    let repr_bit = |repr: &F::Repr, i: usize| (repr.as_ref()[i / 8] >> (i % 8)) & 1 == 1;
+    let repr = assignment.map(|v| v.to_repr());
+
+    let mut bits = Vec::with_capacity(width);
+    for i in 0..width {
+        let name = format!("bit_{}", i);
+        let b = cs.alloc(
+            || name.clone(),
+            || repr.as_ref().map(|r| bit(repr_bit(r, i))).ok_or(SynthesisError::AssignmentMissing),
+        )?;
+        enforce_boolean(cs, &name, b);
+        bits.push(b);
+    }
+    cs.enforce(
+        || "packed",
+        |_| bits.iter().enumerate().fold(LinearCombination::zero(), |lc, (i, &b)| lc + (pow2::<F>(i), b)),
+        |lc| lc + CS::one(),
+        |_| value,
+    );
+
+    // From the top, `prefix` is one while the bits so far match the largest
+    // element; where that has a zero bit, a matching prefix forbids a one
+    let largest = (-F::one()).to_repr();
+    let mut prefix: Option<(Variable, Option<bool>)> = None;
+    for i in (0..width).rev() {
+        let value_bit = repr.as_ref().map(|r| repr_bit(r, i));
+        if repr_bit(&largest, i) {
+            prefix = Some(match prefix {
+                None => (bits[i], value_bit),
+                Some((matched, matched_value)) => {
+                    let value = matched_value.zip(value_bit).map(|(m, b)| m && b);
+                    let next = cs.alloc(
+                        || format!("prefix_{}", i),
+                        || value.map(bit).ok_or(SynthesisError::AssignmentMissing),
+                    )?;
+                    cs.enforce(
+                        || format!("prefix_{}_matches", i),
+                        |lc| lc + matched,
+                        |lc| lc + bits[i],
+                        |lc| lc + next,
+                    );
+                    (next, value)
+                }
+            });
+        } else if let Some((matched, _)) = prefix {
+            cs.enforce(
+                || format!("bit_{}_below_modulus", i),
+                |lc| lc + matched,
+                |lc| lc + bits[i],
+                |lc| lc,
+            );
+        }
+    }
+
+    Ok(bits)
+}
+
+/// Two's complement word of a signed integer encoded as a field element,
+/// negative values as their additive inverse. Proves the integer lies in
+/// `[-2^(width-1), 2^(width-1))`.
+pub fn decompose_signed<F: PrimeField, CS: ConstraintSystem<F>>(
+    cs: &mut CS,
+    value: LinearCombination<F>,
+    assignment: Option<F>,
+    width: usize,
+) -> Result<Word, SynthesisError> {
+    assert!(width > 0, "signed words need a sign bit");
+
+    // Offsetting by 2^(width-1) makes an in-range value non-negative; the
+    // two's complement differs from that offset value in the top bit only
+    let sign = 1u64 << (width - 1);
+    let word = alloc_word(
+        cs,
+        assignment.map(|v| low_u64(v + pow2::<F>(width - 1)) ^ sign),
+        width,
+    )?;
+    cs.enforce(
+        || "signed",
+        |_| word.signed_lc(),
+        |lc| lc + CS::one(),
+        |_| value,
+    );
+    Ok(word)
+}
+
+fn truncate(value: u128, width: usize) -> u64 {
+    (value & ((1u128 << width) - 1)) as u64
+}
+
+/// `a + b` as the low `width` bits of the sum and the carry out.
+pub fn add_with_carry<F: PrimeField, CS: ConstraintSystem<F>>(
+    cs: &mut CS,
+    a: &Word,
+    b: &Word,
+) -> Result<(Word, Variable), SynthesisError> {
+    assert_eq!(a.width(), b.width(), "operands differ in width");
+    let width = a.width();
+
+    let total = a.value.zip(b.value).map(|(a, b)| a as u128 + b as u128);
+    let sum = alloc_word(&mut cs.namespace(|| "sum"), total.map(|t| truncate(t, width)), width)?;
+    let carry = cs.alloc(
+        || "carry",
+        || total.map(|t| bit(t >> width != 0)).ok_or(SynthesisError::AssignmentMissing),
+    )?;
+    enforce_boolean(cs, "carry", carry);
+    cs.enforce(
+        || "sum_with_carry",
+        |_| a.lc::<F>() + &b.lc::<F>(),
+        |lc| lc + CS::one(),
+        |_| sum.lc::<F>() + (pow2::<F>(width), carry),
+    );
+
+    Ok((sum, carry))
+}
+
+/// `a * b` as its low and high `width` bits.
+pub fn mul_with_carry<F: PrimeField, CS: ConstraintSystem<F>>(
+    cs: &mut CS,
+    a: &Word,
+    b: &Word,
+) -> Result<(Word, Word), SynthesisError> {
+    assert_eq!(a.width(), b.width(), "operands differ in width");
+    let width = a.width();
+
+    let product = a.value.zip(b.value).map(|(a, b)| a as u128 * b as u128);
+    let low = alloc_word(&mut cs.namespace(|| "low"), product.map(|p| truncate(p, width)), width)?;
+    let high = alloc_word(&mut cs.namespace(|| "high"), product.map(|p| (p >> width) as u64), width)?;
+    cs.enforce(
+        || "product_with_carry",
+        |_| a.lc(),
+        |_| b.lc(),
+        |_| high.bits.iter().enumerate()
+            .fold(low.lc::<F>(), |lc, (i, &b)| lc + (pow2::<F>(width + i), b)),
+    );
+
+    Ok((low, high))
+}
+
+/// Allocates a bit that is one exactly when `a` equals `b`.
+pub fn alloc_is_equal<F: PrimeField, CS: ConstraintSystem<F>>(
+    cs: &mut CS,
+    a: LinearCombination<F>,
+    b: LinearCombination<F>,
+    assignment: Option<(F, F)>,
+) -> Result<Variable, SynthesisError> {
+    alloc_is_zero(cs, a - &b, assignment.map(|(a, b)| a - b))
+}
+
+/// Bit that is one exactly when `a < b`, for values known to lie in
+/// `[0, 2^width)`: `a - b` is shifted by `2^width` when the bit is set and
+/// must then fit in `width` bits.
+fn alloc_less_than<F: PrimeField, CS: ConstraintSystem<F>>(
+    cs: &mut CS,
+    a: LinearCombination<F>,
+    b: LinearCombination<F>,
+    assignment: Option<(u64, u64)>,
+    width: usize,
+) -> Result<Variable, SynthesisError> {
+    let less = cs.alloc(
+        || "less",
+        || assignment.map(|(a, b)| bit(a < b)).ok_or(SynthesisError::AssignmentMissing),
+    )?;
+    enforce_boolean(cs, "less", less);
+
+    let shifted = a - &b + (pow2::<F>(width), less);
+    let difference = assignment.map(|(a, b)| {
+        let shift = if a < b { 1u128 << width } else { 0 };
+        F::from((a as u128 + shift - b as u128) as u64)
+    });
+    enforce_range(&mut cs.namespace(|| "difference"), shifted, difference, width)?;
+
+    Ok(less)
+}
+
+/// Bit that is one exactly when `a < b` as unsigned words.
+pub fn less_than<F: PrimeField, CS: ConstraintSystem<F>>(
+    cs: &mut CS,
+    a: &Word,
+    b: &Word,
+) -> Result<Variable, SynthesisError> {
+    assert_eq!(a.width(), b.width(), "operands differ in width");
+    alloc_less_than(cs, a.lc(), b.lc(), a.value.zip(b.value), a.width())
+}
+
+/// Bit that is one exactly when `a < b` as two's complement words.
+pub fn signed_less_than<F: PrimeField, CS: ConstraintSystem<F>>(
+    cs: &mut CS,
+    a: &Word,
+    b: &Word,
+) -> Result<Variable, SynthesisError> {
+    assert_eq!(a.width(), b.width(), "operands differ in width");
+    let width = a.width();
+
+    // Offsetting by 2^(width-1) maps signed order onto unsigned order
+    let offset = |word: &Word| word.signed_lc::<F>() + (pow2::<F>(width - 1), CS::one());
+    let sign = 1u64 << (width - 1);
+    let values = a.value.zip(b.value).map(|(a, b)| (a ^ sign, b ^ sign));
+    alloc_less_than(cs, offset(a), offset(b), values, width)
+}
+
+/// Allocates `when_true` if `condition` is one and `when_false` if it is
+/// zero. `condition` must already be constrained to a bit; `assignment` is
+/// the selected value.
+pub fn select<F: PrimeField, CS: ConstraintSystem<F>>(
+    cs: &mut CS,
+    condition: LinearCombination<F>,
+    when_true: LinearCombination<F>,
+    when_false: LinearCombination<F>,
+    assignment: Option<F>,
+) -> Result<Variable, SynthesisError> {
+    let selected = cs.alloc(|| "selected", || assignment.ok_or(SynthesisError::AssignmentMissing))?;
+    cs.enforce(
+        || "selection",
+        |_| condition,
+        |_| when_true - &when_false,
+        |lc| lc + selected - &when_false,
+    );
+    Ok(selected)
+}
@@ -0,0 +1,400 @@
+//! Offline memory checking for `VMCircuit`, used for both its memory and
+//! its stack, whose positions serve as addresses.
+//!
+//! Steps log their accesses (address, timestamp, value, op) in execution
+//! order. A Benes network, whose switches are part of the witness, permutes
+//! that log into a second copy sorted by address and then timestamp. Along
+//! the sorted copy each read must directly follow an access to the same
+//! address and return its value, which makes it the last value written.
+
+use bellman::{ConstraintSystem, LinearCombination, SynthesisError};
+use ff::PrimeField;
+use super::gadgets::{alloc_is_zero, bit, enforce_boolean, enforce_range, low_u64};
+
+/// `op` of a step that does not touch memory.
+pub const OP_NONE: u64 = 0;
+pub const OP_READ: u64 = 1;
+pub const OP_WRITE: u64 = 2;
+
+/// Width of the gaps between consecutive sorted accesses, and of the
+/// smallest address. Addresses and step counts stay well below this.
+const ORDER_BITS: usize = 40;
+
+const ADDR: usize = 0;
+const TIMESTAMP: usize = 1;
+const VALUE: usize = 2;
+const OP: usize = 3;
+
+/// One logged access: its fields as linear combinations, and their values
+/// when a witness is available.
+#[derive(Clone)]
+pub struct Access<F: PrimeField> {
+    fields: [LinearCombination<F>; 4],
+    values: Option<[F; 4]>,
+}
+
+impl<F: PrimeField> Access<F> {
+    pub fn new(
+        addr: LinearCombination<F>,
+        timestamp: LinearCombination<F>,
+        value: LinearCombination<F>,
+        op: LinearCombination<F>,
+        values: Option<[F; 4]>,
+    ) -> Self {
+        Self {
+            fields: [addr, timestamp, value, op],
+            values,
+        }
+    }
+
+    fn value(&self, field: usize) -> Option<F> {
+        self.values.map(|values| values[field])
+    }
+}
+
+/// Switch settings that send input `i` to output `perm[i]` through a Benes
+/// network of `perm.len()` inputs, a power of two, in the order `permute`
+/// consumes them: first column, upper half, lower half, last column.
+fn route(perm: &[usize], switches: &mut Vec<bool>) {
+    let n = perm.len();
+    if n < 2 {
+        return;
+    }
+    if n == 2 {
+        switches.push(perm[0] == 1);
+        return;
+    }
+
+    let mut inverse = vec![0; n];
+    for (input, &output) in perm.iter().enumerate() {
+        inverse[output] = input;
+    }
+
+    // The two inputs of a first-column switch, and the two sources of a
+    // last-column switch, must use different halves
+    let mut lower = vec![None; n];
+    for start in (0..n).step_by(2) {
+        let mut input = start;
+        while lower[input].is_none() {
+            lower[input] = Some(false);
+            lower[input ^ 1] = Some(true);
+            input = inverse[perm[input ^ 1] ^ 1];
+        }
+    }
+    let lower: Vec<bool> = lower.into_iter().map(|half| half.unwrap_or(false)).collect();
+
+    let mut upper_perm = Vec::with_capacity(n / 2);
+    let mut lower_perm = Vec::with_capacity(n / 2);
+    for pair in 0..n / 2 {
+        let swap = lower[2 * pair];
+        switches.push(swap);
+        let (up, down) = if swap { (2 * pair + 1, 2 * pair) } else { (2 * pair, 2 * pair + 1) };
+        upper_perm.push(perm[up] / 2);
+        lower_perm.push(perm[down] / 2);
+    }
+    route(&upper_perm, switches);
+    route(&lower_perm, switches);
+    for pair in 0..n / 2 {
+        switches.push(lower[inverse[2 * pair]]);
+    }
+}
+
+/// Outputs `(x, y)`, or `(y, x)` when the switch is set.
+fn switch<F: PrimeField, CS: ConstraintSystem<F>>(
+    cs: &mut CS,
+    x: Access<F>,
+    y: Access<F>,
+    swap: Option<bool>,
+) -> Result<(Access<F>, Access<F>), SynthesisError> {
+    let set = cs.alloc(|| "swap", || swap.map(bit).ok_or(SynthesisError::AssignmentMissing))?;
+    enforce_boolean(cs, "swap", set);
+
+    let values = match (x.values, y.values, swap) {
+        (Some(x), Some(y), Some(swap)) => Some(if swap { (y, x) } else { (x, y) }),
+        _ => None,
+    };
+
+    let mut first = Vec::with_capacity(4);
+    let mut second = Vec::with_capacity(4);
+    for field in 0..4 {
+        let out0 = cs.alloc(
+            || format!("field_{}_0", field),
+            || values.map(|(v, _)| v[field]).ok_or(SynthesisError::AssignmentMissing),
+        )?;
+        let out1 = cs.alloc(
+            || format!("field_{}_1", field),
+            || values.map(|(_, v)| v[field]).ok_or(SynthesisError::AssignmentMissing),
+        )?;
+        cs.enforce(
+            || format!("field_{}_select", field),
+            |lc| lc + set,
+            |lc| lc + &y.fields[field] - &x.fields[field],
+            |lc| lc + out0 - &x.fields[field],
+        );
+        cs.enforce(
+            || format!("field_{}_conserved", field),
+            |lc| lc + &x.fields[field] + &y.fields[field],
+            |lc| lc + CS::one(),
+            |lc| lc + out0 + out1,
+        );
+        first.push(LinearCombination::zero() + out0);
+        second.push(LinearCombination::zero() + out1);
+    }
+
+    let access = |fields: Vec<LinearCombination<F>>, values| Access {
+        fields: [fields[0].clone(), fields[1].clone(), fields[2].clone(), fields[3].clone()],
+        values,
+    };
+    Ok((
+        access(first, values.map(|(v, _)| v)),
+        access(second, values.map(|(_, v)| v)),
+    ))
+}
+
+/// Next switch setting produced by `route`.
+fn take(switches: Option<&[bool]>, next: &mut usize) -> Option<bool> {
+    let swap = switches.map(|s| s[*next]);
+    *next += 1;
+    swap
+}
+
+/// Routes `inputs` through a Benes network, returning its outputs.
+fn permute<F: PrimeField, CS: ConstraintSystem<F>>(
+    cs: &mut CS,
+    inputs: Vec<Access<F>>,
+    switches: Option<&[bool]>,
+    next: &mut usize,
+) -> Result<Vec<Access<F>>, SynthesisError> {
+    let n = inputs.len();
+    if n < 2 {
+        return Ok(inputs);
+    }
+    let mut inputs = inputs.into_iter();
+    if n == 2 {
+        let (x, y) = (inputs.next().unwrap(), inputs.next().unwrap());
+        let (a, b) = switch(&mut cs.namespace(|| "switch"), x, y, take(switches, next))?;
+        return Ok(vec![a, b]);
+    }
+
+    let mut upper = Vec::with_capacity(n / 2);
+    let mut lower = Vec::with_capacity(n / 2);
+    for pair in 0..n / 2 {
+        let (x, y) = (inputs.next().unwrap(), inputs.next().unwrap());
+        let (up, down) = switch(&mut cs.namespace(|| format!("in_{}", pair)), x, y, take(switches, next))?;
+        upper.push(up);
+        lower.push(down);
+    }
+    let upper = permute(&mut cs.namespace(|| "upper"), upper, switches, next)?;
+    let lower = permute(&mut cs.namespace(|| "lower"), lower, switches, next)?;
+
+    let mut outputs = Vec::with_capacity(n);
+    for (pair, (up, down)) in upper.into_iter().zip(lower).enumerate() {
+        let (a, b) = switch(&mut cs.namespace(|| format!("out_{}", pair)), up, down, take(switches, next))?;
+        outputs.push(a);
+        outputs.push(b);
+    }
+    Ok(outputs)
+}
+
+/// Proves that every read in `accesses`, no two of which share both address
+/// and timestamp, returns the value last written to its address. Memory
+/// starts empty: reading an address that was not written at an earlier
+/// timestamp is unsatisfiable. Entries without an access use `OP_NONE` and
+/// must not come between a read and the access it follows, so they go at
+/// an address above every accessed one, such as `filler`, which also pads
+/// the log to a power of two, or after every access to their own address.
+pub fn enforce_consistency<F: PrimeField, CS: ConstraintSystem<F>>(
+    cs: &mut CS,
+    mut accesses: Vec<Access<F>>,
+    filler: Option<F>,
+) -> Result<(), SynthesisError> {
+    let n = accesses.len().next_power_of_two();
+    for index in accesses.len()..n {
+        let addr = cs.alloc(
+            || format!("padding_{}_addr", index),
+            || filler.ok_or(SynthesisError::AssignmentMissing),
+        )?;
+        let timestamp = F::from(index as u64 + 1);
+        accesses.push(Access::new(
+            LinearCombination::zero() + addr,
+            LinearCombination::zero() + (timestamp, CS::one()),
+            LinearCombination::zero(),
+            LinearCombination::zero(),
+            filler.map(|filler| [filler, timestamp, F::zero(), F::zero()]),
+        ));
+    }
+
+    // Address order, with execution order within an address
+    let witness: Option<Vec<[F; 4]>> = accesses.iter().map(|a| a.values).collect();
+    let switches = witness.as_ref().map(|values| {
+        let mut order: Vec<usize> = (0..values.len()).collect();
+        order.sort_by_key(|&i| (low_u64(values[i][ADDR]), low_u64(values[i][TIMESTAMP])));
+        let mut perm = vec![0; order.len()];
+        for (position, &input) in order.iter().enumerate() {
+            perm[input] = position;
+        }
+        let mut switches = Vec::new();
+        route(&perm, &mut switches);
+        switches
+    });
+
+    let mut next = 0;
+    let sorted = permute(&mut cs.namespace(|| "sort"), accesses, switches.as_deref(), &mut next)?;
+
+    let mut previous: Option<(Access<F>, LinearCombination<F>)> = None;
+    for (index, access) in sorted.into_iter().enumerate() {
+        let cs = &mut cs.namespace(|| format!("sorted_{}", index));
+        let op = &access.fields[OP];
+        let op_value = access.value(OP);
+
+        let read = cs.alloc(
+            || "read",
+            || op_value.map(|op| bit(low_u64(op) == OP_READ)).ok_or(SynthesisError::AssignmentMissing),
+        )?;
+        cs.enforce(
+            || "read_flag",
+            |lc| lc + op,
+            |lc| lc + (F::from(OP_WRITE), CS::one()) - op,
+            |lc| lc + read,
+        );
+        let write = cs.alloc(
+            || "write",
+            || op_value.map(|op| bit(low_u64(op) == OP_WRITE)).ok_or(SynthesisError::AssignmentMissing),
+        )?;
+        cs.enforce(
+            || "write_flag",
+            |lc| lc + op,
+            |lc| lc + op - CS::one(),
+            |lc| lc + (F::from(2), write),
+        );
+
+        match &previous {
+            None => {
+                // The first access cannot read, and anchors the addresses
+                // so that none of them wraps around the field
+                cs.enforce(|| "first_is_not_read", |lc| lc + read, |lc| lc + CS::one(), |lc| lc);
+                enforce_range(
+                    &mut cs.namespace(|| "first_addr"),
+                    access.fields[ADDR].clone(),
+                    access.value(ADDR),
+                    ORDER_BITS,
+                )?;
+            }
+            Some((prev, prev_active)) => {
+                let addr_diff = access.fields[ADDR].clone() - &prev.fields[ADDR];
+                let time_diff = access.fields[TIMESTAMP].clone() - &prev.fields[TIMESTAMP];
+                let diffs = match (access.values, prev.values) {
+                    (Some(v), Some(p)) => Some((v[ADDR] - p[ADDR], v[TIMESTAMP] - p[TIMESTAMP])),
+                    _ => None,
+                };
+
+                let same = alloc_is_zero(
+                    &mut cs.namespace(|| "same_addr"),
+                    addr_diff.clone(),
+                    diffs.map(|(addr, _)| addr),
+                )?;
+
+                // Strictly increasing: by timestamp within an address,
+                // otherwise by address
+                let gap_value = diffs.map(|(addr, time)| {
+                    if bool::from(addr.is_zero()) { time - F::one() } else { addr - F::one() }
+                });
+                let gap = cs.alloc(|| "gap", || gap_value.ok_or(SynthesisError::AssignmentMissing))?;
+                cs.enforce(
+                    || "gap_select",
+                    |lc| lc + same,
+                    |lc| lc + &time_diff - &addr_diff,
+                    |lc| lc + gap - &addr_diff + CS::one(),
+                );
+                enforce_range(&mut cs.namespace(|| "gap_range"), LinearCombination::zero() + gap, gap_value, ORDER_BITS)?;
+
+                // A read continues the access right before it
+                cs.enforce(
+                    || "read_same_addr",
+                    |lc| lc + read,
+                    |lc| lc + CS::one() - same,
+                    |lc| lc,
+                );
+                cs.enforce(
+                    || "read_after_access",
+                    |lc| lc + read,
+                    |lc| lc + CS::one() - prev_active,
+                    |lc| lc,
+                );
+                cs.enforce(
+                    || "read_value",
+                    |lc| lc + read,
+                    |lc| lc + &access.fields[VALUE] - &prev.fields[VALUE],
+                    |lc| lc,
+                );
+            }
+        }
+
+        let active = LinearCombination::zero() + read + write;
+        previous = Some((access, active));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(perm: &[usize]) -> Vec<usize> {
+        // Simulates the network on input labels
+        fn run(inputs: Vec<usize>, switches: &[bool], next: &mut usize) -> Vec<usize> {
+            let n = inputs.len();
+            let take = |next: &mut usize| {
+                *next += 1;
+                switches[*next - 1]
+            };
+            if n < 2 {
+                return inputs;
+            }
+            if n == 2 {
+                return if take(next) { vec![inputs[1], inputs[0]] } else { inputs };
+            }
+            let (mut upper, mut lower) = (Vec::new(), Vec::new());
+            for pair in inputs.chunks(2) {
+                let swap = take(next);
+                upper.push(if swap { pair[1] } else { pair[0] });
+                lower.push(if swap { pair[0] } else { pair[1] });
+            }
+            let upper = run(upper, switches, next);
+            let lower = run(lower, switches, next);
+            let mut outputs = Vec::new();
+            for (up, down) in upper.into_iter().zip(lower) {
+                if take(next) {
+                    outputs.extend([down, up]);
+                } else {
+                    outputs.extend([up, down]);
+                }
+            }
+            outputs
+        }
+
+        let mut switches = Vec::new();
+        route(perm, &mut switches);
+        let mut next = 0;
+        let outputs = run((0..perm.len()).collect(), &switches, &mut next);
+        assert_eq!(next, switches.len());
+        outputs
+    }
+
+    #[test]
+    fn test_route_realizes_permutation() {
+        let perms: [&[usize]; 4] = [
+            &[0],
+            &[1, 0],
+            &[3, 0, 2, 1],
+            &[5, 2, 7, 0, 6, 1, 4, 3],
+        ];
+        for perm in perms {
+            let outputs = apply(perm);
+            for (input, &output) in perm.iter().enumerate() {
+                assert_eq!(outputs[output], input);
+            }
+        }
+    }
+}
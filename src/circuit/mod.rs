@@ -0,0 +1,1880 @@
+use bellman::{Circuit, ConstraintSystem, LinearCombination, SynthesisError, Variable};
+use ff::PrimeField;
+use std::marker::PhantomData;
+use crate::proof::PublicInputs;
+use crate::syscall::Syscall;
+use crate::tracer::TraceStep;
+use crate::vm::{GasTable, Value, VM};
+
+pub mod gadgets;
+mod memory;
+pub mod poseidon;
+pub mod stats;
+
+use gadgets::{alloc_is_equal, alloc_is_zero, bit, enforce_boolean, enforce_range};
+use poseidon::Poseidon;
+
+const OP_PUSH: u8 = 0x01;
+const OP_ADD: u8 = 0x02;
+const OP_MUL: u8 = 0x03;
+const OP_STORE: u8 = 0x04;
+const OP_LOAD: u8 = 0x05;
+const OP_JUMP: u8 = 0x06;
+const OP_JUMPI: u8 = 0x07;
+const OP_EQ: u8 = 0x08;
+const OP_LT: u8 = 0x09;
+const OP_GT: u8 = 0x0A;
+const OP_RETURN: u8 = 0x0D;
+const OP_SHA3: u8 = 0x0E;
+const OP_SYSCALL: u8 = 0x10;
+const OP_LOG: u8 = 0x13;
+const OP_ISZERO: u8 = 0x17;
+const OP_STOP: u8 = 0xFF;
+
+/// Opcodes a step can execute, with how far each advances the pc unless it
+/// jumps and how many stack items it pops and pushes. Anything else cannot
+/// be proven: CREATE, whose length depends on the stack, and the opcodes
+/// whose results come from state the circuit does not model, such as
+/// storage, the environment and other contracts. LOG pops its topics on top
+/// of these, and the syscalls reading input push what they read.
+const STEP_OPCODES: [(u8, u64, u64, u64); 16] = [
+    (OP_PUSH, 2, 0, 1),
+    (OP_ADD, 1, 2, 1),
+    (OP_MUL, 1, 2, 1),
+    (OP_STORE, 1, 2, 0),
+    (OP_LOAD, 1, 1, 1),
+    (OP_JUMP, 0, 1, 0),
+    (OP_JUMPI, 1, 2, 0),
+    (OP_EQ, 1, 2, 1),
+    (OP_LT, 1, 2, 1),
+    (OP_GT, 1, 2, 1),
+    (OP_RETURN, 1, 2, 0),
+    (OP_SHA3, 1, 2, 1),
+    (OP_SYSCALL, 1, 2, 0),
+    (OP_LOG, 1, 2, 0),
+    (OP_ISZERO, 1, 1, 1),
+    (OP_STOP, 0, 0, 0),
+];
+
+/// Syscalls a step can run, under the names their flags are allocated as.
+const STEP_SYSCALLS: [(&str, Syscall); 4] = [
+    ("read", Syscall::Read),
+    ("read_public", Syscall::ReadPublic),
+    ("write", Syscall::Write),
+    ("exit", Syscall::Exit),
+];
+
+/// Most topics a LOG can pop.
+const MAX_LOG_TOPICS: u64 = 4;
+
+/// Bits of a stack height, well above the VM's limit of 1024 items.
+const STACK_HEIGHT_BITS: usize = 16;
+
+/// Bits of a `Value::Int`.
+const INT_BITS: usize = 64;
+
+/// Bits of a gas amount, which the VM counts in a `u64`.
+const GAS_BITS: usize = 64;
+
+/// Bits of the size of a byte region a step hashes, reads or writes: at
+/// most 31 bytes, which pack into a single field element.
+const REGION_SIZE_BITS: usize = 5;
+
+/// Bits of how many bytes a read asked for beyond those it got.
+const SHORTFALL_BITS: usize = 63;
+
+/// Bound on the public input, and on the private input read and the
+/// journal written by the proven steps, unless set otherwise.
+pub const DEFAULT_MAX_IO_LEN: usize = 32;
+
+/// Memory addresses a state root covers unless set otherwise.
+pub const DEFAULT_MEMORY_CELLS: usize = 16;
+
+/// Stack items a state root covers unless set otherwise.
+pub const DEFAULT_STACK_CELLS: usize = 16;
+
+/// Names of the state-root fields ahead of the memory and stack cells, in
+/// the order they are hashed.
+const ROOT_FIELDS: [&str; 5] = ["pc", "halted", "height", "input_cursor", "private_input_exhausted"];
+
+/// State at a boundary of the proven steps, with stack and memory values
+/// as their field encodings.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VMState<F: PrimeField> {
+    /// Stack items, bottom first.
+    pub stack: Vec<F>,
+    /// The value at each address, `empty_cell()` where nothing is stored.
+    pub memory: Vec<F>,
+    pub program_counter: F,
+    pub gas_remaining: F,
+    pub halted: bool,
+    /// Bytes of the public input read so far.
+    pub input_cursor: usize,
+    /// Whether a read of the private input came up short, after which
+    /// every read comes up empty.
+    pub private_input_exhausted: bool,
+}
+
+impl<F: PrimeField> VMState<F> {
+    fn at(program_counter: usize, gas_remaining: u64) -> Self {
+        Self {
+            stack: Vec::new(),
+            memory: Vec::new(),
+            program_counter: F::from(program_counter as u64),
+            gas_remaining: F::from(gas_remaining),
+            halted: false,
+            input_cursor: 0,
+            private_input_exhausted: false,
+        }
+    }
+
+    /// The state `vm` is in, or `None` if it stored a value at or above
+    /// address `memory_cells`.
+    pub fn capture(vm: &VM, memory_cells: usize) -> Option<Self> {
+        let stored = vm.get_memory();
+        if stored.keys().any(|&addr| addr >= memory_cells) {
+            return None;
+        }
+        let mut memory = vec![empty_cell(); stored.keys().max().map_or(0, |&addr| addr + 1)];
+        for (&addr, value) in &stored {
+            memory[addr] = value_to_field(value);
+        }
+
+        let syscalls = vm.get_syscall_trace();
+        let input_cursor = syscalls.iter()
+            .filter(|record| record.syscall == Syscall::ReadPublic)
+            .map(|record| match &record.result {
+                Some(Value::Bytes(bytes)) => bytes.len(),
+                _ => 0,
+            })
+            .sum();
+        let private_input_exhausted = syscalls.iter()
+            .filter(|record| record.syscall == Syscall::Read)
+            .any(|record| is_short_read(record.args.first(), record.result.as_ref()));
+
+        Some(Self {
+            stack: vm.get_stack().iter().map(value_to_field).collect(),
+            memory,
+            program_counter: F::from(vm.get_program_counter() as u64),
+            gas_remaining: F::from(vm.get_gas_remaining()),
+            halted: vm.is_halted(),
+            input_cursor,
+            private_input_exhausted,
+        })
+    }
+
+    /// Whether a root over `memory_cells` addresses and `stack_cells` stack
+    /// items covers the whole state.
+    fn fits(&self, memory_cells: usize, stack_cells: usize) -> bool {
+        self.memory.len() <= memory_cells && self.stack.len() <= stack_cells
+    }
+
+    /// What the root hashes: the `ROOT_FIELDS`, then the memory and stack
+    /// cells, those past the state's own empty.
+    fn root_inputs(&self, memory_cells: usize, stack_cells: usize) -> Vec<F> {
+        let mut inputs = vec![
+            self.program_counter,
+            bit(self.halted),
+            F::from(self.stack.len() as u64),
+            F::from(self.input_cursor as u64),
+            bit(self.private_input_exhausted),
+        ];
+        inputs.extend((0..memory_cells).map(|addr| self.memory.get(addr).copied().unwrap_or_else(empty_cell)));
+        inputs.extend((0..stack_cells).map(|position| self.stack.get(position).copied().unwrap_or_else(empty_cell)));
+        inputs
+    }
+
+    /// Poseidon commitment to the state as a circuit covering `memory_cells`
+    /// addresses and `stack_cells` stack items exposes it. Gas is not part
+    /// of it, as the proof exposes gas on its own.
+    pub fn root(&self, memory_cells: usize, stack_cells: usize) -> F {
+        Poseidon::shared().hash(&self.root_inputs(memory_cells, stack_cells))
+    }
+}
+
+/// Step circuit for any program of at most `max_program_len` bytes running
+/// for at most `max_steps` steps. The program is part of the witness and is
+/// committed in the public inputs, so the circuit's shape, and with it the
+/// trusted setup, depends on the bounds only.
+///
+/// The public input, and the private input read and the journal written by
+/// the proven steps, are at most `max_io_len` bytes each. Reads and writes
+/// move at most 31 bytes at a time, as `Value::Bytes`; writing an integer
+/// cannot be proven.
+///
+/// SHA3 is proven over regions of at most 31 bytes. Its digest is the
+/// Poseidon hash the VM computes over the BLS12-381 scalar field, so over
+/// any other field a step running SHA3 cannot be proven.
+///
+/// The state roots commit to the pc, whether the machine halted, how much
+/// public input was read, whether the private input ran out, memory below
+/// address `memory_cells` and the stack, which must hold at most
+/// `stack_cells` items at either boundary. Steps touching memory at or
+/// above `memory_cells` cannot be proven.
+///
+/// The proven steps may be a segment of a longer execution, starting from
+/// the state `with_initial_state` was given. The `halted` public input tells
+/// a segment that stops short from one that runs to the end.
+#[derive(Clone)]
+pub struct VMCircuit<F: PrimeField> {
+    pub initial_state: Option<VMState<F>>,
+    pub final_state: Option<VMState<F>>,
+    pub program: Vec<u8>,
+    pub public_input: Vec<u8>,
+    pub max_program_len: usize,
+    pub max_steps: usize,
+    pub max_io_len: usize,
+    pub memory_cells: usize,
+    pub stack_cells: usize,
+    pub trace: Option<Vec<TraceStep>>,
+    _marker: PhantomData<F>,
+}
+
+/// Packs a 32-byte digest into a field element, dropping the top byte so the
+/// value is always below the modulus.
+pub fn bytes_to_field<F: PrimeField>(bytes: &[u8; 32]) -> F {
+    let mut repr = F::Repr::default();
+    repr.as_mut()[..31].copy_from_slice(&bytes[..31]);
+    F::from_repr(repr).unwrap_or(F::zero())
+}
+
+/// 2^64, just past every `Value::Int`.
+fn two_pow_64<F: PrimeField>() -> F {
+    F::from(u64::MAX) + F::one()
+}
+
+/// Field encoding of a memory or stack cell holding nothing, which no
+/// value encodes to.
+pub fn empty_cell<F: PrimeField>() -> F {
+    two_pow_64()
+}
+
+/// The length of `bytes`, then its bytes zero padded to `bound` and packed
+/// little-endian, 31 to a field element.
+fn padded_inputs<F: PrimeField>(bytes: &[u8], bound: usize) -> Vec<F> {
+    let mut padded = bytes.to_vec();
+    padded.resize(bound.max(bytes.len()), 0);
+
+    let mut inputs = vec![F::from(bytes.len() as u64)];
+    inputs.extend(poseidon::pack_bytes::<F>(&padded));
+    inputs
+}
+
+/// Commitment to a program as exposed by a circuit bounded to
+/// `max_program_len` bytes: the Poseidon hash of its length, then its bytes
+/// zero padded to the bound and packed little-endian, 31 to a field element.
+pub fn program_commitment<F: PrimeField>(program: &[u8], max_program_len: usize) -> F {
+    Poseidon::shared().hash(&padded_inputs(program, max_program_len))
+}
+
+/// Commitment to the public input or the journal as exposed by a circuit
+/// bounded to `max_io_len` bytes of each, in the format of
+/// `program_commitment`.
+pub fn io_commitment<F: PrimeField>(bytes: &[u8], max_io_len: usize) -> F {
+    Poseidon::shared().hash(&padded_inputs(bytes, max_io_len))
+}
+
+/// The statement a `VMCircuit` proof attests to: on which input, from which
+/// state and with how much gas a program ran, what it produced and used,
+/// whether it halted, and which program it was. Fields are in the order of
+/// the proof's public inputs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PublicValues<F: PrimeField> {
+    pub public_input_hash: F,
+    pub journal_hash: F,
+    pub initial_root: F,
+    pub final_root: F,
+    /// One if the machine halted by the final state, zero otherwise.
+    pub halted: F,
+    pub gas_limit: F,
+    pub gas_used: F,
+    pub program_hash: F,
+}
+
+impl<F: PrimeField> PublicValues<F> {
+    pub fn to_vec(&self) -> Vec<F> {
+        vec![
+            self.public_input_hash,
+            self.journal_hash,
+            self.initial_root,
+            self.final_root,
+            self.halted,
+            self.gas_limit,
+            self.gas_used,
+            self.program_hash,
+        ]
+    }
+
+    /// Reads the values back from `ProofData::public_inputs`.
+    pub fn from_slice(inputs: &[F]) -> Option<Self> {
+        match inputs {
+            [public_input_hash, journal_hash, initial_root, final_root, halted, gas_limit, gas_used, program_hash] => {
+                Some(Self {
+                    public_input_hash: *public_input_hash,
+                    journal_hash: *journal_hash,
+                    initial_root: *initial_root,
+                    final_root: *final_root,
+                    halted: *halted,
+                    gas_limit: *gas_limit,
+                    gas_used: *gas_used,
+                    program_hash: *program_hash,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Field encoding of a stack value. Integers keep their signed value; byte
+/// strings are the Poseidon hash of their length, offset by 2^64 to tell
+/// them from any other hash here, then their packed bytes, which the
+/// circuit recomputes for up to 31 bytes. Any other value is represented by
+/// the Poseidon hash of its canonical encoding.
+pub fn value_to_field<F: PrimeField>(value: &Value) -> F {
+    match value {
+        Value::Int(v) if *v >= 0 => F::from(*v as u64),
+        Value::Int(v) => -F::from(v.unsigned_abs()),
+        Value::Bool(b) => F::from(*b as u64),
+        Value::Bytes(bytes) => {
+            let mut inputs = vec![F::from(bytes.len() as u64) + two_pow_64::<F>()];
+            inputs.extend(poseidon::pack_bytes::<F>(bytes));
+            Poseidon::shared().hash(&inputs)
+        }
+        other => {
+            let mut encoded = Vec::new();
+            other.encode_canonical(&mut encoded);
+            poseidon::hash_bytes(&encoded)
+        }
+    }
+}
+
+/// Witness value for `step`, or `AssignmentMissing` when synthesizing
+/// without a trace. Steps past the end of the trace are halted padding and
+/// see `None`.
+fn witness<F: PrimeField>(
+    trace: Option<&[TraceStep]>,
+    step: usize,
+    value: impl FnOnce(Option<&TraceStep>) -> F,
+) -> Result<F, SynthesisError> {
+    trace.map(|trace| value(trace.get(step))).ok_or(SynthesisError::AssignmentMissing)
+}
+
+fn operand<F: PrimeField>(step: Option<&TraceStep>, index: usize) -> F {
+    step.and_then(|s| s.operands.get(index)).map_or(F::zero(), value_to_field)
+}
+
+/// Name of the variable selecting `op` in a step.
+fn selector_name(op: u8) -> String {
+    format!("is_{:#04x}", op)
+}
+
+/// Bits needed to write `n`.
+fn bits_for(n: usize) -> usize {
+    (usize::BITS - n.leading_zeros()) as usize
+}
+
+/// How many items `step` pops and pushes, as the circuit derives them from
+/// its opcode and top operand.
+fn stack_effect(step: &TraceStep) -> (u64, u64) {
+    let (_, _, mut pops, mut pushes) = STEP_OPCODES.iter()
+        .find(|&&(op, ..)| op == step.opcode)
+        .copied()
+        .unwrap_or((step.opcode, 0, 0, 0));
+    match (step.opcode, step.operands.first()) {
+        (OP_SYSCALL, Some(Value::Int(number)))
+            if *number == Syscall::Write as i64 || *number == Syscall::Exit as i64 => {}
+        (OP_SYSCALL, _) => pushes += 1,
+        (OP_LOG, Some(Value::Int(topics))) if *topics >= 0 => pops += *topics as u64,
+        _ => {}
+    }
+    (pops, pushes)
+}
+
+fn touches_memory(step: &TraceStep) -> bool {
+    step.opcode == OP_STORE || step.opcode == OP_LOAD
+}
+
+fn runs_syscall(step: &TraceStep, syscall: Syscall) -> bool {
+    step.opcode == OP_SYSCALL
+        && matches!(step.operands.first(), Some(Value::Int(number)) if *number == syscall as i64)
+}
+
+/// Bytes `step` moved if it ran `syscall`: those a read returned, or those
+/// a write appended to the journal.
+fn moved_bytes(step: &TraceStep, syscall: Syscall) -> Option<Vec<u8>> {
+    if !runs_syscall(step, syscall) {
+        return None;
+    }
+    let moved = if syscall == Syscall::Write { step.operands.get(1) } else { step.result.as_ref() };
+    Some(match moved {
+        Some(Value::Bytes(bytes)) => bytes.clone(),
+        Some(Value::Int(v)) => v.to_le_bytes().to_vec(),
+        _ => Vec::new(),
+    })
+}
+
+/// Everything the steps in `trace` moved through `syscall`, in order.
+fn stream(trace: &[TraceStep], syscall: Syscall) -> Vec<u8> {
+    trace.iter().filter_map(|s| moved_bytes(s, syscall)).flatten().collect()
+}
+
+/// Whether a read asking for `requested` bytes got fewer in `result`.
+fn is_short_read(requested: Option<&Value>, result: Option<&Value>) -> bool {
+    match (requested, result) {
+        (Some(Value::Int(len)), Some(Value::Bytes(bytes))) => (bytes.len() as i64) < (*len).max(0),
+        _ => false,
+    }
+}
+
+impl<F: PrimeField> VMCircuit<F> {
+    /// Circuit bounded to the length of `program`.
+    pub fn new(program: Vec<u8>, max_steps: usize) -> Self {
+        Self::universal(program.len(), max_steps).with_program(program)
+    }
+
+    /// Program-independent circuit, as used for setup.
+    pub fn universal(max_program_len: usize, max_steps: usize) -> Self {
+        Self {
+            initial_state: None,
+            final_state: None,
+            program: Vec::new(),
+            public_input: Vec::new(),
+            max_program_len,
+            max_steps,
+            max_io_len: DEFAULT_MAX_IO_LEN,
+            memory_cells: DEFAULT_MEMORY_CELLS,
+            stack_cells: DEFAULT_STACK_CELLS,
+            trace: None,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn with_witness(
+        program: Vec<u8>,
+        max_steps: usize,
+        initial_state: VMState<F>,
+        final_state: VMState<F>,
+    ) -> Self {
+        Self {
+            initial_state: Some(initial_state),
+            final_state: Some(final_state),
+            ..Self::new(program, max_steps)
+        }
+    }
+
+    pub fn with_program(mut self, program: Vec<u8>) -> Self {
+        self.program = program;
+        self
+    }
+
+    /// The whole public input, of which the proven steps may read any part
+    /// following what earlier steps read.
+    pub fn with_public_input(mut self, public_input: Vec<u8>) -> Self {
+        self.public_input = public_input;
+        self
+    }
+
+    /// Runs the proven steps from `initial_state`, as captured where a
+    /// segment starts, instead of from a fresh machine.
+    pub fn with_initial_state(mut self, initial_state: VMState<F>) -> Self {
+        self.initial_state = Some(initial_state);
+        self.final_state = self.replay();
+        self
+    }
+
+    /// Drives the witness from the steps the VM actually executed. Without
+    /// an initial state they run from a fresh machine at the first step's
+    /// pc, or at pc 0 for an empty trace. The final state is replayed from
+    /// the trace.
+    pub fn with_trace(mut self, trace: Vec<TraceStep>) -> Self {
+        if self.initial_state.is_none() {
+            self.initial_state = Some(match trace.first() {
+                Some(first) => VMState::at(first.pc, first.gas_before),
+                None => VMState::at(0, 0),
+            });
+        }
+        self.trace = Some(trace);
+        self.final_state = self.replay();
+        self
+    }
+
+    /// Root of the state a fresh VM starts in: at the entry point, with
+    /// nothing on the stack, in memory or read.
+    pub fn entry_root(&self) -> F {
+        VMState::<F>::at(0, 0).root(self.memory_cells, self.stack_cells)
+    }
+
+    /// Counts the constraints, variables and public inputs of this
+    /// circuit's shape, by gadget and by opcode.
+    pub fn stats(&self) -> Result<stats::CircuitStats, SynthesisError> {
+        stats::count(self.clone())
+    }
+
+    /// Gas available in the initial state.
+    pub fn gas_limit(&self) -> Option<F> {
+        self.initial_state.as_ref().map(|s| s.gas_remaining)
+    }
+
+    /// Gas consumed between the initial and final state.
+    pub fn gas_used(&self) -> Option<F> {
+        match (&self.initial_state, &self.final_state) {
+            (Some(initial), Some(last)) => Some(initial.gas_remaining - last.gas_remaining),
+            _ => None,
+        }
+    }
+
+    /// The statement proven from the trace, or `None` without one.
+    pub fn public_values(&self) -> Option<PublicValues<F>> {
+        let trace = self.trace.as_deref()?;
+        let (initial, last) = (self.initial_state.as_ref()?, self.final_state.as_ref()?);
+        Some(PublicValues {
+            public_input_hash: io_commitment(&self.public_input, self.max_io_len),
+            journal_hash: io_commitment(&stream(trace, Syscall::Write), self.max_io_len),
+            initial_root: initial.root(self.memory_cells, self.stack_cells),
+            final_root: last.root(self.memory_cells, self.stack_cells),
+            halted: bit(last.halted),
+            gas_limit: self.gas_limit()?,
+            gas_used: self.gas_used()?,
+            program_hash: program_commitment(&self.program, self.max_program_len),
+        })
+    }
+
+    /// Whether the machine is halted once `step` has run. Running off the
+    /// end of the code only halts it after the step.
+    fn halts_after(&self, step: &TraceStep) -> bool {
+        step.halted || step.next_pc >= self.program.len()
+    }
+
+    /// The state the trace leaves behind when run from the initial state,
+    /// or `None` if it stores outside the memory cells.
+    fn replay(&self) -> Option<VMState<F>> {
+        let mut state = self.initial_state.clone()?;
+        state.halted |= state.program_counter == F::from(self.program.len() as u64);
+
+        for s in self.trace.as_deref()? {
+            let (popped, pushed) = stack_effect(s);
+            state.stack.truncate(state.stack.len().saturating_sub(popped as usize));
+            if pushed > 0 {
+                state.stack.push(s.result.as_ref().map_or(F::zero(), value_to_field));
+            }
+
+            if s.opcode == OP_STORE {
+                let addr = match s.operands.first() {
+                    Some(Value::Int(addr)) if (0..self.memory_cells as i64).contains(addr) => *addr as usize,
+                    _ => return None,
+                };
+                if state.memory.len() <= addr {
+                    state.memory.resize(addr + 1, empty_cell());
+                }
+                state.memory[addr] = operand(Some(s), 1);
+            }
+            if let Some(read) = moved_bytes(s, Syscall::ReadPublic) {
+                state.input_cursor += read.len();
+            }
+            if runs_syscall(s, Syscall::Read) && is_short_read(s.operands.get(1), s.result.as_ref()) {
+                state.private_input_exhausted = true;
+            }
+
+            state.program_counter = F::from(s.next_pc as u64);
+            state.gas_remaining = F::from(s.gas_after);
+            state.halted = self.halts_after(s);
+        }
+        Some(state)
+    }
+
+    /// Allocates `bound` bytes, range checked through their bits, and checks
+    /// that the first `len` of them, at most `bound`, are `bytes`. With a
+    /// `commitment`, binds them to it by hashing their packed words after
+    /// `len`.
+    fn alloc_bytes<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        poseidon: &Poseidon<F>,
+        bytes: Option<&[u8]>,
+        bound: usize,
+        len: Variable,
+        commitment: Option<Variable>,
+    ) -> Result<Vec<LinearCombination<F>>, SynthesisError> {
+        enforce_range(
+            &mut cs.namespace(|| "len_bound"),
+            LinearCombination::zero() + (F::from(bound as u64), CS::one()) - len,
+            bytes.map(|bytes| F::from(bound.saturating_sub(bytes.len()) as u64)),
+            bits_for(bound),
+        )?;
+
+        let mut lcs = Vec::with_capacity(bound);
+        for offset in 0..bound {
+            let value = bytes.map(|bytes| bytes.get(offset).copied().unwrap_or(0) as u64);
+            let byte = gadgets::alloc_word(&mut cs.namespace(|| format!("byte_{}", offset)), value, 8)?;
+            lcs.push(byte.lc());
+        }
+
+        if let Some(commitment) = commitment {
+            let mut inputs = vec![LinearCombination::zero() + len];
+            for chunk in lcs.chunks(poseidon::WORD_BYTES) {
+                let mut packed = LinearCombination::zero();
+                let mut coeff = F::one();
+                for byte in chunk {
+                    packed = packed + (coeff, byte);
+                    coeff *= F::from(256);
+                }
+                inputs.push(packed);
+            }
+            let values = bytes.map(|bytes| padded_inputs::<F>(bytes, bound));
+            let digest = poseidon.alloc_hash(&mut cs.namespace(|| "hash"), &inputs, values.as_deref())?;
+            cs.enforce(
+                || "committed",
+                |lc| lc + digest,
+                |lc| lc + CS::one(),
+                |lc| lc + commitment,
+            );
+        }
+
+        Ok(lcs)
+    }
+
+    /// Allocates the state a root commits to and checks that it hashes to
+    /// `root`. The stack cells from the height up must be empty, which also
+    /// bounds the height by the cells.
+    fn alloc_boundary<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        poseidon: &Poseidon<F>,
+        state: Option<&VMState<F>>,
+        root: Variable,
+    ) -> Result<Boundary<F>, SynthesisError> {
+        let inputs = state.map(|s| s.root_inputs(self.memory_cells, self.stack_cells));
+        let mut cells = Vec::with_capacity(ROOT_FIELDS.len() + self.memory_cells + self.stack_cells);
+        let names = ROOT_FIELDS.iter().map(|name| name.to_string())
+            .chain((0..self.memory_cells).map(|addr| format!("memory_{}", addr)))
+            .chain((0..self.stack_cells).map(|position| format!("stack_{}", position)));
+        for (index, name) in names.enumerate() {
+            let value = inputs.as_ref().map(|inputs| inputs[index]);
+            let cell = cs.alloc(|| name, || value.ok_or(SynthesisError::AssignmentMissing))?;
+            cells.push((cell, value));
+        }
+        enforce_boolean(cs, "halted", cells[1].0);
+        enforce_boolean(cs, "private_input_exhausted", cells[4].0);
+        let stack = cells[ROOT_FIELDS.len() + self.memory_cells..].to_vec();
+
+        // Occupied cells run from the bottom up to the height, and the rest
+        // are empty
+        let height = state.map(|s| s.stack.len());
+        let mut occupied: Vec<(Variable, Option<bool>)> = Vec::with_capacity(self.stack_cells);
+        let mut occupied_count = LinearCombination::zero();
+        for (position, &(cell, _)) in stack.iter().enumerate() {
+            let name = format!("occupied_{}", position);
+            let value = height.map(|height| position < height);
+            let is_occupied = cs.alloc(|| name.clone(), || value.map(bit).ok_or(SynthesisError::AssignmentMissing))?;
+            enforce_boolean(cs, &name, is_occupied);
+            if let Some(&(below, _)) = occupied.last() {
+                cs.enforce(
+                    || format!("{}_contiguous", name),
+                    |lc| lc + is_occupied,
+                    |lc| lc + CS::one() - below,
+                    |lc| lc,
+                );
+            }
+            cs.enforce(
+                || format!("{}_or_empty", name),
+                |lc| lc + CS::one() - is_occupied,
+                |lc| lc + cell - (empty_cell::<F>(), CS::one()),
+                |lc| lc,
+            );
+            occupied_count = occupied_count + is_occupied;
+            occupied.push((is_occupied, value));
+        }
+        cs.enforce(
+            || "height_counts_occupied",
+            |_| occupied_count,
+            |lc| lc + CS::one(),
+            |lc| lc + cells[2].0,
+        );
+
+        let hashed: Vec<_> = cells.iter().map(|&(cell, _)| LinearCombination::zero() + cell).collect();
+        let digest = poseidon.alloc_hash(&mut cs.namespace(|| "root"), &hashed, inputs.as_deref())?;
+        cs.enforce(
+            || "committed",
+            |lc| lc + digest,
+            |lc| lc + CS::one(),
+            |lc| lc + root,
+        );
+
+        Ok(Boundary {
+            program_counter: cells[0].0,
+            halted: cells[1].0,
+            height: cells[2].0,
+            input_cursor: cells[3].0,
+            private_input_exhausted: cells[4].0,
+            memory: cells[ROOT_FIELDS.len()..ROOT_FIELDS.len() + self.memory_cells].to_vec(),
+            stack,
+            occupied,
+        })
+    }
+
+    /// Picks a region of at most 31 bytes out of `source` for a step where
+    /// `active` is set, by one-hot start and end positions, and packs the
+    /// bytes between them into one word. Inactive steps pick nothing, at
+    /// position zero. `picked` is the offset and size of the region, or
+    /// `None` for an inactive step.
+    fn alloc_region<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        source: &[LinearCombination<F>],
+        source_values: Option<&[u8]>,
+        active: &LinearCombination<F>,
+        picked: Option<Option<(usize, usize)>>,
+    ) -> Result<Region<F>, SynthesisError> {
+        let region = picked.map(|picked| picked.unwrap_or((0, 0)));
+        let is_active = picked.map(|picked| picked.is_some());
+        let region_bytes = region.zip(source_values).map(|((offset, size), values)| {
+            (offset..offset + size).map(|j| values.get(j).copied().unwrap_or(0)).collect::<Vec<_>>()
+        });
+
+        let mut starts = LinearCombination::zero();
+        let mut start_position = LinearCombination::zero();
+        let mut shift = LinearCombination::zero();
+        let mut ends = LinearCombination::zero();
+        let mut end_position = LinearCombination::zero();
+        let mut shifted_region = LinearCombination::zero();
+        let mut coeff = F::one();
+        for j in 0..=source.len() {
+            let name = format!("start_{}", j);
+            let start = cs.alloc(
+                || name.clone(),
+                || region.zip(is_active)
+                    .map(|((offset, _), active)| bit(active && offset == j))
+                    .ok_or(SynthesisError::AssignmentMissing),
+            )?;
+            enforce_boolean(cs, &name, start);
+            starts = starts + start;
+            start_position = start_position + (F::from(j as u64), start);
+            shift = shift + (coeff, start);
+
+            let name = format!("end_{}", j);
+            let end = cs.alloc(
+                || name.clone(),
+                || region.zip(is_active)
+                    .map(|((offset, size), active)| bit(active && offset + size == j))
+                    .ok_or(SynthesisError::AssignmentMissing),
+            )?;
+            enforce_boolean(cs, &name, end);
+            ends = ends + end;
+            end_position = end_position + (F::from(j as u64), end);
+
+            // Past the start and not yet at the end
+            if j < source.len() {
+                let name = format!("region_byte_{}", j);
+                let byte = cs.alloc(
+                    || name.clone(),
+                    || region.zip(source_values).map(|((offset, size), values)| {
+                        let inside = offset <= j && j < offset + size;
+                        F::from(if inside { values.get(j).copied().unwrap_or(0) as u64 } else { 0 })
+                    }).ok_or(SynthesisError::AssignmentMissing),
+                )?;
+                cs.enforce(
+                    || format!("{}_selected", name),
+                    |_| starts.clone() - &ends,
+                    |lc| lc + &source[j],
+                    |lc| lc + byte,
+                );
+                shifted_region = shifted_region + (coeff, byte);
+            }
+            coeff *= F::from(256);
+        }
+
+        for (name, one_hot) in [("start_one_hot", &starts), ("end_one_hot", &ends)] {
+            cs.enforce(
+                || name,
+                |_| one_hot.clone(),
+                |lc| lc + CS::one(),
+                |_| active.clone(),
+            );
+        }
+        let size_value = region.map(|(_, size)| F::from(size as u64));
+        enforce_range(
+            &mut cs.namespace(|| "size"),
+            end_position.clone() - &start_position,
+            size_value,
+            REGION_SIZE_BITS,
+        )?;
+
+        // The region shifted up to its offset, so its packed word times
+        // 256^offset
+        let word_value = region_bytes.as_ref().map(|bytes| {
+            poseidon::pack_bytes::<F>(bytes).first().copied().unwrap_or(F::zero())
+        });
+        let word = cs.alloc(|| "region_word", || word_value.ok_or(SynthesisError::AssignmentMissing))?;
+        cs.enforce(
+            || "region_packed",
+            |lc| lc + word,
+            |_| shift,
+            |_| shifted_region,
+        );
+
+        Ok(Region {
+            start: start_position,
+            end: end_position,
+            size_value,
+            word,
+            word_value,
+        })
+    }
+
+    /// Field encoding of the digest SHA3 pushes for `region`: the Poseidon
+    /// hash of the region after its size, decomposed into its canonical
+    /// bytes to encode them as a `Value::Bytes`.
+    fn alloc_digest<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        poseidon: &Poseidon<F>,
+        region: &Region<F>,
+    ) -> Result<Variable, SynthesisError> {
+        let digest_inputs = region.inputs(F::zero());
+        let digest = poseidon.alloc_hash(
+            &mut cs.namespace(|| "digest"),
+            &region.input_lcs::<CS>(F::zero()),
+            digest_inputs.as_deref(),
+        )?;
+        let digest_value = digest_inputs.map(|inputs| poseidon.hash(&inputs));
+        let digest_bits = gadgets::decompose_field(
+            &mut cs.namespace(|| "digest_bits"),
+            LinearCombination::zero() + digest,
+            digest_value,
+        )?;
+
+        // The digest's little-endian bytes, packed into words after their
+        // length
+        let digest_len = F::Repr::default().as_ref().len();
+        let word_bits = 8 * poseidon::WORD_BYTES;
+        let mut inputs = vec![LinearCombination::zero() + (F::from(digest_len as u64) + two_pow_64::<F>(), CS::one())];
+        for (i, &digest_bit) in digest_bits.iter().enumerate() {
+            if i % word_bits == 0 {
+                inputs.push(LinearCombination::zero());
+            }
+            let packed = inputs.last_mut().expect("a word per 248 bits");
+            *packed = packed.clone() + (F::from(2).pow_vartime([(i % word_bits) as u64]), digest_bit);
+        }
+        let encoding_inputs = digest_value.map(|digest| {
+            let bytes = digest.to_repr().as_ref().to_vec();
+            let mut inputs = vec![F::from(bytes.len() as u64) + two_pow_64::<F>()];
+            inputs.extend(poseidon::pack_bytes::<F>(&bytes));
+            inputs
+        });
+        poseidon.alloc_hash(&mut cs.namespace(|| "encoding"), &inputs, encoding_inputs.as_deref())
+    }
+}
+
+/// A boundary state as allocated: the root fields, then the memory and
+/// stack cells with their assignments, and which stack cells lie below the
+/// height.
+struct Boundary<F: PrimeField> {
+    program_counter: Variable,
+    halted: Variable,
+    height: Variable,
+    input_cursor: Variable,
+    private_input_exhausted: Variable,
+    memory: Vec<(Variable, Option<F>)>,
+    stack: Vec<(Variable, Option<F>)>,
+    occupied: Vec<(Variable, Option<bool>)>,
+}
+
+/// A byte region picked by a step: its start and end positions in the
+/// source, zero for an inactive step, and its packed word.
+struct Region<F: PrimeField> {
+    start: LinearCombination<F>,
+    end: LinearCombination<F>,
+    size_value: Option<F>,
+    word: Variable,
+    word_value: Option<F>,
+}
+
+impl<F: PrimeField> Region<F> {
+    fn size(&self) -> LinearCombination<F> {
+        self.end.clone() - &self.start
+    }
+
+    /// The size offset by `tag`, then the word: hashed with no tag for a
+    /// digest, and with 2^64 for the field encoding of the bytes.
+    fn input_lcs<CS: ConstraintSystem<F>>(&self, tag: F) -> [LinearCombination<F>; 2] {
+        [self.size() + (tag, CS::one()), LinearCombination::zero() + self.word]
+    }
+
+    fn inputs(&self, tag: F) -> Option<Vec<F>> {
+        self.size_value.zip(self.word_value).map(|(size, word)| vec![size + tag, word])
+    }
+}
+
+impl<F: PrimeField> Circuit<F> for VMCircuit<F> {
+    fn synthesize<CS: ConstraintSystem<F>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        let trace = self.trace.as_deref();
+        let private_values = trace.map(|trace| stream(trace, Syscall::Read));
+        let journal_values = trace.map(|trace| stream(trace, Syscall::Write));
+        let fits = |state: &Option<VMState<F>>| {
+            state.as_ref().is_none_or(|s| s.fits(self.memory_cells, self.stack_cells))
+        };
+        let io_fits = |bytes: &Option<Vec<u8>>| bytes.as_ref().is_none_or(|b| b.len() <= self.max_io_len);
+        if self.program.len() > self.max_program_len
+            || trace.is_some_and(|trace| trace.len() > self.max_steps)
+            || (trace.is_some() && self.final_state.is_none())
+            || !fits(&self.initial_state)
+            || !fits(&self.final_state)
+            || self.public_input.len() > self.max_io_len
+            || !io_fits(&private_values)
+            || !io_fits(&journal_values)
+        {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+
+        // Public inputs, in the order of `PublicValues`
+        let public_values = self.public_values();
+        let mut inputs = Vec::with_capacity(8);
+        for (index, name) in [
+            "public_input_hash",
+            "journal_hash",
+            "initial_root",
+            "final_root",
+            "halted",
+            "gas_limit",
+            "gas_used",
+            "program_hash",
+        ].into_iter().enumerate() {
+            let value = public_values.as_ref().map(|values| values.to_vec()[index]);
+            inputs.push(cs.alloc_input(|| name, || value.ok_or(SynthesisError::AssignmentMissing))?);
+        }
+        let [public_input_hash, journal_hash, initial_root, final_root, halts, gas_limit, gas_used, program_hash] =
+            inputs[..] else { unreachable!("eight public inputs") };
+
+        // The program, the public input, the private input read and the
+        // journal written, one after the other
+        let poseidon = Poseidon::shared();
+        let code_len = cs.alloc(|| "program_len", || Ok(F::from(self.program.len() as u64)))?;
+        let program = self.alloc_bytes(
+            &mut cs.namespace(|| "program"),
+            poseidon,
+            Some(&self.program),
+            self.max_program_len,
+            code_len,
+            Some(program_hash),
+        )?;
+        let public_len = cs.alloc(|| "public_input_len", || Ok(F::from(self.public_input.len() as u64)))?;
+        let public_input = self.alloc_bytes(
+            &mut cs.namespace(|| "public_input"),
+            poseidon,
+            Some(&self.public_input),
+            self.max_io_len,
+            public_len,
+            Some(public_input_hash),
+        )?;
+        let private_len = cs.alloc(
+            || "private_input_len",
+            || private_values.as_ref().map(|b| F::from(b.len() as u64)).ok_or(SynthesisError::AssignmentMissing),
+        )?;
+        let private_input = self.alloc_bytes(
+            &mut cs.namespace(|| "private_input"),
+            poseidon,
+            private_values.as_deref(),
+            self.max_io_len,
+            private_len,
+            None,
+        )?;
+        let journal_len = cs.alloc(
+            || "journal_len",
+            || journal_values.as_ref().map(|b| F::from(b.len() as u64)).ok_or(SynthesisError::AssignmentMissing),
+        )?;
+        let journal = self.alloc_bytes(
+            &mut cs.namespace(|| "journal"),
+            poseidon,
+            journal_values.as_deref(),
+            self.max_io_len,
+            journal_len,
+            Some(journal_hash),
+        )?;
+
+        let public_base = self.max_program_len;
+        let private_base = public_base + self.max_io_len;
+        let journal_base = private_base + self.max_io_len;
+        let source: Vec<_> = [program, public_input, private_input, journal].concat();
+        let source_values = private_values.as_ref().zip(journal_values.as_ref()).map(|(private, journal)| {
+            let streams = [&self.program, &self.public_input, private, journal];
+            let bounds = [self.max_program_len, self.max_io_len, self.max_io_len, self.max_io_len];
+            streams.iter().zip(bounds).flat_map(|(bytes, bound)| {
+                let mut padded = bytes.to_vec();
+                padded.resize(bound, 0);
+                padded
+            }).collect::<Vec<_>>()
+        });
+        let room_bits = bits_for(source.len());
+
+        let initial = self.alloc_boundary(
+            &mut cs.namespace(|| "initial_state"),
+            poseidon,
+            self.initial_state.as_ref(),
+            initial_root,
+        )?;
+        let last = self.alloc_boundary(
+            &mut cs.namespace(|| "final_state"),
+            poseidon,
+            self.final_state.as_ref(),
+            final_root,
+        )?;
+        let initial_gas = cs.alloc(
+            || "initial_gas",
+            || self.initial_state.as_ref().map(|s| s.gas_remaining).ok_or(SynthesisError::AssignmentMissing),
+        )?;
+        let final_gas = cs.alloc(
+            || "final_gas",
+            || self.final_state.as_ref().map(|s| s.gas_remaining).ok_or(SynthesisError::AssignmentMissing),
+        )?;
+
+        cs.enforce(
+            || "starts_with_gas_limit",
+            |lc| lc + initial_gas,
+            |lc| lc + CS::one(),
+            |lc| lc + gas_limit,
+        );
+        cs.enforce(
+            || "gas_balance",
+            |lc| lc + initial_gas - final_gas,
+            |lc| lc + CS::one(),
+            |lc| lc + gas_used,
+        );
+
+        // Once the private input has run out, it stays out and nothing more
+        // is read
+        cs.enforce(
+            || "stays_exhausted",
+            |lc| lc + initial.private_input_exhausted,
+            |lc| lc + CS::one() - last.private_input_exhausted,
+            |lc| lc,
+        );
+        cs.enforce(
+            || "exhausted_reads_nothing",
+            |lc| lc + initial.private_input_exhausted,
+            |lc| lc + private_len,
+            |lc| lc,
+        );
+
+        // Once halted, every remaining step is padding that keeps the pc
+        let initial_pc = self.initial_state.as_ref().map(|s| s.program_counter);
+        let halt_pc = self.final_state.as_ref().map(|s| s.program_counter);
+        let halt_gas = self.final_state.as_ref().map(|s| s.gas_remaining);
+        let program_len = F::from(self.program.len() as u64);
+
+        // Memory starts out as the initial cells and is read back as the
+        // final ones. Steps without an access use the address past them
+        let memory_cells = F::from(self.memory_cells as u64);
+        let mut accesses = Vec::with_capacity(2 * self.memory_cells + self.max_steps);
+        let write = F::from(memory::OP_WRITE);
+        let read = F::from(memory::OP_READ);
+        for (addr, &(cell, value)) in initial.memory.iter().enumerate() {
+            let addr = F::from(addr as u64);
+            accesses.push(memory::Access::new(
+                LinearCombination::zero() + (addr, CS::one()),
+                LinearCombination::zero(),
+                LinearCombination::zero() + cell,
+                LinearCombination::zero() + (write, CS::one()),
+                value.map(|value| [addr, F::zero(), value, write]),
+            ));
+        }
+
+        // Likewise the stack, whose positions stay below the cells plus
+        // one push per step
+        let stack_filler = trace.map(|_| F::from((self.stack_cells + self.max_steps) as u64));
+        let height_after = |s: &TraceStep| {
+            let (popped, pushed) = stack_effect(s);
+            F::from(s.stack_height as u64) - F::from(popped) + F::from(pushed)
+        };
+        let final_height = self.final_state.as_ref().map(|s| F::from(s.stack.len() as u64));
+        let mut height = LinearCombination::<F>::zero() + initial.height;
+        let mut stack_accesses = Vec::with_capacity(2 * self.stack_cells + 3 * self.max_steps);
+        for (position, &(cell, value)) in initial.stack.iter().enumerate() {
+            let position = F::from(position as u64);
+            stack_accesses.push(memory::Access::new(
+                LinearCombination::zero() + (position, CS::one()),
+                LinearCombination::zero(),
+                LinearCombination::zero() + cell,
+                LinearCombination::zero() + (write, CS::one()),
+                value.map(|value| [position, F::zero(), value, write]),
+            ));
+        }
+
+        // Where the public input, private input and journal are up to. Only
+        // the public input carries over between proofs; the other two count
+        // from the start of the streams this proof is given
+        let mut cursors = [
+            LinearCombination::zero() + initial.input_cursor,
+            LinearCombination::zero(),
+            LinearCombination::zero(),
+        ];
+        let mut cursor_values = [self.initial_state.as_ref().map_or(0, |s| s.input_cursor), 0, 0];
+
+        let gas_table = GasTable::default();
+        let mut gas = initial_gas;
+        let mut pc = initial.program_counter;
+
+        // Execution is over from the start if the initial state says so or
+        // its pc is past the code
+        let at_end = alloc_is_zero(
+            &mut cs.namespace(|| "initially_at_end"),
+            LinearCombination::zero() + pc - code_len,
+            initial_pc.map(|pc| pc - program_len),
+        )?;
+        let initially_halted = cs.alloc(
+            || "initially_halted",
+            || self.initial_state.as_ref()
+                .map(|s| bit(s.halted || s.program_counter == program_len))
+                .ok_or(SynthesisError::AssignmentMissing),
+        )?;
+        cs.enforce(
+            || "halted_at_start",
+            |lc| lc + CS::one() - initial.halted,
+            |lc| lc + CS::one() - at_end,
+            |lc| lc + CS::one() - initially_halted,
+        );
+        let mut halted = LinearCombination::zero() + initially_halted;
+
+        // The code length as a word, for telling where the pc leaves the code
+        let code_len_word = gadgets::decompose(
+            &mut cs.namespace(|| "program_len_bits"),
+            LinearCombination::zero() + code_len,
+            Some(program_len),
+            INT_BITS,
+        )?;
+
+        for step in 0..self.max_steps {
+            let cs = &mut cs.namespace(|| format!("step_{}", step));
+
+            // Fetch: a one-hot choice of the pc among all offsets, or none
+            // once halted, which fixes the opcode and immediate
+            let opcode = cs.alloc(
+                || "opcode",
+                || witness(trace, step, |s| s.map_or(F::zero(), |s| F::from(s.opcode as u64))),
+            )?;
+            let immediate = cs.alloc(
+                || "immediate",
+                || witness(trace, step, |s| {
+                    let immediate = s.and_then(|s| self.program.get(s.pc + 1));
+                    F::from(immediate.copied().unwrap_or(0) as u64)
+                }),
+            )?;
+
+            let mut fetched = halted.clone();
+            let mut fetched_pc = LinearCombination::zero();
+            for offset in 0..self.max_program_len {
+                let name = format!("at_{}", offset);
+                let at = cs.alloc(
+                    || name.clone(),
+                    || witness(trace, step, |s| bit(s.is_some_and(|s| s.pc == offset))),
+                )?;
+                enforce_boolean(cs, &name, at);
+                cs.enforce(
+                    || format!("{}_opcode", name),
+                    |lc| lc + at,
+                    |lc| lc + opcode - &source[offset],
+                    |lc| lc,
+                );
+                let next_byte = source[..self.max_program_len].get(offset + 1)
+                    .cloned()
+                    .unwrap_or_else(LinearCombination::zero);
+                cs.enforce(
+                    || format!("{}_immediate", name),
+                    |lc| lc + at,
+                    |lc| lc + immediate - &next_byte,
+                    |lc| lc,
+                );
+
+                fetched = fetched + at;
+                fetched_pc = fetched_pc + (F::from(offset as u64), at);
+            }
+
+            cs.enforce(
+                || "fetch_one_hot",
+                |_| fetched,
+                |lc| lc + CS::one(),
+                |lc| lc + CS::one(),
+            );
+            cs.enforce(
+                || "fetch_pc",
+                |lc| lc + pc - &fetched_pc,
+                |lc| lc + CS::one() - &halted,
+                |lc| lc,
+            );
+
+            // Decode: a one-hot opcode selector, again empty once halted
+            let mut decoded = halted.clone();
+            let mut decoded_opcode = LinearCombination::zero();
+            let mut advance = LinearCombination::zero();
+            let mut cost = LinearCombination::zero();
+            let mut pops = LinearCombination::zero();
+            let mut pushes = LinearCombination::zero();
+            let mut reads_top = LinearCombination::zero();
+            let mut reads_second = LinearCombination::zero();
+            let mut selectors = Vec::with_capacity(STEP_OPCODES.len());
+            for &(op, len, popped, pushed) in STEP_OPCODES.iter() {
+                let name = selector_name(op);
+                let is_op = cs.alloc(
+                    || name.clone(),
+                    || witness(trace, step, |s| bit(s.is_some_and(|s| s.opcode == op))),
+                )?;
+                enforce_boolean(cs, &name, is_op);
+                decoded = decoded + is_op;
+                decoded_opcode = decoded_opcode + (F::from(op as u64), is_op);
+                advance = advance + (F::from(len), is_op);
+                cost = cost + (F::from(gas_table.cost(op)), is_op);
+                pops = pops + (F::from(popped), is_op);
+                pushes = pushes + (F::from(pushed), is_op);
+                if popped >= 1 {
+                    reads_top = reads_top + is_op;
+                }
+                if popped >= 2 {
+                    reads_second = reads_second + is_op;
+                }
+                selectors.push((op, is_op));
+            }
+            let selector = |op: u8| {
+                selectors.iter().find(|&&(o, _)| o == op).map(|&(_, v)| v).expect("step opcode")
+            };
+
+            cs.enforce(
+                || "decode_one_hot",
+                |_| decoded,
+                |lc| lc + CS::one(),
+                |lc| lc + CS::one(),
+            );
+            cs.enforce(
+                || "decode_opcode",
+                |_| decoded_opcode,
+                |lc| lc + CS::one(),
+                |lc| lc + opcode,
+            );
+
+            // Execute: operands and result are witness, constrained by the
+            // selected operation
+            let a = cs.alloc(|| "a", || witness(trace, step, |s| operand(s, 0)))?;
+            let b = cs.alloc(|| "b", || witness(trace, step, |s| operand(s, 1)))?;
+            let result_value = trace.map(|trace| {
+                trace.get(step).and_then(|s| s.result.as_ref()).map_or(F::zero(), value_to_field)
+            });
+            let result = cs.alloc(|| "result", || result_value.ok_or(SynthesisError::AssignmentMissing))?;
+
+            cs.enforce(
+                || "push",
+                |lc| lc + selector(OP_PUSH),
+                |lc| lc + immediate - result,
+                |lc| lc,
+            );
+
+            // Integer operations act on 64-bit two's complement words and
+            // wrap like the VM. Other steps decompose zero instead, as their
+            // operands need not be integers
+            const WORD_OPCODES: [u8; 4] = [OP_ADD, OP_MUL, OP_LT, OP_GT];
+            let arithmetic = WORD_OPCODES.iter()
+                .fold(LinearCombination::zero(), |lc, &op| lc + selector(op));
+            let is_arithmetic = |s: &TraceStep| WORD_OPCODES.contains(&s.opcode);
+            let mut words = Vec::with_capacity(2);
+            for (index, (name, operand_var)) in [("a", a), ("b", b)].into_iter().enumerate() {
+                let int_value = trace.map(|trace| match trace.get(step) {
+                    Some(s) if is_arithmetic(s) => operand(Some(s), index),
+                    _ => F::zero(),
+                });
+                let int = cs.alloc(
+                    || format!("int_{}", name),
+                    || int_value.ok_or(SynthesisError::AssignmentMissing),
+                )?;
+                cs.enforce(
+                    || format!("int_{}_masked", name),
+                    |lc| lc + &arithmetic,
+                    |lc| lc + operand_var,
+                    |lc| lc + int,
+                );
+                words.push(gadgets::decompose_signed(
+                    &mut cs.namespace(|| format!("{}_word", name)),
+                    LinearCombination::zero() + int,
+                    int_value,
+                    INT_BITS,
+                )?);
+            }
+
+            let (sum, _) = gadgets::add_with_carry(&mut cs.namespace(|| "add"), &words[0], &words[1])?;
+            cs.enforce(
+                || "add_result",
+                |lc| lc + selector(OP_ADD),
+                |_| sum.signed_lc::<F>() - result,
+                |lc| lc,
+            );
+            let (low, _) = gadgets::mul_with_carry(&mut cs.namespace(|| "mul"), &words[0], &words[1])?;
+            cs.enforce(
+                || "mul_result",
+                |lc| lc + selector(OP_MUL),
+                |_| low.signed_lc::<F>() - result,
+                |lc| lc,
+            );
+
+            // Comparisons push 0 or 1. The VM compares the second item
+            // against the top one, so `LT` tests `b < a`
+            let a_value = trace.map(|trace| operand::<F>(trace.get(step), 0));
+            let b_value = trace.map(|trace| operand::<F>(trace.get(step), 1));
+            let equal = alloc_is_equal(
+                &mut cs.namespace(|| "eq"),
+                LinearCombination::zero() + a,
+                LinearCombination::zero() + b,
+                a_value.zip(b_value),
+            )?;
+            let less = gadgets::signed_less_than(&mut cs.namespace(|| "lt"), &words[1], &words[0])?;
+            let greater = gadgets::signed_less_than(&mut cs.namespace(|| "gt"), &words[0], &words[1])?;
+            let a_is_zero = alloc_is_zero(&mut cs.namespace(|| "iszero"), LinearCombination::zero() + a, a_value)?;
+            for (name, op, outcome) in [
+                ("eq_result", OP_EQ, equal),
+                ("lt_result", OP_LT, less),
+                ("gt_result", OP_GT, greater),
+                ("iszero_result", OP_ISZERO, a_is_zero),
+            ] {
+                cs.enforce(
+                    || name,
+                    |lc| lc + selector(op),
+                    |lc| lc + outcome - result,
+                    |lc| lc,
+                );
+            }
+
+            // SYSCALL reads the private or public input, which pushes the
+            // bytes read, writes to the journal or exits; any other syscall
+            // cannot be proven
+            let current = trace.and_then(|trace| trace.get(step));
+            let mut supported = LinearCombination::zero();
+            let mut runs = [CS::one(); STEP_SYSCALLS.len()];
+            for (slot, &(name, syscall)) in STEP_SYSCALLS.iter().enumerate() {
+                let number = F::from(syscall as u64);
+                let is_number = alloc_is_zero(
+                    &mut cs.namespace(|| format!("syscall_{}", name)),
+                    LinearCombination::zero() + a - (number, CS::one()),
+                    a_value.map(|a| a - number),
+                )?;
+                supported = supported + is_number;
+                runs[slot] = cs.alloc(
+                    || format!("runs_{}", name),
+                    || witness(trace, step, |s| bit(s.is_some_and(|s| runs_syscall(s, syscall)))),
+                )?;
+                cs.enforce(
+                    || format!("runs_{}_selected", name),
+                    |lc| lc + selector(OP_SYSCALL),
+                    |lc| lc + is_number,
+                    |lc| lc + runs[slot],
+                );
+            }
+            cs.enforce(
+                || "syscall_supported",
+                |lc| lc + selector(OP_SYSCALL),
+                |lc| lc + CS::one() - &supported,
+                |lc| lc,
+            );
+            let [private_read, public_read, journal_write, syscall_exits] = runs;
+            let reading = LinearCombination::zero() + private_read + public_read;
+            let pushes = pushes + &reading;
+
+            // Byte regions: SHA3 hashes `a` bytes of the program from `b`, a
+            // read takes up to `b` bytes of its input from the cursor, and a
+            // write appends the bytes in `b` to the journal. A read comes up
+            // short only at the end of its input
+            let is_sha3 = selector(OP_SHA3);
+            let picked = trace.map(|trace| trace.get(step).and_then(|s| {
+                let moved = |syscall| moved_bytes(s, syscall).map(|bytes| bytes.len());
+                if s.opcode == OP_SHA3 {
+                    match (s.operands.first(), s.operands.get(1)) {
+                        (Some(Value::Int(size)), Some(Value::Int(offset))) if *size >= 0 && *offset >= 0 => {
+                            Some((*offset as usize, *size as usize))
+                        }
+                        _ => None,
+                    }
+                } else if let Some(size) = moved(Syscall::ReadPublic) {
+                    Some((public_base + cursor_values[0], size))
+                } else if let Some(size) = moved(Syscall::Read) {
+                    Some((private_base + cursor_values[1], size))
+                } else {
+                    moved(Syscall::Write).map(|size| (journal_base + cursor_values[2], size))
+                }
+            }));
+            let region = self.alloc_region(
+                &mut cs.namespace(|| "bytes"),
+                &source,
+                source_values.as_deref(),
+                &(reading.clone() + journal_write + is_sha3),
+                picked,
+            )?;
+            let size_value = region.size_value;
+
+            let limit_value = trace.map(|_| match current {
+                Some(s) if s.opcode == OP_SHA3 => self.program.len(),
+                Some(s) if runs_syscall(s, Syscall::ReadPublic) => public_base + self.public_input.len(),
+                Some(s) if runs_syscall(s, Syscall::Read) => private_base + private_values.as_ref().map_or(0, Vec::len),
+                Some(s) if runs_syscall(s, Syscall::Write) => journal_base + journal_values.as_ref().map_or(0, Vec::len),
+                _ => 0,
+            });
+            let limit = cs.alloc(
+                || "limit",
+                || limit_value.map(|limit| F::from(limit as u64)).ok_or(SynthesisError::AssignmentMissing),
+            )?;
+            let constant = |value: usize| LinearCombination::<F>::zero() + (F::from(value as u64), CS::one());
+            let region_sources = [
+                ("sha3", is_sha3, LinearCombination::zero() + b, LinearCombination::zero() + code_len),
+                ("public_read", public_read, constant(public_base) + &cursors[0], constant(public_base) + public_len),
+                ("private_read", private_read, constant(private_base) + &cursors[1], constant(private_base) + private_len),
+                ("journal_write", journal_write, constant(journal_base) + &cursors[2], constant(journal_base) + journal_len),
+            ];
+            for (name, active, start, end_limit) in region_sources {
+                cs.enforce(
+                    || format!("{}_start", name),
+                    |lc| lc + active,
+                    |_| region.start.clone() - &start,
+                    |lc| lc,
+                );
+                cs.enforce(
+                    || format!("{}_limit", name),
+                    |lc| lc + active,
+                    |_| end_limit - limit,
+                    |lc| lc,
+                );
+            }
+            cs.enforce(
+                || "sha3_size",
+                |lc| lc + is_sha3,
+                |_| region.size() - a,
+                |lc| lc,
+            );
+            let room = LinearCombination::zero() + limit - &region.end;
+            let end_value = picked.map(|picked| picked.map_or(0, |(offset, size)| offset + size));
+            let room_value = limit_value.zip(end_value).map(|(limit, end)| F::from(limit as u64) - F::from(end as u64));
+            enforce_range(&mut cs.namespace(|| "room"), room.clone(), room_value, room_bits)?;
+
+            let shortfall_value = trace.map(|_| match current {
+                Some(s) if runs_syscall(s, Syscall::Read) || runs_syscall(s, Syscall::ReadPublic) => {
+                    operand::<F>(Some(s), 1) - size_value.unwrap_or(F::zero())
+                }
+                _ => F::zero(),
+            });
+            let shortfall = cs.alloc(|| "shortfall", || shortfall_value.ok_or(SynthesisError::AssignmentMissing))?;
+            cs.enforce(
+                || "shortfall_of_read",
+                |_| reading.clone(),
+                |_| region.size() - b,
+                |lc| lc - shortfall,
+            );
+            enforce_range(
+                &mut cs.namespace(|| "shortfall_range"),
+                LinearCombination::zero() + shortfall,
+                shortfall_value,
+                SHORTFALL_BITS,
+            )?;
+            cs.enforce(
+                || "short_at_end",
+                |lc| lc + shortfall,
+                |_| room,
+                |lc| lc,
+            );
+
+            // A short read of the private input runs it out for good
+            let read_in_full = alloc_is_zero(
+                &mut cs.namespace(|| "read_in_full"),
+                LinearCombination::zero() + shortfall,
+                shortfall_value,
+            )?;
+            let private_short = cs.alloc(
+                || "private_short",
+                || witness(trace, step, |s| {
+                    bit(s.is_some_and(|s| runs_syscall(s, Syscall::Read) && is_short_read(s.operands.get(1), s.result.as_ref())))
+                }),
+            )?;
+            cs.enforce(
+                || "private_short_read",
+                |lc| lc + private_read,
+                |lc| lc + CS::one() - read_in_full,
+                |lc| lc + private_short,
+            );
+            cs.enforce(
+                || "short_read_exhausts",
+                |lc| lc + private_short,
+                |lc| lc + CS::one() - last.private_input_exhausted,
+                |lc| lc,
+            );
+
+            for (index, (name, active, syscall)) in [
+                ("public_advance", public_read, Syscall::ReadPublic),
+                ("private_advance", private_read, Syscall::Read),
+                ("journal_advance", journal_write, Syscall::Write),
+            ].into_iter().enumerate() {
+                let moved = current.and_then(|s| moved_bytes(s, syscall)).map_or(0, |bytes| bytes.len());
+                let advance = cs.alloc(
+                    || name,
+                    || trace.map(|_| F::from(moved as u64)).ok_or(SynthesisError::AssignmentMissing),
+                )?;
+                cs.enforce(
+                    || format!("{}_by_size", name),
+                    |lc| lc + active,
+                    |_| region.size(),
+                    |lc| lc + advance,
+                );
+                cursors[index] = cursors[index].clone() + advance;
+                cursor_values[index] += moved;
+            }
+
+            // What was read is pushed, and what is written must be the
+            // bytes appended to the journal, both as `Value::Bytes`
+            let encoding = poseidon.alloc_hash(
+                &mut cs.namespace(|| "encoding"),
+                &region.input_lcs::<CS>(two_pow_64()),
+                region.inputs(two_pow_64()).as_deref(),
+            )?;
+            cs.enforce(
+                || "read_result",
+                |_| reading,
+                |lc| lc + encoding - result,
+                |lc| lc,
+            );
+            cs.enforce(
+                || "write_value",
+                |lc| lc + journal_write,
+                |lc| lc + encoding - b,
+                |lc| lc,
+            );
+
+            let hashed = self.alloc_digest(&mut cs.namespace(|| "sha3"), poseidon, &region)?;
+            cs.enforce(
+                || "sha3_result",
+                |lc| lc + is_sha3,
+                |lc| lc + hashed - result,
+                |lc| lc,
+            );
+
+            // Memory: STORE writes `b` to address `a` and LOAD reads its
+            // result from `a`, which must hold something; other steps log an
+            // inactive access past the cells
+            let is_store = selector(OP_STORE);
+            let is_load = selector(OP_LOAD);
+            let touches = LinearCombination::zero() + is_store + is_load;
+            let access_values = trace.map(|_| match current {
+                Some(s) if touches_memory(s) => {
+                    let value = if s.opcode == OP_STORE {
+                        operand(Some(s), 1)
+                    } else {
+                        s.result.as_ref().map_or(F::zero(), value_to_field)
+                    };
+                    let op = if s.opcode == OP_STORE { memory::OP_WRITE } else { memory::OP_READ };
+                    (operand(Some(s), 0), value, F::from(op))
+                }
+                _ => (memory_cells, F::zero(), F::from(memory::OP_NONE)),
+            });
+            let mem_addr = cs.alloc(
+                || "mem_addr",
+                || access_values.map(|(addr, _, _)| addr).ok_or(SynthesisError::AssignmentMissing),
+            )?;
+            let mem_value = cs.alloc(
+                || "mem_value",
+                || access_values.map(|(_, value, _)| value).ok_or(SynthesisError::AssignmentMissing),
+            )?;
+            cs.enforce(
+                || "mem_addr_is_a",
+                |_| touches.clone(),
+                |lc| lc + mem_addr - a,
+                |lc| lc,
+            );
+            cs.enforce(
+                || "mem_addr_idle",
+                |lc| lc + CS::one() - &touches,
+                |lc| lc + mem_addr - (memory_cells, CS::one()),
+                |lc| lc,
+            );
+            enforce_range(
+                &mut cs.namespace(|| "mem_addr_in_cells"),
+                LinearCombination::zero() + (memory_cells, CS::one()) - mem_addr - &touches,
+                access_values.map(|(addr, _, op)| memory_cells - addr - bit::<F>(op != F::from(memory::OP_NONE))),
+                bits_for(self.memory_cells),
+            )?;
+            cs.enforce(
+                || "store_value",
+                |lc| lc + is_store,
+                |lc| lc + mem_value - b,
+                |lc| lc,
+            );
+            cs.enforce(
+                || "load_value",
+                |lc| lc + is_load,
+                |lc| lc + mem_value - result,
+                |lc| lc,
+            );
+            let loaded_empty = alloc_is_zero(
+                &mut cs.namespace(|| "load_empty"),
+                LinearCombination::zero() + result - (empty_cell::<F>(), CS::one()),
+                result_value.map(|result| result - empty_cell::<F>()),
+            )?;
+            cs.enforce(
+                || "load_not_empty",
+                |lc| lc + is_load,
+                |lc| lc + loaded_empty,
+                |lc| lc,
+            );
+            let timestamp = F::from(step as u64 + 1);
+            accesses.push(memory::Access::new(
+                LinearCombination::zero() + mem_addr,
+                LinearCombination::zero() + (timestamp, CS::one()),
+                LinearCombination::zero() + mem_value,
+                LinearCombination::zero() + is_load + (F::from(memory::OP_WRITE), is_store),
+                access_values.map(|(addr, value, op)| [addr, timestamp, value, op]),
+            ));
+
+            // Stack: pops read the top positions and a push writes the new
+            // top, checked like memory with positions as addresses. LOG also
+            // pops its topics
+            let mut logged = LinearCombination::zero();
+            let mut topics = LinearCombination::zero();
+            for count in 0..=MAX_LOG_TOPICS {
+                let name = format!("topics_{}", count);
+                let has_count = cs.alloc(
+                    || name.clone(),
+                    || witness(trace, step, |s| {
+                        bit(s.is_some_and(|s| s.opcode == OP_LOG) && operand::<F>(s, 0) == F::from(count))
+                    }),
+                )?;
+                enforce_boolean(cs, &name, has_count);
+                logged = logged + has_count;
+                topics = topics + (F::from(count), has_count);
+            }
+            cs.enforce(
+                || "log_topics_one_hot",
+                |_| logged,
+                |lc| lc + CS::one(),
+                |lc| lc + selector(OP_LOG),
+            );
+            cs.enforce(
+                || "log_topic_count",
+                |lc| lc + selector(OP_LOG),
+                |lc| lc + a,
+                |lc| lc + &topics,
+            );
+            let pops = pops + &topics;
+
+            // The pops must not underflow
+            let remaining = height.clone() - &pops;
+            enforce_range(
+                &mut cs.namespace(|| "remaining"),
+                remaining.clone(),
+                trace.map(|trace| match trace.get(step) {
+                    Some(s) => F::from(s.stack_height as u64) - F::from(stack_effect(s).0),
+                    None => final_height.unwrap_or(F::zero()),
+                }),
+                STACK_HEIGHT_BITS,
+            )?;
+            let next_height = cs.alloc(
+                || "height",
+                || witness(trace, step, |s| s.map_or(final_height.unwrap_or(F::zero()), height_after)),
+            )?;
+            cs.enforce(
+                || "height_update",
+                |lc| lc + &remaining + &pushes,
+                |lc| lc + CS::one(),
+                |lc| lc + next_height,
+            );
+
+            let current_stack = current.map(|s| {
+                let (popped, pushed) = stack_effect(s);
+                let height = F::from(s.stack_height as u64);
+                (popped, pushed, height, height_after(s))
+            });
+            let stack_entries = [
+                ("top", reads_top, LinearCombination::zero() + &height - CS::one(), a, 0),
+                ("second", reads_second, LinearCombination::zero() + &height - (F::from(2), CS::one()), b, 1),
+                ("push", pushes, LinearCombination::zero() + next_height - CS::one(), result, 2),
+            ];
+            for (name, active, position, value, slot) in stack_entries {
+                let entry_value = match slot {
+                    2 => current.and_then(|s| s.result.as_ref()).map_or(F::zero(), value_to_field),
+                    _ => operand(current, slot as usize),
+                };
+                let entry_values = stack_filler.map(|filler| match current_stack {
+                    Some((popped, _, height, _)) if slot < 2 && popped > slot => {
+                        (height - F::from(slot + 1), entry_value, F::from(memory::OP_READ))
+                    }
+                    Some((_, 1, _, after)) if slot == 2 => {
+                        (after - F::one(), entry_value, F::from(memory::OP_WRITE))
+                    }
+                    _ => (filler, entry_value, F::from(memory::OP_NONE)),
+                });
+                let addr = cs.alloc(
+                    || format!("{}_position", name),
+                    || entry_values.map(|(addr, _, _)| addr).ok_or(SynthesisError::AssignmentMissing),
+                )?;
+                cs.enforce(
+                    || format!("{}_at_position", name),
+                    |lc| lc + &active,
+                    |lc| lc + addr - &position,
+                    |lc| lc,
+                );
+                let op = if slot < 2 { F::from(memory::OP_READ) } else { F::from(memory::OP_WRITE) };
+                let timestamp = F::from(3 * step as u64 + slot + 1);
+                stack_accesses.push(memory::Access::new(
+                    LinearCombination::zero() + addr,
+                    LinearCombination::zero() + (timestamp, CS::one()),
+                    LinearCombination::zero() + value,
+                    LinearCombination::zero() + (op, &active),
+                    entry_values.map(|(addr, value, op)| [addr, timestamp, value, op]),
+                ));
+            }
+
+            // Gas: the selected opcode's cost is charged, nothing once
+            // halted, and the remaining gas never goes negative
+            let next_gas_value = trace.and_then(|trace| match trace.get(step) {
+                Some(s) => Some(F::from(s.gas_after)),
+                None => halt_gas,
+            });
+            let next_gas = cs.alloc(
+                || "gas",
+                || next_gas_value.ok_or(SynthesisError::AssignmentMissing),
+            )?;
+            cs.enforce(
+                || "gas_charge",
+                |lc| lc + gas - &cost,
+                |lc| lc + CS::one(),
+                |lc| lc + next_gas,
+            );
+            enforce_range(
+                &mut cs.namespace(|| "gas_left"),
+                LinearCombination::zero() + next_gas,
+                next_gas_value,
+                GAS_BITS,
+            )?;
+
+            // pc update: JUMP, and JUMPI on a nonzero condition `b`, go to
+            // `a`; anything else advances by the selected instruction's
+            // length, which is zero for STOP and for halted padding
+            let condition_is_zero = alloc_is_zero(
+                &mut cs.namespace(|| "condition"),
+                LinearCombination::zero() + b,
+                b_value,
+            )?;
+            let branches = cs.alloc(
+                || "branches",
+                || witness(trace, step, |s| {
+                    bit(s.is_some_and(|s| s.opcode == OP_JUMPI) && operand::<F>(s, 1) != F::zero())
+                }),
+            )?;
+            cs.enforce(
+                || "branch_taken",
+                |lc| lc + selector(OP_JUMPI),
+                |lc| lc + CS::one() - condition_is_zero,
+                |lc| lc + branches,
+            );
+
+            let next_pc_value = trace.and_then(|trace| match trace.get(step) {
+                Some(s) => Some(F::from(s.next_pc as u64)),
+                None => halt_pc,
+            });
+            let next_pc = gadgets::select(
+                &mut cs.namespace(|| "next_pc"),
+                LinearCombination::zero() + selector(OP_JUMP) + branches,
+                LinearCombination::zero() + a,
+                LinearCombination::zero() + pc + &advance,
+                next_pc_value,
+            )?;
+
+            // Halting: STOP, RETURN, the exit syscall, or running off the
+            // end of the code, by stepping or jumping to any pc at or past
+            // it. Callees run on a VM of their own, so RETURN always ends
+            // the execution
+            let next_pc_word = gadgets::decompose(
+                &mut cs.namespace(|| "next_pc_bits"),
+                LinearCombination::zero() + next_pc,
+                next_pc_value,
+                INT_BITS,
+            )?;
+            let in_code = gadgets::less_than(&mut cs.namespace(|| "in_code"), &next_pc_word, &code_len_word)?;
+            let stops = LinearCombination::zero() + selector(OP_STOP) + selector(OP_RETURN) + syscall_exits;
+            let keeps_running_value = trace.map(|trace| match trace.get(step) {
+                Some(s) => !self.halts_after(s),
+                None => next_pc_word.value().is_some_and(|pc| pc < self.program.len() as u64),
+            });
+            let keeps_running = cs.alloc(
+                || "keeps_running",
+                || keeps_running_value.map(bit).ok_or(SynthesisError::AssignmentMissing),
+            )?;
+            cs.enforce(
+                || "keeps_running_unless_stopped",
+                |lc| lc + CS::one() - &stops,
+                |lc| lc + in_code,
+                |lc| lc + keeps_running,
+            );
+
+            let next_halted = cs.alloc(
+                || "halted",
+                || witness(trace, step, |s| bit(s.is_none_or(|s| self.halts_after(s)))),
+            )?;
+            cs.enforce(
+                || "halting",
+                |lc| lc + CS::one() - &halted,
+                |lc| lc + keeps_running,
+                |lc| lc + CS::one() - next_halted,
+            );
+
+            pc = next_pc;
+            gas = next_gas;
+            height = LinearCombination::zero() + next_height;
+            halted = LinearCombination::zero() + next_halted;
+        }
+
+        // Read back every memory cell, and the stack cells below the final
+        // height, after the last step
+        let memory_end = F::from(self.max_steps as u64 + 1);
+        for (addr, &(cell, value)) in last.memory.iter().enumerate() {
+            let addr = F::from(addr as u64);
+            accesses.push(memory::Access::new(
+                LinearCombination::zero() + (addr, CS::one()),
+                LinearCombination::zero() + (memory_end, CS::one()),
+                LinearCombination::zero() + cell,
+                LinearCombination::zero() + (read, CS::one()),
+                value.map(|value| [addr, memory_end, value, read]),
+            ));
+        }
+        let stack_end = F::from(3 * self.max_steps as u64 + 1);
+        for (position, (&(cell, value), &(occupied, is_occupied))) in last.stack.iter().zip(&last.occupied).enumerate() {
+            let position = F::from(position as u64);
+            stack_accesses.push(memory::Access::new(
+                LinearCombination::zero() + (position, CS::one()),
+                LinearCombination::zero() + (stack_end, CS::one()),
+                LinearCombination::zero() + cell,
+                LinearCombination::zero() + (read, occupied),
+                value.zip(is_occupied).map(|(value, occupied)| {
+                    [position, stack_end, value, if occupied { read } else { F::from(memory::OP_NONE) }]
+                }),
+            ));
+        }
+
+        memory::enforce_consistency(&mut cs.namespace(|| "memory"), accesses, trace.map(|_| memory_cells))?;
+        memory::enforce_consistency(&mut cs.namespace(|| "stack"), stack_accesses, stack_filler)?;
+
+        let [input_cursor, private_cursor, journal_cursor] = cursors;
+        for (name, reached, expected) in [
+            ("ends_at_final_pc", LinearCombination::zero() + pc, last.program_counter),
+            ("ends_halted", halted, last.halted),
+            ("exposes_halted", LinearCombination::zero() + last.halted, halts),
+            ("ends_at_final_height", height, last.height),
+            ("ends_at_input_cursor", input_cursor, last.input_cursor),
+            ("reads_private_input", private_cursor, private_len),
+            ("writes_journal", journal_cursor, journal_len),
+            ("ends_with_final_gas", LinearCombination::zero() + gas, final_gas),
+        ] {
+            cs.enforce(
+                || name,
+                |_| reached,
+                |lc| lc + CS::one(),
+                |lc| lc + expected,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl<F: PrimeField> PublicInputs<F> for VMCircuit<F> {
+    fn public_inputs(&self) -> Vec<F> {
+        self.public_values().map_or_else(Vec::new, |values| values.to_vec())
+    }
+}
@@ -0,0 +1,238 @@
+//! Poseidon, an algebraic hash that is cheap to prove, natively and as a
+//! gadget over the same parameters.
+//!
+//! The permutation acts on three field elements with the `x^5` S-box, eight
+//! full and 57 partial rounds, which targets 128-bit security on
+//! 255-bit fields whose multiplicative group order is coprime to 5, such as
+//! the BLS12-381 scalar field. Round constants are expanded from a fixed
+//! seed and the MDS matrix is a Cauchy matrix, so nothing is hidden in
+//! either. Hashing absorbs two elements per permutation, zero padded;
+//! callers length-prefix variable inputs so that padding is unambiguous.
+
+use bellman::{ConstraintSystem, LinearCombination, SynthesisError, Variable};
+use ff::PrimeField;
+use parking_lot::Mutex;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use crate::vm::hash32;
+use super::bytes_to_field;
+
+pub const WIDTH: usize = 3;
+pub const RATE: usize = WIDTH - 1;
+pub const FULL_ROUNDS: usize = 8;
+pub const PARTIAL_ROUNDS: usize = 57;
+
+/// Bytes packed into each field element of a hashed byte string.
+pub const WORD_BYTES: usize = 31;
+
+/// An element of the permutation state while it is being constrained, with
+/// its assignment.
+type StateCell<F> = (LinearCombination<F>, Option<F>);
+
+/// Seed of the round constants, bumped whenever the parameters change.
+const CONSTANTS_DOMAIN: &[u8] = b"zkvm/poseidon/v1";
+
+/// Parameters of the permutation over `F`.
+#[derive(Clone, Debug)]
+pub struct Poseidon<F: PrimeField> {
+    round_constants: Vec<[F; WIDTH]>,
+    mds: [[F; WIDTH]; WIDTH],
+}
+
+impl<F: PrimeField> Default for Poseidon<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F: PrimeField> Poseidon<F> {
+    pub fn new() -> Self {
+        let round_constants = (0..FULL_ROUNDS + PARTIAL_ROUNDS)
+            .map(|round| {
+                let mut constants = [F::zero(); WIDTH];
+                for (i, constant) in constants.iter_mut().enumerate() {
+                    let mut seed = CONSTANTS_DOMAIN.to_vec();
+                    seed.extend_from_slice(&(round as u32).to_be_bytes());
+                    seed.push(i as u8);
+                    *constant = bytes_to_field(&hash32(&seed));
+                }
+                constants
+            })
+            .collect();
+
+        // 1 / (x_i + y_j) with distinct x_i = i and y_j = WIDTH + j
+        let mut mds = [[F::zero(); WIDTH]; WIDTH];
+        for (i, row) in mds.iter_mut().enumerate() {
+            for (j, entry) in row.iter_mut().enumerate() {
+                *entry = F::from((i + WIDTH + j) as u64).invert().unwrap();
+            }
+        }
+
+        Self { round_constants, mds }
+    }
+
+    /// Parameters over `F`, expanded on first use and shared afterwards.
+    /// Expanding the round constants takes a few hundred Blake2 calls, too
+    /// many to repeat on every hash.
+    pub fn shared() -> &'static Self {
+        static PARAMETERS: OnceLock<Mutex<HashMap<TypeId, &'static (dyn Any + Send + Sync)>>> = OnceLock::new();
+
+        let mut parameters = PARAMETERS.get_or_init(Default::default).lock();
+        let entry = parameters
+            .entry(TypeId::of::<F>())
+            .or_insert_with(|| Box::leak(Box::new(Self::new())));
+        entry.downcast_ref().expect("parameters keyed by their field")
+    }
+
+    fn is_full_round(round: usize) -> bool {
+        !(FULL_ROUNDS / 2..FULL_ROUNDS / 2 + PARTIAL_ROUNDS).contains(&round)
+    }
+
+    pub fn permute(&self, state: &mut [F; WIDTH]) {
+        for (round, constants) in self.round_constants.iter().enumerate() {
+            for (element, constant) in state.iter_mut().zip(constants) {
+                *element += constant;
+            }
+            let sboxes = if Self::is_full_round(round) { WIDTH } else { 1 };
+            for element in state.iter_mut().take(sboxes) {
+                *element = element.square().square() * *element;
+            }
+            *state = self.mix(state);
+        }
+    }
+
+    fn mix(&self, state: &[F; WIDTH]) -> [F; WIDTH] {
+        let mut mixed = [F::zero(); WIDTH];
+        for (out, row) in mixed.iter_mut().zip(&self.mds) {
+            *out = row.iter().zip(state).fold(F::zero(), |acc, (m, s)| acc + *m * s);
+        }
+        mixed
+    }
+
+    /// Sponge hash of `inputs`.
+    pub fn hash(&self, inputs: &[F]) -> F {
+        let mut state = [F::zero(); WIDTH];
+        for chunk in padded(inputs.len()).map(|range| &inputs[range]) {
+            for (element, input) in state[1..].iter_mut().zip(chunk) {
+                *element += input;
+            }
+            self.permute(&mut state);
+        }
+        state[1]
+    }
+
+    /// Allocates the hash of `inputs`, constrained round by round. Each
+    /// S-box costs three constraints, and in partial rounds the two
+    /// elements that skip it are reallocated to keep the linear
+    /// combinations small.
+    pub fn alloc_hash<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        inputs: &[LinearCombination<F>],
+        assignment: Option<&[F]>,
+    ) -> Result<Variable, SynthesisError> {
+        let mut state = [(); WIDTH].map(|_| (LinearCombination::zero(), assignment.map(|_| F::zero())));
+        for (index, range) in padded(inputs.len()).enumerate() {
+            for (offset, i) in range.enumerate() {
+                let (lc, value) = &mut state[1 + offset];
+                *lc = lc.clone() + &inputs[i];
+                *value = value.zip(assignment).map(|(v, inputs)| v + inputs[i]);
+            }
+            state = self.alloc_permutation(&mut cs.namespace(|| format!("permutation_{}", index)), state)?;
+        }
+
+        let (lc, value) = state[1].clone();
+        let digest = cs.alloc(|| "digest", || value.ok_or(SynthesisError::AssignmentMissing))?;
+        cs.enforce(
+            || "digest_is_output",
+            |_| lc,
+            |lc| lc + CS::one(),
+            |lc| lc + digest,
+        );
+        Ok(digest)
+    }
+
+    fn alloc_permutation<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        mut state: [StateCell<F>; WIDTH],
+    ) -> Result<[StateCell<F>; WIDTH], SynthesisError> {
+        for (round, constants) in self.round_constants.iter().enumerate() {
+            let full = Self::is_full_round(round);
+            let cs = &mut cs.namespace(|| format!("round_{}", round));
+            for (i, ((lc, value), constant)) in state.iter_mut().zip(constants).enumerate() {
+                let input = lc.clone() + (*constant, CS::one());
+                let input_value = value.map(|v| v + constant);
+                *value = input_value;
+                *lc = if i == 0 || full {
+                    let output = alloc_sbox(&mut cs.namespace(|| format!("sbox_{}", i)), input, input_value)?;
+                    *value = input_value.map(|v| v.square().square() * v);
+                    LinearCombination::zero() + output
+                } else {
+                    let copy = cs.alloc(|| format!("state_{}", i), || input_value.ok_or(SynthesisError::AssignmentMissing))?;
+                    cs.enforce(
+                        || format!("state_{}_copied", i),
+                        |_| input,
+                        |lc| lc + CS::one(),
+                        |lc| lc + copy,
+                    );
+                    LinearCombination::zero() + copy
+                };
+            }
+
+            let mut mixed = [(); WIDTH].map(|_| (LinearCombination::zero(), Some(F::zero())));
+            for ((lc, value), row) in mixed.iter_mut().zip(&self.mds) {
+                for ((input, input_value), m) in state.iter().zip(row) {
+                    *lc = lc.clone() + (*m, input);
+                    *value = value.zip(*input_value).map(|(v, input)| v + *m * input);
+                }
+            }
+            state = mixed;
+        }
+        Ok(state)
+    }
+}
+
+/// `x^5` as three multiplications.
+fn alloc_sbox<F: PrimeField, CS: ConstraintSystem<F>>(
+    cs: &mut CS,
+    x: LinearCombination<F>,
+    value: Option<F>,
+) -> Result<Variable, SynthesisError> {
+    let square = cs.alloc(|| "x2", || value.map(|v| v.square()).ok_or(SynthesisError::AssignmentMissing))?;
+    cs.enforce(|| "square", |_| x.clone(), |_| x.clone(), |lc| lc + square);
+    let fourth = cs.alloc(|| "x4", || value.map(|v| v.square().square()).ok_or(SynthesisError::AssignmentMissing))?;
+    cs.enforce(|| "fourth", |lc| lc + square, |lc| lc + square, |lc| lc + fourth);
+    let fifth = cs.alloc(
+        || "x5",
+        || value.map(|v| v.square().square() * v).ok_or(SynthesisError::AssignmentMissing),
+    )?;
+    cs.enforce(|| "fifth", |lc| lc + fourth, |_| x, |lc| lc + fifth);
+    Ok(fifth)
+}
+
+/// Ranges of the absorbed chunks of `len` inputs: at least one, the last
+/// possibly short and implicitly zero padded.
+fn padded(len: usize) -> impl Iterator<Item = std::ops::Range<usize>> {
+    let chunks = len.div_ceil(RATE).max(1);
+    (0..chunks).map(move |c| c * RATE..((c + 1) * RATE).min(len))
+}
+
+/// `bytes` packed little-endian, `WORD_BYTES` to a field element.
+pub fn pack_bytes<F: PrimeField>(bytes: &[u8]) -> Vec<F> {
+    bytes.chunks(WORD_BYTES)
+        .map(|chunk| {
+            let mut word = [0u8; 32];
+            word[..chunk.len()].copy_from_slice(chunk);
+            bytes_to_field::<F>(&word)
+        })
+        .collect()
+}
+
+/// Hash of a byte string: its length, then its packed words.
+pub fn hash_bytes<F: PrimeField>(bytes: &[u8]) -> F {
+    let mut inputs = vec![F::from(bytes.len() as u64)];
+    inputs.extend(pack_bytes::<F>(bytes));
+    Poseidon::shared().hash(&inputs)
+}
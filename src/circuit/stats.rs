@@ -0,0 +1,225 @@
+//! Size of a synthesized `VMCircuit`, for capacity planning and for
+//! catching cost regressions.
+//!
+//! The circuit is synthesized into a constraint system that only counts,
+//! so no witness is needed. Counts are broken down by gadget, meaning the
+//! namespace they were allocated under, and by opcode for the parts of
+//! each step that serve a single opcode.
+
+use bellman::{Circuit, ConstraintSystem, Index, LinearCombination, SynthesisError, Variable};
+use ff::PrimeField;
+use std::collections::{BTreeMap, HashMap};
+use std::marker::PhantomData;
+use super::{
+    selector_name, VMCircuit, MAX_LOG_TOPICS, OP_ADD, OP_EQ, OP_GT, OP_ISZERO, OP_JUMPI, OP_LOAD,
+    OP_LOG, OP_LT, OP_MUL, OP_PUSH, OP_SHA3, OP_STORE, OP_SYSCALL, STEP_OPCODES, STEP_SYSCALLS,
+};
+
+/// Key of everything allocated outside any namespace.
+pub const TOP_LEVEL: &str = "top";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub constraints: usize,
+    pub variables: usize,
+}
+
+impl Counts {
+    fn count(&mut self, kind: Kind) {
+        match kind {
+            Kind::Constraint => self.constraints += 1,
+            Kind::Variable => self.variables += 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Constraint,
+    Variable,
+}
+
+/// Counts of a synthesized circuit. `variables` are the private ones;
+/// `public_inputs` does not include the constant one.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CircuitStats {
+    pub constraints: usize,
+    pub variables: usize,
+    pub public_inputs: usize,
+    /// What a single step allocates; every step costs the same.
+    pub per_step: Counts,
+    /// Keyed by the first two namespaces, with numbered ones such as
+    /// `step_3` folded into `step_*`.
+    pub gadgets: BTreeMap<String, Counts>,
+    /// What each step spends on one opcode only: its selector and the
+    /// gadgets and constraints computing its result, over all steps.
+    /// Shared machinery, such as fetching or the 64-bit words integer
+    /// opcodes decompose their operands into, is only in `gadgets`.
+    pub opcodes: BTreeMap<u8, Counts>,
+}
+
+/// Names, directly inside a step, of what only `op` allocates.
+fn owned_by(op: u8) -> Vec<String> {
+    let selector = selector_name(op);
+    let mut names = vec![format!("{}_boolean", selector), selector];
+    let owned: &[&str] = match op {
+        OP_PUSH => &["push"],
+        OP_ADD => &["add", "add_result"],
+        OP_MUL => &["mul", "mul_result"],
+        OP_STORE => &["store_value"],
+        OP_LOAD => &["load_value", "load_empty", "load_not_empty"],
+        OP_JUMPI => &["condition", "branches", "branch_taken"],
+        OP_EQ => &["eq", "eq_result"],
+        OP_LT => &["lt", "lt_result"],
+        OP_GT => &["gt", "gt_result"],
+        OP_SHA3 => &["sha3", "sha3_start", "sha3_limit", "sha3_size", "sha3_result"],
+        OP_SYSCALL => &["syscall_supported", "read_result", "write_value", "private_short", "private_short_read", "short_read_exhausts"],
+        OP_LOG => &["log_topics_one_hot", "log_topic_count"],
+        OP_ISZERO => &["iszero", "iszero_result"],
+        _ => &[],
+    };
+    names.extend(owned.iter().map(|name| name.to_string()));
+    if op == OP_SYSCALL {
+        for (name, _) in STEP_SYSCALLS {
+            names.push(format!("syscall_{}", name));
+            names.push(format!("runs_{}", name));
+            names.push(format!("runs_{}_selected", name));
+        }
+        for source in ["public_read", "private_read", "journal_write"] {
+            names.push(format!("{}_start", source));
+            names.push(format!("{}_limit", source));
+        }
+        for cursor in ["public", "private", "journal"] {
+            names.push(format!("{}_advance", cursor));
+            names.push(format!("{}_advance_by_size", cursor));
+        }
+    }
+    if op == OP_LOG {
+        for count in 0..=MAX_LOG_TOPICS {
+            names.push(format!("topics_{}", count));
+            names.push(format!("topics_{}_boolean", count));
+        }
+    }
+    names
+}
+
+/// `name` with a trailing index replaced by `*`.
+fn fold_index(name: &str) -> String {
+    match name.rsplit_once('_') {
+        Some((stem, index)) if !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()) => {
+            format!("{}_*", stem)
+        }
+        _ => name.to_string(),
+    }
+}
+
+struct Counter<F: PrimeField> {
+    namespace: Vec<String>,
+    owners: HashMap<String, u8>,
+    steps: Counts,
+    stats: CircuitStats,
+    _marker: PhantomData<F>,
+}
+
+impl<F: PrimeField> Counter<F> {
+    fn new() -> Self {
+        let owners = STEP_OPCODES.iter()
+            .flat_map(|&(op, ..)| owned_by(op).into_iter().map(move |name| (name, op)))
+            .collect();
+        Self {
+            namespace: Vec::new(),
+            owners,
+            steps: Counts::default(),
+            stats: CircuitStats::default(),
+            _marker: PhantomData,
+        }
+    }
+
+    fn record(&mut self, name: String, kind: Kind) {
+        let gadget = match self.namespace.len() {
+            0 => TOP_LEVEL.to_string(),
+            _ => self.namespace.iter().take(2).map(|n| fold_index(n)).collect::<Vec<_>>().join("/"),
+        };
+        self.stats.gadgets.entry(gadget).or_default().count(kind);
+
+        let in_step = self.namespace.first().is_some_and(|n| fold_index(n) == "step_*");
+        if in_step {
+            self.steps.count(kind);
+            let element = self.namespace.get(1).unwrap_or(&name);
+            if let Some(&op) = self.owners.get(element) {
+                self.stats.opcodes.entry(op).or_default().count(kind);
+            }
+        }
+    }
+}
+
+impl<F: PrimeField> ConstraintSystem<F> for Counter<F> {
+    type Root = Self;
+
+    fn alloc<V, A, AR>(&mut self, annotation: A, _value: V) -> Result<Variable, SynthesisError>
+    where
+        V: FnOnce() -> Result<F, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        let index = self.stats.variables;
+        self.stats.variables += 1;
+        self.record(annotation().into(), Kind::Variable);
+        Ok(Variable::new_unchecked(Index::Aux(index)))
+    }
+
+    fn alloc_input<V, A, AR>(&mut self, _annotation: A, _value: V) -> Result<Variable, SynthesisError>
+    where
+        V: FnOnce() -> Result<F, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        // Input 0 is the constant one
+        self.stats.public_inputs += 1;
+        Ok(Variable::new_unchecked(Index::Input(self.stats.public_inputs)))
+    }
+
+    fn enforce<A, AR, LA, LB, LC>(&mut self, annotation: A, _a: LA, _b: LB, _c: LC)
+    where
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+        LA: FnOnce(LinearCombination<F>) -> LinearCombination<F>,
+        LB: FnOnce(LinearCombination<F>) -> LinearCombination<F>,
+        LC: FnOnce(LinearCombination<F>) -> LinearCombination<F>,
+    {
+        self.stats.constraints += 1;
+        self.record(annotation().into(), Kind::Constraint);
+    }
+
+    fn push_namespace<NR, N>(&mut self, name_fn: N)
+    where
+        NR: Into<String>,
+        N: FnOnce() -> NR,
+    {
+        self.namespace.push(name_fn().into());
+    }
+
+    fn pop_namespace(&mut self) {
+        self.namespace.pop();
+    }
+
+    fn get_root(&mut self) -> &mut Self::Root {
+        self
+    }
+}
+
+/// Synthesizes `circuit` and counts what it allocates. The shape of a
+/// `VMCircuit` depends on its bounds only, so a circuit without a program
+/// or trace is counted like any other of the same bounds.
+pub fn count<F: PrimeField>(circuit: VMCircuit<F>) -> Result<CircuitStats, SynthesisError> {
+    let max_steps = circuit.max_steps;
+    let mut counter = Counter::<F>::new();
+    circuit.synthesize(&mut counter)?;
+
+    let mut stats = counter.stats;
+    stats.per_step = Counts {
+        constraints: counter.steps.constraints.checked_div(max_steps).unwrap_or(0),
+        variables: counter.steps.variables.checked_div(max_steps).unwrap_or(0),
+    };
+    Ok(stats)
+}
@@ -0,0 +1,203 @@
+use std::sync::Arc;
+use ff::PrimeField;
+use parking_lot::Mutex;
+use rayon::prelude::*;
+use crate::{
+    circuit::{gadgets::bit, program_commitment, PublicValues, VMCircuit, VMState},
+    proof::{ProofData, ProofSystem},
+    syscall::{ReplayHandler, SyscallRecord},
+    tracer::{StepRecorder, TraceStep},
+    vm::{ExecutionStatus, VM, VMError},
+};
+
+pub const DEFAULT_SEGMENT_CYCLES: usize = 1000;
+
+/// A bounded slice of an execution. `start_snapshot` and the input reads in
+/// `syscalls` are enough to re-run the segment on its own, and `journal`
+/// holds the bytes it wrote.
+#[derive(Clone, Debug)]
+pub struct Segment {
+    pub index: usize,
+    pub start_snapshot: Vec<u8>,
+    pub cycles: u64,
+    pub journal: Vec<u8>,
+    pub syscalls: Vec<SyscallRecord>,
+    pub halted: bool,
+}
+
+/// A segment's proof and whether the machine halted by its end. The state
+/// roots at its boundaries are read from the proof's public values.
+#[derive(Clone)]
+pub struct SegmentProof<F: PrimeField> {
+    pub index: usize,
+    pub halted: bool,
+    pub proof: ProofData<F>,
+}
+
+impl<F: PrimeField> SegmentProof<F> {
+    pub fn public_values(&self) -> Option<PublicValues<F>> {
+        PublicValues::from_slice(&self.proof.public_inputs)
+    }
+}
+
+/// Runs `vm` to completion, cutting the execution every `cycles_per_segment`
+/// instructions.
+pub fn split(vm: &VM, cycles_per_segment: usize) -> Result<Vec<Segment>, VMError> {
+    if cycles_per_segment == 0 {
+        return Err(VMError::ExecutionError("Segment size must be non-zero".to_string()));
+    }
+
+    let mut segments = Vec::new();
+    loop {
+        let start_snapshot = vm.snapshot()?;
+        let start_cycles = vm.get_cycle_count();
+        let start_syscalls = vm.get_syscall_trace().len();
+        let start_journal = vm.get_journal().len();
+
+        let status = vm.run(cycles_per_segment)?;
+
+        segments.push(Segment {
+            index: segments.len(),
+            start_snapshot,
+            cycles: vm.get_cycle_count() - start_cycles,
+            journal: vm.get_journal().split_off(start_journal),
+            syscalls: vm.get_syscall_trace().split_off(start_syscalls),
+            halted: status == ExecutionStatus::Halted,
+        });
+
+        if status == ExecutionStatus::Halted {
+            return Ok(segments);
+        }
+    }
+}
+
+/// Proves segments of one program against a single setup sized for
+/// `cycles_per_segment` steps, independent of the total program length.
+pub struct SegmentProver<F: PrimeField> {
+    proof_system: ProofSystem<F>,
+    program: Vec<u8>,
+    cycles_per_segment: usize,
+}
+
+impl<F: PrimeField> SegmentProver<F> {
+    pub fn setup(program: Vec<u8>, cycles_per_segment: usize) -> Result<Self, Box<dyn std::error::Error>> {
+        let circuit = VMCircuit::new(program.clone(), cycles_per_segment);
+        let proof_system = ProofSystem::setup(circuit)?;
+
+        Ok(Self {
+            proof_system,
+            program,
+            cycles_per_segment,
+        })
+    }
+
+    pub fn cycles_per_segment(&self) -> usize {
+        self.cycles_per_segment
+    }
+
+    /// Re-executes `segment` from its start snapshot, answering input reads
+    /// from its recorded syscalls, and returns the steps it ran.
+    pub fn trace(&self, segment: &Segment) -> Result<Vec<TraceStep>, VMError> {
+        let recorder = Arc::new(Mutex::new(StepRecorder::new()));
+        let replay = VM::new(self.program.clone())
+            .with_syscall_handler(ReplayHandler::new(&segment.syscalls))
+            .with_inspector(recorder.clone());
+        replay.restore(&segment.start_snapshot)?;
+        replay.run(segment.cycles as usize)?;
+
+        let steps = recorder.lock().take_steps();
+        Ok(steps)
+    }
+
+    /// The state `segment` starts in, as the circuit commits to it.
+    pub fn start_state(&self, segment: &Segment) -> Result<VMState<F>, VMError> {
+        let start = VM::new(self.program.clone());
+        start.restore(&segment.start_snapshot)?;
+        let circuit = VMCircuit::<F>::new(self.program.clone(), self.cycles_per_segment);
+        VMState::capture(&start, circuit.memory_cells)
+            .ok_or_else(|| VMError::ExecutionError("Segment starts with memory outside the state root".to_string()))
+    }
+
+    /// Proves `segment` from its start state, reading from `public_input`
+    /// where earlier segments left off.
+    pub fn prove(&self, segment: &Segment, public_input: &[u8]) -> Result<SegmentProof<F>, Box<dyn std::error::Error>> {
+        let circuit = VMCircuit::new(self.program.clone(), self.cycles_per_segment)
+            .with_public_input(public_input.to_vec())
+            .with_initial_state(self.start_state(segment)?)
+            .with_trace(self.trace(segment)?);
+        let values = circuit.public_values()
+            .ok_or_else(|| VMError::ExecutionError("Segment trace cannot be replayed".to_string()))?;
+
+        Ok(SegmentProof {
+            index: segment.index,
+            halted: values.halted == F::one(),
+            proof: self.proof_system.prove(circuit)?,
+        })
+    }
+
+    /// Segments are proven in parallel since each depends only on its own
+    /// start snapshot.
+    pub fn prove_all(&self, segments: &[Segment], public_input: &[u8]) -> Result<Vec<SegmentProof<F>>, Box<dyn std::error::Error>> {
+        let proofs: Result<Vec<_>, String> = segments.par_iter()
+            .map(|segment| self.prove(segment, public_input).map_err(|e| e.to_string()))
+            .collect();
+        proofs.map_err(|e| e.into())
+    }
+
+    /// Checks every segment proof and that the chain starts at `initial_root`,
+    /// links each end root to the next start root, and ends in a halted state.
+    /// Every segment must read the same public input, and each must start
+    /// with the gas the one before left.
+    pub fn verify(
+        &self,
+        proofs: &[SegmentProof<F>],
+        initial_root: F,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let mut values = Vec::with_capacity(proofs.len());
+        for segment in proofs {
+            match segment.public_values() {
+                Some(segment_values) => values.push(segment_values),
+                None => return Ok(false),
+            }
+        }
+
+        let first = match values.first() {
+            Some(first) => first,
+            None => return Ok(false),
+        };
+        if first.initial_root != initial_root {
+            return Ok(false);
+        }
+
+        let program = program_commitment(&self.program, self.program.len());
+        for (i, (segment, segment_values)) in proofs.iter().zip(&values).enumerate() {
+            if segment.index != i
+                || segment_values.program_hash != program
+                || segment_values.public_input_hash != first.public_input_hash
+                || segment_values.halted != bit(segment.halted)
+            {
+                return Ok(false);
+            }
+        }
+
+        for pair in values.windows(2) {
+            if pair[0].halted != F::zero()
+                || pair[0].final_root != pair[1].initial_root
+                || pair[0].gas_limit - pair[0].gas_used != pair[1].gas_limit
+            {
+                return Ok(false);
+            }
+        }
+        if values.last().unwrap().halted != F::one() {
+            return Ok(false);
+        }
+
+        for segment in proofs {
+            if !self.proof_system.verify(&segment.proof)? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
@@ -1,9 +1,15 @@
 pub mod circuit;
 pub mod vm;
 pub mod proof;
+pub mod loader;
+pub mod memory;
+pub mod syscall;
+pub mod continuation;
+pub mod tracer;
+pub mod transaction;
 
 use std::sync::Arc;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use ff::PrimeField;
 use thiserror::Error;
 
@@ -19,17 +25,58 @@ pub enum ZKVMError {
     StateError(String),
 }
 
+/// Steps a `ZKVM` proves when created without an explicit setup.
+pub const DEFAULT_MAX_STEPS: usize = 1000;
+
+/// Trusted setup of the universal `VMCircuit`. It proves any program of at
+/// most `max_program_len` bytes running at most `max_steps` steps, so one
+/// setup can be shared by every `ZKVM` within those bounds.
+#[derive(Clone)]
+pub struct UniversalSetup<F: PrimeField> {
+    proof_system: Arc<proof::ProofSystem<F>>,
+    max_program_len: usize,
+    max_steps: usize,
+}
+
+impl<F: PrimeField> UniversalSetup<F> {
+    pub fn new(max_program_len: usize, max_steps: usize) -> Result<Self, ZKVMError> {
+        let circuit = circuit::VMCircuit::<F>::universal(max_program_len, max_steps);
+        let proof_system = proof::ProofSystem::setup(circuit)
+            .map_err(|e| ZKVMError::ProofError(e))?;
+
+        Ok(Self {
+            proof_system: Arc::new(proof_system),
+            max_program_len,
+            max_steps,
+        })
+    }
+
+    pub fn max_program_len(&self) -> usize {
+        self.max_program_len
+    }
+
+    pub fn max_steps(&self) -> usize {
+        self.max_steps
+    }
+}
+
 pub struct ZKVM<F: PrimeField> {
     vm: vm::VM,
     proof_system: Arc<proof::ProofSystem<F>>,
     circuit: Option<circuit::VMCircuit<F>>,
+    program_commitment: F,
     state: Arc<RwLock<VMState>>,
+    recorder: Arc<Mutex<tracer::StepRecorder>>,
+    public_input: Vec<u8>,
+    initial_root: F,
+    max_io_len: usize,
 }
 
 pub struct VMState {
     pub gas_used: u64,
     pub execution_trace: Vec<ExecutionStep>,
     pub state_root: [u8; 32],
+    pub journal: Vec<u8>,
 }
 
 #[derive(Clone, Debug)]
@@ -46,22 +93,64 @@ impl Default for VMState {
             gas_used: 0,
             execution_trace: Vec::new(),
             state_root: [0; 32],
+            journal: Vec::new(),
         }
     }
 }
 
 impl<F: PrimeField> ZKVM<F> {
     pub fn new(program: Vec<u8>) -> Result<Self, ZKVMError> {
-        let vm = vm::VM::new(program.clone());
-        let circuit = circuit::VMCircuit::new(program.clone(), 1000);
-        let proof_system = proof::ProofSystem::setup(circuit.clone())
-            .map_err(|e| ZKVMError::ProofError(e))?;
-        
+        Self::with_inputs(program, Vec::new(), Vec::new())
+    }
+
+    /// Creates a ZKVM whose program can read `public_input`, which is revealed
+    /// to the verifier, and `private_input`, which is not.
+    pub fn with_inputs(
+        program: Vec<u8>,
+        public_input: Vec<u8>,
+        private_input: Vec<u8>,
+    ) -> Result<Self, ZKVMError> {
+        let setup = UniversalSetup::new(program.len(), DEFAULT_MAX_STEPS)?;
+        Self::with_setup(&setup, program, public_input, private_input)
+    }
+
+    /// Like `with_inputs`, but proves against an existing setup instead of
+    /// running a new one for this program.
+    pub fn with_setup(
+        setup: &UniversalSetup<F>,
+        program: Vec<u8>,
+        public_input: Vec<u8>,
+        private_input: Vec<u8>,
+    ) -> Result<Self, ZKVMError> {
+        if program.len() > setup.max_program_len {
+            return Err(ZKVMError::StateError(format!(
+                "Program of {} bytes exceeds the setup bound of {} bytes",
+                program.len(),
+                setup.max_program_len,
+            )));
+        }
+
+        let recorder = Arc::new(Mutex::new(tracer::StepRecorder::new()));
+        let vm = vm::VM::new(program.clone())
+            .with_syscall_handler(syscall::HostIo::new(public_input.clone(), private_input))
+            .with_inspector(recorder.clone());
+        let program_commitment = circuit::program_commitment(&program, setup.max_program_len);
+        let circuit = circuit::VMCircuit::universal(setup.max_program_len, setup.max_steps)
+            .with_program(program)
+            .with_public_input(public_input.clone());
+        let initial_root = circuit.entry_root();
+        let max_io_len = circuit.max_io_len;
+
         Ok(Self {
             vm,
-            proof_system: Arc::new(proof_system),
+            proof_system: setup.proof_system.clone(),
             circuit: Some(circuit),
+            program_commitment,
             state: Arc::new(RwLock::new(VMState::default())),
+            recorder,
+            public_input,
+            initial_root,
+            max_io_len,
         })
     }
 
@@ -71,8 +160,9 @@ impl<F: PrimeField> ZKVM<F> {
         
         // Update state
         let mut state = self.state.write();
-        state.gas_used = self.vm.get_gas_remaining();
+        state.gas_used = self.vm.get_gas_used();
         state.state_root = self.vm.get_state_root();
+        state.journal = self.vm.get_journal();
         
         // Record execution trace
         let step = ExecutionStep {
@@ -89,7 +179,8 @@ impl<F: PrimeField> ZKVM<F> {
     pub fn generate_proof(&mut self) -> Result<proof::ProofData<F>, ZKVMError> {
         // Create circuit with current state
         let circuit = self.circuit.take()
-            .ok_or_else(|| ZKVMError::StateError("Circuit already consumed".to_string()))?;
+            .ok_or_else(|| ZKVMError::StateError("Circuit already consumed".to_string()))?
+            .with_trace(self.recorder.lock().steps().to_vec());
             
         // Generate proof
         self.proof_system.prove(circuit)
@@ -101,6 +192,30 @@ impl<F: PrimeField> ZKVM<F> {
             .map_err(|e| ZKVMError::ProofError(e))
     }
 
+    /// Verifies a proof and checks that it is for this program, runs from a
+    /// fresh machine until it halts and commits to the given public input and
+    /// journal. The private input is never needed.
+    pub fn verify_with_io(
+        &self,
+        proof_data: &proof::ProofData<F>,
+        public_input: &[u8],
+        journal: &[u8],
+    ) -> Result<bool, ZKVMError> {
+        let values = match circuit::PublicValues::<F>::from_slice(&proof_data.public_inputs) {
+            Some(values) => values,
+            None => return Ok(false),
+        };
+        if values.program_hash != self.program_commitment
+            || values.initial_root != self.initial_root
+            || values.halted != F::one()
+            || values.public_input_hash != circuit::io_commitment(public_input, self.max_io_len)
+            || values.journal_hash != circuit::io_commitment(journal, self.max_io_len)
+        {
+            return Ok(false);
+        }
+        self.verify_proof(proof_data)
+    }
+
     pub fn batch_verify(&self, proofs: &[proof::ProofData<F>]) -> Result<bool, ZKVMError> {
         self.proof_system.batch_verify(proofs)
             .map_err(|e| ZKVMError::ProofError(e))
@@ -117,6 +232,14 @@ impl<F: PrimeField> ZKVM<F> {
     pub fn get_gas_used(&self) -> u64 {
         self.state.read().gas_used
     }
+
+    pub fn get_journal(&self) -> Vec<u8> {
+        self.state.read().journal.clone()
+    }
+
+    pub fn get_public_input(&self) -> &[u8] {
+        &self.public_input
+    }
 }
 
 #[cfg(test)]
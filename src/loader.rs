@@ -0,0 +1,278 @@
+use crate::memory::{AccessPermissions, SegmentType};
+use blake2::{Blake2b512, Digest};
+use thiserror::Error;
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const ELFDATA2MSB: u8 = 2;
+const ET_EXEC: u16 = 2;
+
+const ELF32_HEADER_SIZE: usize = 52;
+const ELF32_PHDR_SIZE: usize = 32;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+
+const PF_X: u32 = 0x1;
+const PF_W: u32 = 0x2;
+const PF_R: u32 = 0x4;
+
+pub const PAGE_SIZE: usize = 4096;
+pub const MAX_IMAGE_SIZE: usize = 16 * 1024 * 1024;
+pub const STACK_SIZE: usize = 64 * 1024;
+
+#[derive(Error, Debug)]
+pub enum LoaderError {
+    #[error("Not an ELF file")]
+    InvalidMagic,
+    #[error("Unsupported ELF class: {0}")]
+    UnsupportedClass(u8),
+    #[error("Unsupported data encoding: {0}")]
+    UnsupportedEncoding(u8),
+    #[error("Not an executable: e_type {0}")]
+    NotExecutable(u16),
+    #[error("Dynamically linked executables are not supported")]
+    DynamicallyLinked,
+    #[error("Truncated ELF: {0}")]
+    Truncated(String),
+    #[error("Invalid segment at {vaddr:#x}: {reason}")]
+    InvalidSegment { vaddr: usize, reason: String },
+    #[error("Entry point {0:#x} is not in an executable segment")]
+    InvalidEntryPoint(usize),
+    #[error("Image exceeds maximum image size")]
+    ImageTooLarge,
+}
+
+pub type LoaderResult<T> = Result<T, LoaderError>;
+
+#[derive(Clone, Debug)]
+pub struct LoadedSegment {
+    pub vaddr: usize,
+    pub size: usize,
+    pub data: Vec<u8>,
+    pub segment_type: SegmentType,
+    pub permissions: AccessPermissions,
+}
+
+#[derive(Clone, Debug)]
+pub struct Program {
+    pub entry: usize,
+    pub segments: Vec<LoadedSegment>,
+    pub stack_top: usize,
+    pub image_id: [u8; 32],
+}
+
+impl LoadedSegment {
+    pub fn end(&self) -> usize {
+        self.vaddr + self.size
+    }
+
+    pub fn contains(&self, address: usize) -> bool {
+        address >= self.vaddr && address < self.end()
+    }
+
+    /// Segment contents including the zero-filled tail (`.bss`) beyond the file image.
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut bytes = self.data.clone();
+        bytes.resize(self.size, 0);
+        bytes
+    }
+}
+
+impl Program {
+    /// Flat code image indexed by virtual address, as executed by the VM.
+    pub fn code(&self) -> Vec<u8> {
+        let end = self.segments.iter()
+            .filter(|s| s.segment_type == SegmentType::Code)
+            .map(|s| s.end())
+            .max()
+            .unwrap_or(0);
+
+        let mut code = vec![0u8; end];
+        for segment in self.segments.iter().filter(|s| s.segment_type == SegmentType::Code) {
+            code[segment.vaddr..segment.end()].copy_from_slice(&segment.bytes());
+        }
+        code
+    }
+
+    pub fn data_segments(&self) -> impl Iterator<Item = &LoadedSegment> {
+        self.segments.iter().filter(|s| s.segment_type == SegmentType::Data)
+    }
+
+    pub fn stack_segment(&self) -> Option<&LoadedSegment> {
+        self.segments.iter().find(|s| s.segment_type == SegmentType::Stack)
+    }
+}
+
+struct ElfReader<'a> {
+    bytes: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> ElfReader<'a> {
+    fn u16(&self, offset: usize) -> LoaderResult<u16> {
+        let raw: [u8; 2] = self.bytes.get(offset..offset + 2)
+            .ok_or_else(|| LoaderError::Truncated(format!("u16 at {:#x}", offset)))?
+            .try_into()
+            .unwrap();
+        Ok(if self.big_endian { u16::from_be_bytes(raw) } else { u16::from_le_bytes(raw) })
+    }
+
+    fn u32(&self, offset: usize) -> LoaderResult<u32> {
+        let raw: [u8; 4] = self.bytes.get(offset..offset + 4)
+            .ok_or_else(|| LoaderError::Truncated(format!("u32 at {:#x}", offset)))?
+            .try_into()
+            .unwrap();
+        Ok(if self.big_endian { u32::from_be_bytes(raw) } else { u32::from_le_bytes(raw) })
+    }
+}
+
+/// Loads a statically linked ELF32 executable, mapping its `PT_LOAD` segments
+/// and reserving a stack above the highest mapped address.
+pub fn load_elf(bytes: &[u8]) -> LoaderResult<Program> {
+    if bytes.len() < ELF32_HEADER_SIZE {
+        return Err(LoaderError::Truncated("ELF header".to_string()));
+    }
+    if bytes[0..4] != ELF_MAGIC {
+        return Err(LoaderError::InvalidMagic);
+    }
+    if bytes[4] != ELFCLASS32 {
+        return Err(LoaderError::UnsupportedClass(bytes[4]));
+    }
+    let big_endian = match bytes[5] {
+        ELFDATA2LSB => false,
+        ELFDATA2MSB => true,
+        other => return Err(LoaderError::UnsupportedEncoding(other)),
+    };
+
+    let reader = ElfReader { bytes, big_endian };
+
+    let e_type = reader.u16(16)?;
+    if e_type != ET_EXEC {
+        return Err(LoaderError::NotExecutable(e_type));
+    }
+
+    let entry = reader.u32(24)? as usize;
+    let phoff = reader.u32(28)? as usize;
+    let phentsize = reader.u16(42)? as usize;
+    let phnum = reader.u16(44)? as usize;
+
+    if phentsize != ELF32_PHDR_SIZE {
+        return Err(LoaderError::Truncated(format!("program header size {}", phentsize)));
+    }
+
+    let mut segments = Vec::new();
+    for i in 0..phnum {
+        let ph = phoff + i * phentsize;
+        let p_type = reader.u32(ph)?;
+
+        match p_type {
+            PT_DYNAMIC | PT_INTERP => return Err(LoaderError::DynamicallyLinked),
+            PT_LOAD => {}
+            _ => continue,
+        }
+
+        let offset = reader.u32(ph + 4)? as usize;
+        let vaddr = reader.u32(ph + 8)? as usize;
+        let filesz = reader.u32(ph + 16)? as usize;
+        let memsz = reader.u32(ph + 20)? as usize;
+        let flags = reader.u32(ph + 24)?;
+
+        if memsz == 0 {
+            continue;
+        }
+        if filesz > memsz {
+            return Err(LoaderError::InvalidSegment {
+                vaddr,
+                reason: "file size exceeds memory size".to_string(),
+            });
+        }
+        if vaddr.checked_add(memsz).is_none_or(|end| end > MAX_IMAGE_SIZE) {
+            return Err(LoaderError::ImageTooLarge);
+        }
+
+        let data = bytes.get(offset..offset + filesz)
+            .ok_or_else(|| LoaderError::Truncated(format!("segment at {:#x}", vaddr)))?
+            .to_vec();
+
+        let (segment_type, permissions) = if flags & PF_X != 0 {
+            if flags & PF_W != 0 {
+                return Err(LoaderError::InvalidSegment {
+                    vaddr,
+                    reason: "segment is both writable and executable".to_string(),
+                });
+            }
+            (SegmentType::Code, AccessPermissions::READ_EXECUTE)
+        } else {
+            let permissions = AccessPermissions::new(flags & PF_R != 0, flags & PF_W != 0, false);
+            (SegmentType::Data, permissions)
+        };
+
+        if let Some(other) = segments.iter().find(|s: &&LoadedSegment| {
+            vaddr < s.end() && s.vaddr < vaddr + memsz
+        }) {
+            return Err(LoaderError::InvalidSegment {
+                vaddr,
+                reason: format!("overlaps segment at {:#x}", other.vaddr),
+            });
+        }
+
+        segments.push(LoadedSegment {
+            vaddr,
+            size: memsz,
+            data,
+            segment_type,
+            permissions,
+        });
+    }
+
+    if !segments.iter().any(|s| s.segment_type == SegmentType::Code && s.contains(entry)) {
+        return Err(LoaderError::InvalidEntryPoint(entry));
+    }
+
+    // Reserve the initial stack on the first page boundary above the image
+    let image_end = segments.iter().map(|s| s.end()).max().unwrap_or(0);
+    let stack_base = image_end.div_ceil(PAGE_SIZE) * PAGE_SIZE;
+    let stack_top = stack_base + STACK_SIZE;
+    if stack_top > MAX_IMAGE_SIZE {
+        return Err(LoaderError::ImageTooLarge);
+    }
+
+    segments.push(LoadedSegment {
+        vaddr: stack_base,
+        size: STACK_SIZE,
+        data: Vec::new(),
+        segment_type: SegmentType::Stack,
+        permissions: AccessPermissions::READ_WRITE,
+    });
+
+    segments.sort_by_key(|s| s.vaddr);
+    let image_id = compute_image_id(entry, &segments);
+
+    Ok(Program {
+        entry,
+        segments,
+        stack_top,
+        image_id,
+    })
+}
+
+/// Program identifier committing to the entry point and every mapped segment.
+pub fn compute_image_id(entry: usize, segments: &[LoadedSegment]) -> [u8; 32] {
+    let mut hasher = Blake2b512::new();
+    hasher.update((entry as u64).to_le_bytes());
+
+    for segment in segments {
+        hasher.update((segment.vaddr as u64).to_le_bytes());
+        hasher.update((segment.size as u64).to_le_bytes());
+        hasher.update([segment.permissions.as_bits()]);
+        hasher.update((segment.data.len() as u64).to_le_bytes());
+        hasher.update(&segment.data);
+    }
+
+    let mut image_id = [0u8; 32];
+    image_id.copy_from_slice(&hasher.finalize()[..32]);
+    image_id
+}
@@ -45,9 +45,9 @@ impl GarbageCollector {
     }
 
     fn mark(&mut self, segments: &[MemorySegment]) {
-        for (i, segment) in segments.iter().enumerate() {
+        for segment in segments {
             if self.is_root_segment(segment) {
-                self.mark_segment(i as u32);
+                self.mark_segment(segment.id());
             }
         }
     }
@@ -66,14 +66,15 @@ impl GarbageCollector {
         // Remove unmarked segments
         let mut i = 0;
         while i < segments.len() {
-            if !self.marked.contains(&(i as u32)) {
+            if !self.marked.contains(&segments[i].id()) {
                 let segment = segments.remove(i);
                 freed_segments += 1;
                 
                 // Free associated pages
-                // This assumes segments track their page IDs
-                // You would need to add this tracking
-                freed_pages += 1;
+                for page_id in segment.pages() {
+                    page_table.free_page(page_id);
+                    freed_pages += 1;
+                }
             } else {
                 i += 1;
             }
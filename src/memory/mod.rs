@@ -1,9 +1,11 @@
 use std::{
     collections::{HashMap, VecDeque},
-    sync::{Arc, RwLock},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
 };
-use blake3::Hash;
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
 use thiserror::Error;
 
 mod page_table;
@@ -11,7 +13,7 @@ mod segment;
 mod permissions;
 mod gc;
 
-pub use page_table::{PageTable, PageEntry, PageFlags};
+pub use page_table::{PageTable, PageFlags};
 pub use segment::{MemorySegment, SegmentType};
 pub use permissions::{AccessPermissions, Permission};
 pub use gc::{GarbageCollector, GCStats};
@@ -30,7 +32,7 @@ pub enum MemoryError {
 
 pub type MemoryResult<T> = Result<T, MemoryError>;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct MemoryAddress {
     segment_id: u32,
     page_id: u32,
@@ -41,7 +43,9 @@ pub struct MemoryAddress {
 pub struct MemoryManager {
     page_table: Arc<RwLock<PageTable>>,
     segments: Arc<RwLock<Vec<MemorySegment>>>,
-    permissions: Arc<RwLock<HashMap<MemoryAddress, AccessPermissions>>>,
+    /// Keyed by segment id.
+    permissions: Arc<RwLock<HashMap<u32, AccessPermissions>>>,
+    next_segment_id: Arc<AtomicU32>,
     gc: Arc<Mutex<GarbageCollector>>,
     cache: Arc<Mutex<LRUCache>>,
     stats: Arc<RwLock<MemoryStats>>,
@@ -56,6 +60,7 @@ pub struct MemoryStats {
     cache_misses: usize,
 }
 
+#[derive(Debug)]
 struct LRUCache {
     capacity: usize,
     cache: HashMap<MemoryAddress, Vec<u8>>,
@@ -68,6 +73,7 @@ impl MemoryManager {
             page_table: Arc::new(RwLock::new(PageTable::new(config.page_size))),
             segments: Arc::new(RwLock::new(Vec::new())),
             permissions: Arc::new(RwLock::new(HashMap::new())),
+            next_segment_id: Arc::new(AtomicU32::new(0)),
             gc: Arc::new(Mutex::new(GarbageCollector::new(config.gc_threshold))),
             cache: Arc::new(Mutex::new(LRUCache::new(config.cache_size))),
             stats: Arc::new(RwLock::new(MemoryStats::default())),
@@ -79,17 +85,19 @@ impl MemoryManager {
         let mut page_table = self.page_table.write();
 
         // Check if GC needed
-        if self.should_collect_garbage() {
+        if self.should_collect_garbage(&segments) {
             self.gc.lock().collect(&mut segments, &mut page_table);
+            self.permissions.write().retain(|id, _| segments.iter().any(|s| s.id() == *id));
         }
 
         // Allocate new segment
-        let segment = MemorySegment::new(segment_type, size)?;
-        let segment_id = segments.len() as u32;
+        let mut segment = MemorySegment::new(segment_type, size)?;
+        let segment_id = self.next_segment_id();
+        segment.set_id(segment_id);
         segments.push(segment);
 
         // Allocate pages
-        let pages_needed = (size + page_table.page_size() - 1) / page_table.page_size();
+        let pages_needed = size.div_ceil(page_table.page_size());
         let mut page_ids = Vec::with_capacity(pages_needed);
 
         for _ in 0..pages_needed {
@@ -103,7 +111,7 @@ impl MemoryManager {
             page_id: page_ids[0],
             offset: 0,
         };
-        self.permissions.write().insert(addr.clone(), AccessPermissions::default());
+        self.permissions.write().insert(segment_id, AccessPermissions::default());
 
         // Update stats
         self.stats.write().total_allocations += 1;
@@ -117,13 +125,15 @@ impl MemoryManager {
         let mut permissions = self.permissions.write();
 
         // Remove segment
-        segments.remove(addr.segment_id as usize);
+        let index = segments.iter().position(|s| s.id() == addr.segment_id)
+            .ok_or(MemoryError::SegmentFault(addr.segment_id as usize))?;
+        segments.remove(index);
 
         // Free pages
         page_table.free_page(addr.page_id);
 
         // Remove permissions
-        permissions.remove(&addr);
+        permissions.remove(&addr.segment_id);
 
         // Update stats
         self.stats.write().total_deallocations += 1;
@@ -132,6 +142,9 @@ impl MemoryManager {
     }
 
     pub fn read(&self, addr: MemoryAddress, size: usize) -> MemoryResult<Vec<u8>> {
+        // Check permissions
+        self.check_permissions(&addr, Permission::Read)?;
+
         // Check cache first
         if let Some(data) = self.cache.lock().get(&addr) {
             self.stats.write().cache_hits += 1;
@@ -139,9 +152,6 @@ impl MemoryManager {
         }
         self.stats.write().cache_misses += 1;
 
-        // Check permissions
-        self.check_permissions(&addr, Permission::Read)?;
-
         // Read from page table
         let page_table = self.page_table.read();
         let data = page_table.read(addr.page_id, addr.offset as usize, size)?;
@@ -167,12 +177,164 @@ impl MemoryManager {
     }
 
     pub fn protect(&self, addr: MemoryAddress, perms: AccessPermissions) -> MemoryResult<()> {
-        self.permissions.write().insert(addr, perms);
+        self.permissions.write().insert(addr.segment_id, perms);
+        Ok(())
+    }
+
+    /// Maps a segment at the fixed virtual address `base`. `data` is copied to
+    /// the start of the segment; pages are only allocated once written, so
+    /// the rest reads as zero.
+    pub fn map(
+        &self,
+        base: usize,
+        size: usize,
+        data: &[u8],
+        segment_type: SegmentType,
+        perms: AccessPermissions,
+    ) -> MemoryResult<u32> {
+        if data.len() > size || base.checked_add(size).is_none() {
+            return Err(MemoryError::SegmentFault(base));
+        }
+
+        let segment_id = {
+            let mut segments = self.segments.write();
+            if segments.iter().any(|s| base < s.base() + s.size() && s.base() < base + size) {
+                return Err(MemoryError::SegmentFault(base));
+            }
+
+            let mut segment = MemorySegment::new(segment_type, size)?;
+            let segment_id = self.next_segment_id();
+            segment.set_id(segment_id);
+            segment.set_base(base);
+            segments.push(segment);
+            segment_id
+        };
+        self.permissions.write().insert(segment_id, perms);
+
+        self.write_range(base, data)?;
+        self.stats.write().total_allocations += 1;
+
+        Ok(segment_id)
+    }
+
+    pub fn is_mapped(&self, vaddr: usize) -> bool {
+        self.segments.read().iter().any(|s| s.contains(vaddr))
+    }
+
+    /// Checks that `vaddr` is mapped and its segment grants `required`.
+    pub fn check_access(&self, vaddr: usize, required: Permission) -> MemoryResult<()> {
+        let segments = self.segments.read();
+        let segment_id = match segments.iter().find(|s| s.contains(vaddr)) {
+            Some(segment) => segment.id(),
+            None => {
+                self.stats.write().page_faults += 1;
+                return Err(MemoryError::PageFault(vaddr));
+            }
+        };
+
+        match self.permissions.read().get(&segment_id) {
+            Some(perms) if perms.has_permission(required) => Ok(()),
+            _ => Err(MemoryError::PermissionDenied(vaddr)),
+        }
+    }
+
+    /// Reads `len` bytes at a virtual address in a readable segment.
+    pub fn read_at(&self, vaddr: usize, len: usize) -> MemoryResult<Vec<u8>> {
+        self.read_range(vaddr, len, Permission::Read)
+    }
+
+    /// Reads `len` bytes of code at a virtual address in an executable segment.
+    pub fn fetch(&self, vaddr: usize, len: usize) -> MemoryResult<Vec<u8>> {
+        self.read_range(vaddr, len, Permission::Execute)
+    }
+
+    /// Writes bytes at a virtual address in a writable segment.
+    pub fn write_at(&self, vaddr: usize, data: &[u8]) -> MemoryResult<()> {
+        self.check_range(vaddr, data.len(), Permission::Write)?;
+        self.write_range(vaddr, data)
+    }
+
+    fn check_range(&self, vaddr: usize, len: usize, required: Permission) -> MemoryResult<()> {
+        self.check_access(vaddr, required)?;
+        if len > 0 {
+            let segments = self.segments.read();
+            let end = vaddr.checked_add(len - 1).ok_or(MemoryError::SegmentFault(vaddr))?;
+            if !segments.iter().any(|s| s.contains(vaddr) && s.contains(end)) {
+                return Err(MemoryError::SegmentFault(vaddr));
+            }
+        }
         Ok(())
     }
 
-    fn should_collect_garbage(&self) -> bool {
+    fn read_range(&self, vaddr: usize, len: usize, required: Permission) -> MemoryResult<Vec<u8>> {
+        self.check_range(vaddr, len, required)?;
+
         let segments = self.segments.read();
+        let page_table = self.page_table.read();
+        let page_size = page_table.page_size();
+        let segment = segments.iter().find(|s| s.contains(vaddr))
+            .ok_or(MemoryError::PageFault(vaddr))?;
+
+        let mut data = Vec::with_capacity(len);
+        let mut offset = vaddr - segment.base();
+        let end = offset + len;
+        while offset < end {
+            let chunk = (page_size - offset % page_size).min(end - offset);
+            match segment.page(offset / page_size) {
+                Some(page_id) => data.extend(page_table.read(page_id, offset % page_size, chunk)?),
+                None => data.resize(data.len() + chunk, 0),
+            }
+            offset += chunk;
+        }
+        Ok(data)
+    }
+
+    /// Writes without a permission check, allocating pages on first touch.
+    /// Page flags follow the segment's permissions.
+    fn write_range(&self, vaddr: usize, data: &[u8]) -> MemoryResult<()> {
+        let mut segments = self.segments.write();
+        let mut page_table = self.page_table.write();
+        let page_size = page_table.page_size();
+        let segment = match segments.iter_mut().find(|s| s.contains(vaddr)) {
+            Some(segment) => segment,
+            None if data.is_empty() => return Ok(()),
+            None => return Err(MemoryError::PageFault(vaddr)),
+        };
+        let perms = self.permissions.read().get(&segment.id()).copied().unwrap_or_default();
+
+        let mut offset = vaddr - segment.base();
+        let mut written = 0;
+        while written < data.len() {
+            let chunk = (page_size - offset % page_size).min(data.len() - written);
+            let index = offset / page_size;
+            let page_id = match segment.page(index) {
+                Some(page_id) => page_id,
+                None => {
+                    let page_id = page_table.allocate_page()?;
+                    segment.set_page(index, page_id);
+                    page_id
+                }
+            };
+
+            let mut flags = page_table.get_flags(page_id)?;
+            flags.writable = true;
+            page_table.set_flags(page_id, flags)?;
+            page_table.write(page_id, offset % page_size, &data[written..written + chunk])?;
+            flags.writable = perms.can_write();
+            flags.executable = perms.can_execute();
+            page_table.set_flags(page_id, flags)?;
+
+            offset += chunk;
+            written += chunk;
+        }
+        Ok(())
+    }
+
+    fn next_segment_id(&self) -> u32 {
+        self.next_segment_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn should_collect_garbage(&self, segments: &[MemorySegment]) -> bool {
         let total_memory = segments.iter().map(|s| s.size()).sum::<usize>();
         let gc = self.gc.lock();
         total_memory >= gc.threshold()
@@ -180,7 +342,7 @@ impl MemoryManager {
 
     fn check_permissions(&self, addr: &MemoryAddress, required: Permission) -> MemoryResult<()> {
         let permissions = self.permissions.read();
-        match permissions.get(addr) {
+        match permissions.get(&addr.segment_id) {
             Some(perms) if perms.has_permission(required) => Ok(()),
             _ => Err(MemoryError::PermissionDenied(addr.segment_id as usize)),
         }
@@ -226,7 +388,17 @@ impl LRUCache {
 
 #[derive(Clone, Debug)]
 pub struct MemoryConfig {
-    page_size: usize,
-    gc_threshold: usize,
-    cache_size: usize,
+    pub page_size: usize,
+    pub gc_threshold: usize,
+    pub cache_size: usize,
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        Self {
+            page_size: 4096,
+            gc_threshold: 64 * 1024 * 1024,
+            cache_size: 256,
+        }
+    }
 }
@@ -123,11 +123,3 @@ impl Default for PageFlags {
         }
     }
 }
-
-impl Page {
-    pub fn clear(&mut self) {
-        self.data.fill(0);
-        self.flags.dirty = false;
-        self.flags.accessed = false;
-    }
-}
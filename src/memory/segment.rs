@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use super::MemoryError;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -10,11 +11,16 @@ pub enum SegmentType {
 
 #[derive(Debug)]
 pub struct MemorySegment {
+    /// Assigned by the `MemoryManager`; unlike the segment's position in
+    /// its list, it survives other segments being freed.
+    id: u32,
     segment_type: SegmentType,
     base: usize,
     size: usize,
     max_size: usize,
     is_growable: bool,
+    /// Page table ids backing the segment, by page index within it.
+    pages: HashMap<usize, u32>,
     metadata: SegmentMetadata,
 }
 
@@ -41,11 +47,13 @@ impl MemorySegment {
         };
 
         Ok(Self {
+            id: 0, // Will be set during allocation
             segment_type,
             base: 0, // Will be set during allocation
             size,
             max_size,
             is_growable,
+            pages: HashMap::new(),
             metadata: SegmentMetadata::new(),
         })
     }
@@ -75,6 +83,14 @@ impl MemorySegment {
         }
     }
 
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn set_id(&mut self, id: u32) {
+        self.id = id;
+    }
+
     pub fn segment_type(&self) -> SegmentType {
         self.segment_type
     }
@@ -91,6 +107,18 @@ impl MemorySegment {
         self.base = base;
     }
 
+    pub fn page(&self, index: usize) -> Option<u32> {
+        self.pages.get(&index).copied()
+    }
+
+    pub fn set_page(&mut self, index: usize, page_id: u32) {
+        self.pages.insert(index, page_id);
+    }
+
+    pub fn pages(&self) -> impl Iterator<Item = u32> + '_ {
+        self.pages.values().copied()
+    }
+
     pub fn is_growable(&self) -> bool {
         self.is_growable
     }
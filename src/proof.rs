@@ -13,6 +13,11 @@ use parking_lot::RwLock;
 use rayon::prelude::*;
 use blake2::{Blake2b512, Blake2s256};
 
+/// Values a circuit exposes to the verifier, in allocation order.
+pub trait PublicInputs<F: PrimeField> {
+    fn public_inputs(&self) -> Vec<F>;
+}
+
 #[derive(Clone)]
 pub struct ProofSystem<F: PrimeField> {
     params: Arc<Parameters<F>>,
@@ -33,15 +38,15 @@ impl<F: PrimeField> ProofSystem<F> {
         })
     }
 
-    pub fn prove<C: Circuit<F>>(&self, circuit: C) -> Result<ProofData<F>, Box<dyn std::error::Error>> {
+    pub fn prove<C: Circuit<F> + PublicInputs<F>>(&self, circuit: C) -> Result<ProofData<F>, Box<dyn std::error::Error>> {
         let rng = &mut thread_rng();
         
+        // Collect public inputs before the circuit is consumed
+        let public_inputs = self.collect_public_inputs(&circuit)?;
+        
         // Generate proof
         let proof = create_random_proof(circuit, &self.params, rng)?;
         
-        // Collect public inputs
-        let public_inputs = self.collect_public_inputs(&proof)?;
-        
         // Generate proof hash
         let proof_hash = self.hash_proof(&proof, &public_inputs)?;
         
@@ -90,10 +95,16 @@ impl<F: PrimeField> ProofSystem<F> {
         Ok(results.iter().all(|&x| x))
     }
 
-    fn collect_public_inputs(&self, proof: &Proof<F>) -> Result<Vec<F>, Box<dyn std::error::Error>> {
-        // Implementation depends on circuit structure
-        // This is a placeholder that should be customized based on your circuit
-        Ok(Vec::new())
+    fn collect_public_inputs<C: PublicInputs<F>>(&self, circuit: &C) -> Result<Vec<F>, Box<dyn std::error::Error>> {
+        let public_inputs = circuit.public_inputs();
+        if public_inputs.len() + 1 != self.verifying_key.ic.len() {
+            return Err(format!(
+                "Circuit exposes {} public inputs, verifying key expects {}",
+                public_inputs.len(),
+                self.verifying_key.ic.len() - 1,
+            ).into());
+        }
+        Ok(public_inputs)
     }
 
     fn hash_proof(&self, proof: &Proof<F>, public_inputs: &[F]) -> Result<[u8; 32], Box<dyn std::error::Error>> {
@@ -0,0 +1,168 @@
+use std::collections::VecDeque;
+use blake2::{Blake2b512, Digest};
+use bls12_381::Scalar;
+use ff::PrimeField;
+use serde::{Deserialize, Serialize};
+use crate::circuit::poseidon;
+use crate::vm::{Value, VMError};
+
+/// Upper bound on how far `brk`/`sbrk` may grow the heap past its start.
+pub const MAX_HEAP_SIZE: usize = 16 * 1024 * 1024;
+
+/// Heap start for programs that are not loaded from an ELF image.
+pub const DEFAULT_HEAP_START: usize = 0x10000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Syscall {
+    /// Pops `len`, pushes up to `len` bytes of private input as `Value::Bytes`.
+    Read = 0x01,
+    /// Pops a `Value::Bytes` or `Value::Int` and appends it to the public journal.
+    Write = 0x02,
+    /// Pops the requested break (0 queries it), pushes the resulting break.
+    Brk = 0x03,
+    /// Pops an increment, pushes the previous break.
+    Sbrk = 0x04,
+    /// Pops an exit code and halts execution.
+    Exit = 0x05,
+    /// Pops a `Value::Bytes`, pushes its 32-byte host-side hash.
+    Hash = 0x06,
+    /// Pops `len`, pushes up to `len` bytes of public input as `Value::Bytes`.
+    ReadPublic = 0x07,
+}
+
+impl Syscall {
+    pub fn from_number(number: i64) -> Result<Self, VMError> {
+        match number {
+            0x01 => Ok(Syscall::Read),
+            0x02 => Ok(Syscall::Write),
+            0x03 => Ok(Syscall::Brk),
+            0x04 => Ok(Syscall::Sbrk),
+            0x05 => Ok(Syscall::Exit),
+            0x06 => Ok(Syscall::Hash),
+            0x07 => Ok(Syscall::ReadPublic),
+            _ => Err(VMError::ExecutionError(format!("Unknown syscall: {}", number))),
+        }
+    }
+}
+
+/// A syscall as it was executed, with its operands and result, so the prover
+/// can replay the run without access to the host.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SyscallRecord {
+    pub pc: usize,
+    pub syscall: Syscall,
+    pub args: Vec<Value>,
+    pub result: Option<Value>,
+}
+
+/// Host side of the guest syscall interface. Heap growth and the journal are
+/// VM state; the handler supplies input and observes everything else.
+pub trait SyscallHandler: Send {
+    /// Returns up to `len` bytes of private input; fewer at end of input.
+    fn read_input(&mut self, len: usize) -> Vec<u8>;
+
+    /// Returns up to `len` bytes of public input; fewer at end of input.
+    fn read_public_input(&mut self, len: usize) -> Vec<u8>;
+
+    /// Called with every chunk the guest commits to the journal.
+    fn write_output(&mut self, _data: &[u8]) {}
+
+    fn hash(&mut self, data: &[u8]) -> [u8; 32] {
+        let mut hasher = Blake2b512::new();
+        hasher.update(data);
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&hasher.finalize()[..32]);
+        hash
+    }
+
+    fn exit(&mut self, _code: i64) {}
+
+    /// Moves the read positions to the given byte offsets into the public
+    /// and private input, as recorded in a restored snapshot. Handlers that
+    /// do not serve input by position, such as a replay, ignore it.
+    fn seek_input(&mut self, _public: usize, _private: usize) {}
+}
+
+/// Default handler serving public and private input from in-memory buffers.
+#[derive(Clone, Debug, Default)]
+pub struct HostIo {
+    public_input: Vec<u8>,
+    private_input: Vec<u8>,
+    public_position: usize,
+    private_position: usize,
+}
+
+impl HostIo {
+    pub fn new(public_input: Vec<u8>, private_input: Vec<u8>) -> Self {
+        Self {
+            public_input,
+            private_input,
+            ..Self::default()
+        }
+    }
+}
+
+fn read_from(input: &[u8], position: &mut usize, len: usize) -> Vec<u8> {
+    let start = (*position).min(input.len());
+    let end = start + len.min(input.len() - start);
+    *position = end;
+    input[start..end].to_vec()
+}
+
+impl SyscallHandler for HostIo {
+    fn read_input(&mut self, len: usize) -> Vec<u8> {
+        read_from(&self.private_input, &mut self.private_position, len)
+    }
+
+    fn read_public_input(&mut self, len: usize) -> Vec<u8> {
+        read_from(&self.public_input, &mut self.public_position, len)
+    }
+
+    fn seek_input(&mut self, public: usize, private: usize) {
+        self.public_position = public.min(self.public_input.len());
+        self.private_position = private.min(self.private_input.len());
+    }
+}
+
+/// Handler that answers input reads from a recorded trace instead of the host.
+#[derive(Clone, Debug)]
+pub struct ReplayHandler {
+    private_reads: VecDeque<Vec<u8>>,
+    public_reads: VecDeque<Vec<u8>>,
+}
+
+impl ReplayHandler {
+    pub fn new(records: &[SyscallRecord]) -> Self {
+        let reads = |syscall: Syscall| -> VecDeque<Vec<u8>> {
+            records.iter()
+                .filter(|r| r.syscall == syscall)
+                .map(|r| match &r.result {
+                    Some(Value::Bytes(data)) => data.clone(),
+                    _ => Vec::new(),
+                })
+                .collect()
+        };
+
+        Self {
+            private_reads: reads(Syscall::Read),
+            public_reads: reads(Syscall::ReadPublic),
+        }
+    }
+}
+
+impl SyscallHandler for ReplayHandler {
+    fn read_input(&mut self, _len: usize) -> Vec<u8> {
+        self.private_reads.pop_front().unwrap_or_default()
+    }
+
+    fn read_public_input(&mut self, _len: usize) -> Vec<u8> {
+        self.public_reads.pop_front().unwrap_or_default()
+    }
+}
+
+/// Commitment to a byte string such as a state snapshot: the Poseidon hash
+/// of its length and packed words over the proof field, in its canonical
+/// encoding.
+pub fn commit_bytes(data: &[u8]) -> [u8; 32] {
+    poseidon::hash_bytes::<Scalar>(data).to_repr()
+}
@@ -0,0 +1,234 @@
+use std::io::{self, Write};
+use serde::Serialize;
+use crate::vm::{Inspector, StepInfo, Value, VMError};
+
+/// One line of an EIP-3155 style trace, emitted before the instruction runs.
+/// `error` is only set on the line of the instruction that failed.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceLine {
+    pub pc: usize,
+    pub op: u8,
+    pub gas: String,
+    pub gas_cost: String,
+    pub mem_size: usize,
+    pub stack: Vec<String>,
+    pub depth: usize,
+    pub op_name: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Final line of a trace, written once execution stops.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceSummary {
+    pub state_root: String,
+    pub output: String,
+    pub gas_used: String,
+    pub pass: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Inspector writing one JSON object per executed instruction. The line for
+/// an instruction is held back until it either completes or fails, so a
+/// failing step carries its error.
+pub struct JsonTracer<W: Write> {
+    writer: W,
+    pending: Option<TraceLine>,
+    io_error: Option<io::Error>,
+}
+
+impl<W: Write> JsonTracer<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            pending: None,
+            io_error: None,
+        }
+    }
+
+    pub fn write_summary(&mut self, summary: &TraceSummary) {
+        self.write_json(summary);
+    }
+
+    /// Returns the writer, or the first write error hit while tracing.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(error) = self.io_error.take() {
+            return Err(error);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_json<T: Serialize>(&mut self, value: &T) {
+        if self.io_error.is_some() {
+            return;
+        }
+        let result = serde_json::to_writer(&mut self.writer, value)
+            .map_err(io::Error::from)
+            .and_then(|_| self.writer.write_all(b"\n"));
+        if let Err(error) = result {
+            self.io_error = Some(error);
+        }
+    }
+
+    fn flush_pending(&mut self) {
+        if let Some(line) = self.pending.take() {
+            self.write_json(&line);
+        }
+    }
+}
+
+impl<W: Write + Send> Inspector for JsonTracer<W> {
+    fn step(&mut self, step: &StepInfo<'_>) {
+        self.flush_pending();
+        self.pending = Some(TraceLine {
+            pc: step.pc,
+            op: step.opcode,
+            gas: format!("{:#x}", step.gas_remaining),
+            gas_cost: format!("{:#x}", step.gas_cost),
+            mem_size: step.memory.len(),
+            stack: step.stack.iter().map(format_value).collect(),
+            depth: step.depth + 1,
+            op_name: opcode_name(step.opcode),
+            error: None,
+        });
+    }
+
+    fn step_end(&mut self, _step: &StepInfo<'_>) {
+        self.flush_pending();
+    }
+
+    fn error(&mut self, _pc: usize, _opcode: u8, error: &VMError) {
+        if let Some(line) = self.pending.as_mut() {
+            // The line already carries the location
+            line.error = Some(error.kind().to_string());
+        }
+        self.flush_pending();
+    }
+}
+
+/// One executed instruction as the circuit sees it: where it ran, where it
+/// went next, what it read from the stack and what it left on top.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceStep {
+    pub pc: usize,
+    pub next_pc: usize,
+    pub opcode: u8,
+    pub gas_before: u64,
+    pub gas_after: u64,
+    /// Stack depth before the step.
+    pub stack_height: usize,
+    /// The top two stack items before the step, topmost first.
+    pub operands: Vec<Value>,
+    /// The top stack item after the step.
+    pub result: Option<Value>,
+    /// Whether the step stopped the machine. Running off the end of the code
+    /// is not a step and leaves this unset.
+    pub halted: bool,
+}
+
+/// Inspector collecting a `TraceStep` for every instruction that completed.
+/// Share it with the VM as an `Arc<Mutex<StepRecorder>>` to read it back.
+#[derive(Debug, Default)]
+pub struct StepRecorder {
+    steps: Vec<TraceStep>,
+    pending: Option<TraceStep>,
+}
+
+impl StepRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn steps(&self) -> &[TraceStep] {
+        &self.steps
+    }
+
+    pub fn take_steps(&mut self) -> Vec<TraceStep> {
+        self.pending = None;
+        std::mem::take(&mut self.steps)
+    }
+}
+
+impl Inspector for StepRecorder {
+    fn step(&mut self, step: &StepInfo<'_>) {
+        self.pending = Some(TraceStep {
+            pc: step.pc,
+            next_pc: step.pc,
+            opcode: step.opcode,
+            gas_before: step.gas_remaining,
+            gas_after: step.gas_remaining,
+            stack_height: step.stack.len(),
+            operands: step.stack.iter().rev().take(2).cloned().collect(),
+            result: None,
+            halted: false,
+        });
+    }
+
+    fn step_end(&mut self, step: &StepInfo<'_>) {
+        if let Some(mut recorded) = self.pending.take() {
+            recorded.next_pc = step.next_pc;
+            recorded.gas_after = step.gas_remaining;
+            recorded.result = step.stack.last().cloned();
+            recorded.halted = step.halted;
+            self.steps.push(recorded);
+        }
+    }
+
+    fn error(&mut self, _pc: usize, _opcode: u8, _error: &VMError) {
+        self.pending = None;
+    }
+}
+
+pub fn opcode_name(opcode: u8) -> &'static str {
+    match opcode {
+        0x01 => "PUSH",
+        0x02 => "ADD",
+        0x03 => "MUL",
+        0x04 => "STORE",
+        0x05 => "LOAD",
+        0x06 => "JUMP",
+        0x07 => "JUMPI",
+        0x08 => "EQ",
+        0x09 => "LT",
+        0x0A => "GT",
+        0x0B => "CREATE",
+        0x0C => "CALL",
+        0x0D => "RETURN",
+        0x0E => "SHA3",
+        0x0F => "BALANCE",
+        0x10 => "SYSCALL",
+        0x11 => "SSTORE",
+        0x12 => "SLOAD",
+        0x13 => "LOG",
+        0x14 => "CALLDATA",
+        0x15 => "CALLER",
+        0x16 => "STATICCALL",
+        0x17 => "ISZERO",
+        0xFF => "STOP",
+        _ => "INVALID",
+    }
+}
+
+/// Stack items are rendered as hex quantities, byte strings as hex data.
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::Int(v) => format!("{:#x}", v),
+        Value::Bool(b) => format!("{:#x}", *b as u8),
+        Value::Bytes(bytes) => to_hex(bytes),
+        Value::Address(address) => to_hex(address),
+        Value::Contract(contract) => to_hex(&contract.code),
+    }
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(2 + bytes.len() * 2);
+    hex.push_str("0x");
+    for byte in bytes {
+        hex.push_str(&format!("{:02x}", byte));
+    }
+    hex
+}
@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+use crate::vm::{encode_bytes, hash32, Log, DEFAULT_GAS_LIMIT};
+
+/// A call into the persistent VM: the code to run, its input and who sent it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Transaction {
+    pub sender: [u8; 32],
+    pub code: Vec<u8>,
+    pub calldata: Vec<u8>,
+    pub value: u64,
+    pub gas_limit: u64,
+}
+
+impl Transaction {
+    pub fn new(sender: [u8; 32], code: Vec<u8>, calldata: Vec<u8>) -> Self {
+        Self {
+            sender,
+            code,
+            calldata,
+            value: 0,
+            gas_limit: DEFAULT_GAS_LIMIT,
+        }
+    }
+
+    pub fn with_value(mut self, value: u64) -> Self {
+        self.value = value;
+        self
+    }
+
+    pub fn with_gas_limit(mut self, gas_limit: u64) -> Self {
+        self.gas_limit = gas_limit;
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReceiptStatus {
+    Success,
+    /// Execution failed; storage changes were rolled back.
+    Revert,
+}
+
+/// Outcome of one transaction. Logs and return data are only kept when it
+/// succeeded.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub status: ReceiptStatus,
+    /// Gas used by this and all earlier transactions of the same block.
+    pub cumulative_gas_used: u64,
+    pub gas_used: u64,
+    pub logs: Vec<Log>,
+    pub return_data: Vec<u8>,
+    pub contract_address: Option<[u8; 32]>,
+    /// Why the transaction reverted. Not part of the receipt commitment.
+    pub error: Option<String>,
+}
+
+impl Receipt {
+    pub fn is_success(&self) -> bool {
+        self.status == ReceiptStatus::Success
+    }
+
+    pub fn encode_canonical(&self, out: &mut Vec<u8>) {
+        out.push(match self.status {
+            ReceiptStatus::Success => 1,
+            ReceiptStatus::Revert => 0,
+        });
+        out.extend_from_slice(&self.cumulative_gas_used.to_be_bytes());
+        out.extend_from_slice(&self.gas_used.to_be_bytes());
+        out.extend_from_slice(&(self.logs.len() as u64).to_be_bytes());
+        for log in &self.logs {
+            log.encode_canonical(out);
+        }
+        encode_bytes(out, &self.return_data);
+        match &self.contract_address {
+            Some(address) => {
+                out.push(1);
+                out.extend_from_slice(address);
+            }
+            None => out.push(0),
+        }
+    }
+}
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// Binary Merkle root over the receipts of a block, in order. Leaves and
+/// inner nodes are hashed with distinct prefixes, and an odd node is carried
+/// up unchanged rather than paired with itself. An empty block has the zero
+/// root.
+pub fn receipts_root(receipts: &[Receipt]) -> [u8; 32] {
+    let mut level: Vec<[u8; 32]> = receipts.iter()
+        .map(|receipt| {
+            let mut leaf = vec![LEAF_PREFIX];
+            receipt.encode_canonical(&mut leaf);
+            hash32(&leaf)
+        })
+        .collect();
+
+    if level.is_empty() {
+        return [0; 32];
+    }
+
+    while level.len() > 1 {
+        level = level.chunks(2)
+            .map(|pair| match pair {
+                [left, right] => {
+                    let mut node = vec![NODE_PREFIX];
+                    node.extend_from_slice(left);
+                    node.extend_from_slice(right);
+                    hash32(&node)
+                }
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
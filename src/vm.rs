@@ -1,9 +1,17 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use serde::{Deserialize, Serialize, Serializer};
 use thiserror::Error;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, MutexGuard, RwLock};
 use std::sync::Arc;
 use blake2::{Blake2b512, Digest};
-use rayon::prelude::*;
+use bls12_381::Scalar;
+use ff::PrimeField;
+use crate::circuit::poseidon;
+use crate::loader::{self, LoadedSegment, LoaderError, Program};
+use crate::memory::{AccessPermissions, MemoryConfig, MemoryError, MemoryManager, Permission, SegmentType};
+use crate::syscall::{self, HostIo, Syscall, SyscallHandler, SyscallRecord};
+use crate::tracer::{self, JsonTracer, TraceSummary};
+use crate::transaction::{Receipt, ReceiptStatus, Transaction};
 
 #[derive(Error, Debug)]
 pub enum VMError {
@@ -25,9 +33,59 @@ pub enum VMError {
     ContractCreationError(String),
     #[error("Invalid state transition: {0}")]
     InvalidStateTransition(String),
+    #[error("Program load error: {0}")]
+    LoadError(#[from] LoaderError),
+    #[error("Snapshot error: {0}")]
+    SnapshotError(String),
+    #[error("State modification in static context: {0}")]
+    StaticCallViolation(String),
+    #[error("Call depth limit exceeded")]
+    CallDepthExceeded,
+    #[error("Type mismatch: expected {expected}, found {found}")]
+    TypeMismatch { expected: &'static str, found: &'static str },
+    #[error("{source} ({context})")]
+    Fault { context: ErrorContext, source: Box<VMError> },
 }
 
-#[derive(Clone, Debug, PartialEq)]
+impl From<MemoryError> for VMError {
+    fn from(error: MemoryError) -> Self {
+        VMError::MemoryError(error.to_string())
+    }
+}
+
+/// Where a failing instruction was executed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub pc: usize,
+    pub opcode: u8,
+    pub depth: usize,
+    pub address: [u8; 32],
+}
+
+impl std::fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pc {:#x}, opcode {:#04x}, depth {}", self.pc, self.opcode, self.depth)
+    }
+}
+
+impl VMError {
+    /// The underlying error, without the location it was raised at.
+    pub fn kind(&self) -> &VMError {
+        match self {
+            VMError::Fault { source, .. } => source.kind(),
+            other => other,
+        }
+    }
+
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            VMError::Fault { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Value {
     Int(i64),
     Bool(bool),
@@ -36,13 +94,111 @@ pub enum Value {
     Contract(ContractData),
 }
 
-#[derive(Clone, Debug, PartialEq)]
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Bool(_) => "bool",
+            Value::Bytes(_) => "bytes",
+            Value::Address(_) => "address",
+            Value::Contract(_) => "contract",
+        }
+    }
+
+    /// Appends the canonical encoding: a type tag, then fixed-width
+    /// big-endian fields, with every variable-length part length-prefixed.
+    /// Distinct values never share an encoding.
+    pub fn encode_canonical(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Int(i) => {
+                out.push(TAG_INT);
+                out.extend_from_slice(&i.to_be_bytes());
+            }
+            Value::Bool(b) => {
+                out.push(TAG_BOOL);
+                out.push(*b as u8);
+            }
+            Value::Bytes(b) => {
+                out.push(TAG_BYTES);
+                encode_bytes(out, b);
+            }
+            Value::Address(a) => {
+                out.push(TAG_ADDRESS);
+                out.extend_from_slice(a);
+            }
+            Value::Contract(c) => {
+                out.push(TAG_CONTRACT);
+                encode_bytes(out, &c.code);
+                out.extend_from_slice(&c.balance.to_be_bytes());
+                encode_storage(out, &c.storage);
+            }
+        }
+    }
+}
+
+const TAG_INT: u8 = 0x01;
+const TAG_BOOL: u8 = 0x02;
+const TAG_BYTES: u8 = 0x03;
+const TAG_ADDRESS: u8 = 0x04;
+const TAG_CONTRACT: u8 = 0x05;
+
+/// Domain separator for state roots, bumped whenever the encoding changes.
+const STATE_ROOT_DOMAIN: &[u8] = b"zkvm/state-root/v1";
+
+pub(crate) fn encode_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn encode_storage(out: &mut Vec<u8>, storage: &HashMap<[u8; 32], Value>) {
+    out.extend_from_slice(&(storage.len() as u64).to_be_bytes());
+    for (key, value) in storage.iter().collect::<BTreeMap<_, _>>() {
+        out.extend_from_slice(key);
+        value.encode_canonical(out);
+    }
+}
+
+pub(crate) fn hash32(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Blake2b512::new();
+    hasher.update(data);
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&hasher.finalize()[..32]);
+    hash
+}
+
+/// Commitment to a storage map, independent of insertion order.
+pub fn storage_root(storage: &HashMap<[u8; 32], Value>) -> [u8; 32] {
+    let mut encoded = Vec::new();
+    encode_storage(&mut encoded, storage);
+    hash32(&encoded)
+}
+
+/// Address of the contract `creator` deploys as its `nonce`-th creation,
+/// so deploying the same code twice yields two accounts.
+pub fn contract_address(creator: &[u8; 32], nonce: u64) -> [u8; 32] {
+    let mut preimage = creator.to_vec();
+    preimage.extend_from_slice(&nonce.to_be_bytes());
+    hash32(&preimage)
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ContractData {
     pub code: Vec<u8>,
+    #[serde(serialize_with = "serialize_sorted")]
     pub storage: HashMap<[u8; 32], Value>,
     pub balance: u64,
 }
 
+impl ContractData {
+    pub fn code_hash(&self) -> [u8; 32] {
+        hash32(&self.code)
+    }
+
+    pub fn storage_root(&self) -> [u8; 32] {
+        storage_root(&self.storage)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct GasConfig {
     pub base: u64,
@@ -69,6 +225,14 @@ impl Default for GasConfig {
         op_cost.insert(0x0D, 5);   // RETURN
         op_cost.insert(0x0E, 50);  // SHA3
         op_cost.insert(0x0F, 20);  // BALANCE
+        op_cost.insert(0x10, 10);  // SYSCALL
+        op_cost.insert(0x11, 100); // SSTORE
+        op_cost.insert(0x12, 50);  // SLOAD
+        op_cost.insert(0x13, 30);  // LOG
+        op_cost.insert(0x14, 3);   // CALLDATA
+        op_cost.insert(0x15, 3);   // CALLER
+        op_cost.insert(0x16, 40);  // STATICCALL
+        op_cost.insert(0x17, 3);   // ISZERO
 
         Self {
             base: 2,
@@ -79,19 +243,212 @@ impl Default for GasConfig {
     }
 }
 
+/// Gas cost of every opcode, indexed directly by the opcode byte.
+#[derive(Clone, Debug)]
+pub struct GasTable([u64; 256]);
+
+impl GasTable {
+    pub fn new(config: &GasConfig) -> Self {
+        let mut table = [config.base; 256];
+        for (&opcode, &cost) in &config.op_cost {
+            table[opcode as usize] = cost;
+        }
+        Self(table)
+    }
+
+    #[inline]
+    pub fn cost(&self, opcode: u8) -> u64 {
+        self.0[opcode as usize]
+    }
+}
+
+impl Default for GasTable {
+    fn default() -> Self {
+        Self::new(&GasConfig::default())
+    }
+}
+
+/// A decoded instruction. Programs are decoded once, with one entry per byte
+/// offset, so any pc reached by CREATE, a jump or a restored snapshot lands
+/// on a valid entry without re-reading the bytecode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub opcode: u8,
+    pub immediate: Option<u8>,
+    pub gas: u64,
+    /// Cleared for offsets outside executable segments, which fault on fetch.
+    pub executable: bool,
+}
+
+pub fn decode(program: &[u8], gas_table: &GasTable) -> Vec<Instruction> {
+    program.iter().enumerate().map(|(pc, &opcode)| Instruction {
+        opcode,
+        immediate: if opcode == 0x01 { program.get(pc + 1).copied() } else { None },
+        gas: gas_table.cost(opcode),
+        executable: true,
+    }).collect()
+}
+
+const MEMORY_PAGE_CELLS: usize = 4096;
+
+/// Where raw bytecode is mapped, directly above the data and heap segments
+/// of its address space so that STORE can never reach it. The pc of a raw
+/// program is an offset from this base; ELF programs use virtual addresses.
+pub const RAW_CODE_BASE: usize = syscall::DEFAULT_HEAP_START + syscall::MAX_HEAP_SIZE;
+
+/// Image id of raw bytecode, as reported by `VM::image_id` once loaded.
+pub fn raw_image_id(program: &[u8]) -> [u8; 32] {
+    let code = LoadedSegment {
+        vaddr: 0,
+        size: program.len(),
+        data: program.to_vec(),
+        segment_type: SegmentType::Code,
+        permissions: AccessPermissions::READ_EXECUTE,
+    };
+    loader::compute_image_id(0, &[code])
+}
+
+/// Word-addressed VM memory. Cells live in contiguous fixed-size pages that
+/// are allocated on first write, so sparse layouts stay cheap while lookups
+/// are two array indexes.
+#[derive(Clone, Debug, Default)]
+pub struct Memory {
+    pages: Vec<Option<Box<[Option<Value>]>>>,
+    len: usize,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn get(&self, address: usize) -> Option<&Value> {
+        self.pages.get(address / MEMORY_PAGE_CELLS)?
+            .as_ref()?[address % MEMORY_PAGE_CELLS]
+            .as_ref()
+    }
+
+    pub fn insert(&mut self, address: usize, value: Value) {
+        let page_index = address / MEMORY_PAGE_CELLS;
+        if page_index >= self.pages.len() {
+            self.pages.resize_with(page_index + 1, || None);
+        }
+
+        let page = self.pages[page_index]
+            .get_or_insert_with(|| vec![None; MEMORY_PAGE_CELLS].into_boxed_slice());
+        let cell = &mut page[address % MEMORY_PAGE_CELLS];
+        if cell.is_none() {
+            self.len += 1;
+        }
+        *cell = Some(value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Occupied cells in ascending address order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Value)> {
+        self.pages.iter().enumerate()
+            .filter_map(|(i, page)| page.as_ref().map(|page| (i, page)))
+            .flat_map(|(i, page)| {
+                page.iter().enumerate().filter_map(move |(j, cell)| {
+                    cell.as_ref().map(|value| (i * MEMORY_PAGE_CELLS + j, value))
+                })
+            })
+    }
+
+    pub fn to_map(&self) -> HashMap<usize, Value> {
+        self.iter().map(|(address, value)| (address, value.clone())).collect()
+    }
+}
+
+/// Jumps may land on any offset; landing outside the code halts like running
+/// off its end.
+fn jump_target(target: i64) -> Result<usize, VMError> {
+    usize::try_from(target)
+        .map_err(|_| VMError::ExecutionError(format!("Invalid jump target: {}", target)))
+}
+
+fn type_mismatch(expected: &'static str, found: &Value) -> VMError {
+    VMError::TypeMismatch {
+        expected,
+        found: found.type_name(),
+    }
+}
+
+pub const DEFAULT_GAS_LIMIT: u64 = 1_000_000;
+
+/// Deepest a CALL or STATICCALL may nest, as in the EVM.
+pub const MAX_CALL_DEPTH: usize = 1024;
+
+/// Native stack a callee needs to start running; with less left, a new
+/// segment of `CALL_STACK_GROWTH` bytes is allocated for it.
+const CALL_STACK_RED_ZONE: usize = 256 * 1024;
+const CALL_STACK_GROWTH: usize = 4 * 1024 * 1024;
+
+/// Transaction environment a program runs in.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Env {
+    pub caller: [u8; 32],
+    pub address: [u8; 32],
+    pub value: u64,
+    pub gas_limit: u64,
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Self {
+            caller: [0; 32],
+            address: [0; 32],
+            value: 0,
+            gas_limit: DEFAULT_GAS_LIMIT,
+        }
+    }
+}
+
 pub struct ExecutionContext {
+    env: Env,
+    calldata: Vec<u8>,
     stack: Vec<Value>,
-    memory: HashMap<usize, Value>,
+    memory: Memory,
     storage: HashMap<[u8; 32], Value>,
+    /// Deployed contracts by address.
+    accounts: HashMap<[u8; 32], ContractData>,
     program_counter: usize,
     gas_remaining: u64,
     gas_config: GasConfig,
     call_stack: VecDeque<CallFrame>,
+    /// Frames entered before this execution: zero at the top level, one
+    /// more in each callee.
+    depth: usize,
     state_root: [u8; 32],
     logs: Vec<Log>,
+    stack_top: usize,
+    heap_start: usize,
+    heap_break: usize,
+    journal: Vec<u8>,
+    syscalls: Vec<SyscallRecord>,
+    /// Bytes of public and private input read so far.
+    public_input_read: usize,
+    private_input_read: usize,
+    exit_code: Option<i64>,
+    halted: bool,
+    cycles: u64,
+    /// Top-level read-only mode, see `VM::call_static`.
+    static_mode: bool,
+    /// Set by a top-level RETURN.
+    return_data: Vec<u8>,
+    /// First contract created during this execution.
+    created_address: Option<[u8; 32]>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct CallFrame {
     pub caller: [u8; 32],
     pub address: [u8; 32],
@@ -99,28 +456,183 @@ pub struct CallFrame {
     pub gas_limit: u64,
     pub code: Vec<u8>,
     pub return_data: Vec<u8>,
+    /// Set for frames entered through STATICCALL or nested in one.
+    pub is_static: bool,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Log {
     pub address: [u8; 32],
     pub topics: Vec<[u8; 32]>,
     pub data: Vec<u8>,
 }
 
+impl Log {
+    pub fn encode_canonical(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.address);
+        out.extend_from_slice(&(self.topics.len() as u64).to_be_bytes());
+        for topic in &self.topics {
+            out.extend_from_slice(topic);
+        }
+        encode_bytes(out, &self.data);
+    }
+}
+
 impl ExecutionContext {
     pub fn new(gas_limit: u64) -> Self {
         Self {
+            env: Env {
+                gas_limit,
+                ..Env::default()
+            },
+            calldata: Vec::new(),
             stack: Vec::with_capacity(1024),
-            memory: HashMap::new(),
+            memory: Memory::new(),
             storage: HashMap::new(),
+            accounts: HashMap::new(),
             program_counter: 0,
             gas_remaining: gas_limit,
             gas_config: GasConfig::default(),
             call_stack: VecDeque::new(),
+            depth: 0,
             state_root: [0; 32],
             logs: Vec::new(),
+            stack_top: 0,
+            heap_start: syscall::DEFAULT_HEAP_START,
+            heap_break: syscall::DEFAULT_HEAP_START,
+            journal: Vec::new(),
+            syscalls: Vec::new(),
+            public_input_read: 0,
+            private_input_read: 0,
+            exit_code: None,
+            halted: false,
+            cycles: 0,
+            static_mode: false,
+            return_data: Vec::new(),
+            created_address: None,
+        }
+    }
+
+    fn pop(&mut self) -> Result<Value, VMError> {
+        self.stack.pop().ok_or(VMError::StackUnderflow)
+    }
+
+    fn pop_int(&mut self) -> Result<i64, VMError> {
+        match self.pop()? {
+            Value::Int(v) => Ok(v),
+            other => Err(type_mismatch("int", &other)),
+        }
+    }
+
+    /// Pops an address, also accepted as a 32-byte word such as calldata.
+    fn pop_address(&mut self) -> Result<[u8; 32], VMError> {
+        match self.pop()? {
+            Value::Address(addr) => Ok(addr),
+            Value::Bytes(b) if b.len() == 32 => {
+                let mut addr = [0u8; 32];
+                addr.copy_from_slice(&b);
+                Ok(addr)
+            }
+            other => Err(type_mismatch("address", &other)),
+        }
+    }
+
+    fn pop_bytes(&mut self) -> Result<Vec<u8>, VMError> {
+        match self.pop()? {
+            Value::Bytes(b) => Ok(b),
+            other => Err(type_mismatch("bytes", &other)),
+        }
+    }
+
+    /// Pops a storage key or log topic. Integers are widened big-endian.
+    fn pop_word(&mut self) -> Result<[u8; 32], VMError> {
+        match self.pop()? {
+            Value::Int(v) => {
+                let mut word = [0u8; 32];
+                word[24..].copy_from_slice(&v.to_be_bytes());
+                Ok(word)
+            }
+            Value::Address(a) => Ok(a),
+            Value::Bytes(b) if b.len() == 32 => {
+                let mut word = [0u8; 32];
+                word.copy_from_slice(&b);
+                Ok(word)
+            }
+            other => Err(type_mismatch("32-byte word", &other)),
+        }
+    }
+
+    fn error_context(&self, pc: usize, opcode: u8) -> ErrorContext {
+        ErrorContext {
+            pc,
+            opcode,
+            depth: self.call_depth(),
+            address: self.current_address(),
+        }
+    }
+
+    /// Frames entered so far, across the VMs callees run on.
+    fn call_depth(&self) -> usize {
+        self.depth + self.call_stack.len()
+    }
+
+    fn current_address(&self) -> [u8; 32] {
+        self.call_stack.back().map(|frame| frame.address).unwrap_or(self.env.address)
+    }
+
+    fn is_static(&self) -> bool {
+        self.static_mode || self.call_stack.back().is_some_and(|frame| frame.is_static)
+    }
+
+    fn require_mutable(&self, operation: &str) -> Result<(), VMError> {
+        if self.is_static() {
+            return Err(VMError::StaticCallViolation(operation.to_string()));
         }
+        Ok(())
+    }
+
+    /// Pushes a frame for CALL or STATICCALL. A static frame stays static
+    /// for everything nested in it; no more than `MAX_CALL_DEPTH` frames nest.
+    fn enter_call(
+        &mut self,
+        address: [u8; 32],
+        value: u64,
+        gas_limit: u64,
+        is_static: bool,
+    ) -> Result<(), VMError> {
+        if self.call_depth() >= MAX_CALL_DEPTH {
+            return Err(VMError::CallDepthExceeded);
+        }
+
+        // Forwarded gas must be available up front; the callee's usage is
+        // charged when it returns
+        if gas_limit > self.gas_remaining {
+            return Err(VMError::GasLimitExceeded);
+        }
+
+        let contract = match self.accounts.get(&address) {
+            Some(c) => c.clone(),
+            None => return Err(VMError::ExecutionError("Contract not found".to_string())),
+        };
+
+        self.call_stack.push_back(CallFrame {
+            caller: self.current_address(),
+            address,
+            value,
+            gas_limit,
+            code: contract.code,
+            return_data: Vec::new(),
+            is_static: is_static || self.is_static(),
+        });
+        Ok(())
+    }
+
+    fn set_heap_break(&mut self, new_break: usize) -> Result<(), VMError> {
+        if new_break < self.heap_start || new_break > self.heap_start + syscall::MAX_HEAP_SIZE {
+            return Err(VMError::MemoryError(format!("Invalid heap break: {:#x}", new_break)));
+        }
+        self.heap_break = new_break;
+        Ok(())
     }
 
     fn use_gas(&mut self, amount: u64) -> Result<(), VMError> {
@@ -131,241 +643,1061 @@ impl ExecutionContext {
         Ok(())
     }
 
+    /// Commits to the full state: every contract account held in memory
+    /// (address, balance, code hash, storage root), the executing account's
+    /// storage, all memory cells and the logs. Each section is counted and
+    /// every value canonically encoded, so sections cannot run into each other.
     fn compute_state_root(&mut self) {
-        let mut hasher = Blake2b512::new();
-        
-        // Hash storage
-        let mut storage_vec: Vec<_> = self.storage.iter().collect();
-        storage_vec.par_sort_by_key(|&(k, _)| k);
-        
-        for (key, value) in storage_vec {
-            hasher.update(key);
-            match value {
-                Value::Int(i) => hasher.update(&i.to_le_bytes()),
-                Value::Bool(b) => hasher.update(&[*b as u8]),
-                Value::Bytes(b) => hasher.update(b),
-                Value::Address(a) => hasher.update(a),
-                Value::Contract(c) => {
-                    hasher.update(&c.code);
-                    hasher.update(&c.balance.to_le_bytes());
-                }
-            }
+        let mut state = STATE_ROOT_DOMAIN.to_vec();
+
+        let accounts: BTreeMap<&[u8; 32], &ContractData> = self.accounts.iter().collect();
+        state.extend_from_slice(&(accounts.len() as u64).to_be_bytes());
+        for (address, contract) in accounts {
+            state.extend_from_slice(address);
+            state.extend_from_slice(&contract.balance.to_be_bytes());
+            state.extend_from_slice(&contract.code_hash());
+            state.extend_from_slice(&contract.storage_root());
+        }
+
+        state.extend_from_slice(&storage_root(&self.storage));
+
+        state.extend_from_slice(&(self.memory.len() as u64).to_be_bytes());
+        for (address, value) in self.memory.iter() {
+            state.extend_from_slice(&(address as u64).to_be_bytes());
+            value.encode_canonical(&mut state);
         }
 
-        // Hash logs
+        state.extend_from_slice(&(self.logs.len() as u64).to_be_bytes());
         for log in &self.logs {
-            hasher.update(&log.address);
-            for topic in &log.topics {
-                hasher.update(topic);
-            }
-            hasher.update(&log.data);
+            log.encode_canonical(&mut state);
+        }
+
+        self.state_root = hash32(&state);
+    }
+}
+
+/// Read-only view of the interpreter around a single step.
+pub struct StepInfo<'a> {
+    pub pc: usize,
+    /// Program counter after the instruction; equal to `pc` until it has run.
+    pub next_pc: usize,
+    /// Whether the instruction stopped the machine, e.g. STOP or an exit.
+    pub halted: bool,
+    pub opcode: u8,
+    pub gas_cost: u64,
+    pub gas_remaining: u64,
+    pub depth: usize,
+    pub stack: &'a [Value],
+    pub memory: &'a Memory,
+}
+
+impl<'a> StepInfo<'a> {
+    fn new(context: &'a ExecutionContext, pc: usize, instruction: Instruction) -> Self {
+        Self {
+            pc,
+            next_pc: context.program_counter,
+            halted: context.halted,
+            opcode: instruction.opcode,
+            gas_cost: instruction.gas,
+            gas_remaining: context.gas_remaining,
+            depth: context.call_stack.len(),
+            stack: &context.stack,
+            memory: &context.memory,
         }
+    }
+}
+
+/// Hooks into the interpreter loop. Every method has an empty default, so
+/// tracers, profilers and coverage tools implement only what they need.
+pub trait Inspector: Send {
+    /// Called before an instruction is charged and executed.
+    fn step(&mut self, _step: &StepInfo<'_>) {}
+
+    /// Called after an instruction completed successfully.
+    fn step_end(&mut self, _step: &StepInfo<'_>) {}
+
+    fn call(&mut self, _frame: &CallFrame, _depth: usize) {}
+
+    fn call_end(&mut self, _frame: &CallFrame, _depth: usize) {}
+
+    fn storage_write(&mut self, _address: &[u8; 32], _key: &[u8; 32], _value: &Value) {}
+
+    fn log(&mut self, _log: &Log) {}
+
+    /// Called when an instruction fails; execution stops with `error`.
+    fn error(&mut self, _pc: usize, _opcode: u8, _error: &VMError) {}
+}
+
+/// Lets callers keep a handle on an inspector's collected data after
+/// attaching it to a VM.
+impl<I: Inspector> Inspector for Arc<Mutex<I>> {
+    fn step(&mut self, step: &StepInfo<'_>) {
+        self.lock().step(step)
+    }
+
+    fn step_end(&mut self, step: &StepInfo<'_>) {
+        self.lock().step_end(step)
+    }
+
+    fn call(&mut self, frame: &CallFrame, depth: usize) {
+        self.lock().call(frame, depth)
+    }
+
+    fn call_end(&mut self, frame: &CallFrame, depth: usize) {
+        self.lock().call_end(frame, depth)
+    }
+
+    fn storage_write(&mut self, address: &[u8; 32], key: &[u8; 32], value: &Value) {
+        self.lock().storage_write(address, key, value)
+    }
 
-        let result = hasher.finalize();
-        self.state_root.copy_from_slice(&result[..32]);
+    fn log(&mut self, log: &Log) {
+        self.lock().log(log)
+    }
+
+    fn error(&mut self, pc: usize, opcode: u8, error: &VMError) {
+        self.lock().error(pc, opcode, error)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GasEstimate {
+    /// Smallest gas limit with which execution succeeds.
+    pub gas_limit: u64,
+    /// Gas actually consumed by a successful run.
+    pub gas_used: u64,
+    /// Gas that must be available on top of `gas_used` for forwarding calls.
+    pub forwarded_gas: u64,
+    /// Number of full executions performed.
+    pub iterations: usize,
+}
+
+#[derive(Error, Debug)]
+#[error("Execution fails with gas limit {gas_limit}: {reason}")]
+pub struct GasEstimateError {
+    pub gas_limit: u64,
+    pub reason: VMError,
+}
+
+/// Tracks the most gas that had to be available at any CALL: gas used up to
+/// and including the call, plus the amount it forwards.
+struct ForwardedGasTracker {
+    gas_limit: u64,
+    required: u64,
+}
+
+impl ForwardedGasTracker {
+    fn new(gas_limit: u64) -> Self {
+        Self { gas_limit, required: 0 }
+    }
+}
+
+impl Inspector for ForwardedGasTracker {
+    fn step(&mut self, step: &StepInfo<'_>) {
+        // Operands, top first: CALL takes address, value, gas limit and
+        // STATICCALL takes address, gas limit
+        let gas_operand = match step.opcode {
+            0x0C => 3,
+            0x16 => 2,
+            _ => return,
+        };
+        if step.stack.len() < gas_operand {
+            return;
+        }
+        if let Value::Int(forwarded) = step.stack[step.stack.len() - gas_operand] {
+            let used = self.gas_limit - step.gas_remaining + step.gas_cost;
+            self.required = self.required.max(used + forwarded.max(0) as u64);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExecutionStatus {
+    Halted,
+    Paused,
+}
+
+fn serialize_sorted<S, K, V>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    K: Ord + Serialize,
+    V: Serialize,
+{
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
+
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"ZKVS";
+pub const SNAPSHOT_VERSION: u16 = 3;
+
+/// Complete resumable state of a paused execution. Maps are stored as sorted
+/// vectors so the encoding is byte-for-byte deterministic.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExecutionSnapshot {
+    pub image_id: [u8; 32],
+    pub env: Env,
+    pub calldata: Vec<u8>,
+    pub program_counter: usize,
+    pub stack: Vec<Value>,
+    pub memory: Vec<(usize, Value)>,
+    pub storage: Vec<([u8; 32], Value)>,
+    pub accounts: Vec<([u8; 32], ContractData)>,
+    pub call_stack: Vec<CallFrame>,
+    pub logs: Vec<Log>,
+    pub gas_remaining: u64,
+    pub stack_top: usize,
+    pub heap_start: usize,
+    pub heap_break: usize,
+    pub journal: Vec<u8>,
+    pub syscalls: Vec<SyscallRecord>,
+    pub public_input_read: usize,
+    pub private_input_read: usize,
+    pub exit_code: Option<i64>,
+    pub halted: bool,
+    pub cycles: u64,
+    pub static_mode: bool,
+    pub return_data: Vec<u8>,
+    pub created_address: Option<[u8; 32]>,
+}
+
+impl ExecutionSnapshot {
+    /// Encodes as `SNAPSHOT_MAGIC || version (u16 LE) || bincode(snapshot)`.
+    pub fn encode(&self) -> Result<Vec<u8>, VMError> {
+        let body = bincode::serialize(self)
+            .map_err(|e| VMError::SnapshotError(e.to_string()))?;
+
+        let mut bytes = Vec::with_capacity(SNAPSHOT_MAGIC.len() + 2 + body.len());
+        bytes.extend_from_slice(&SNAPSHOT_MAGIC);
+        bytes.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&body);
+        Ok(bytes)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, VMError> {
+        if bytes.len() < SNAPSHOT_MAGIC.len() + 2 || bytes[..4] != SNAPSHOT_MAGIC {
+            return Err(VMError::SnapshotError("Invalid snapshot header".to_string()));
+        }
+
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != SNAPSHOT_VERSION {
+            return Err(VMError::SnapshotError(format!("Unsupported snapshot version: {}", version)));
+        }
+
+        bincode::deserialize(&bytes[6..])
+            .map_err(|e| VMError::SnapshotError(e.to_string()))
+    }
+}
+
+impl ExecutionContext {
+    fn to_snapshot(&self, image_id: [u8; 32]) -> ExecutionSnapshot {
+        let memory = self.memory.iter().map(|(k, v)| (k, v.clone())).collect();
+        let mut storage: Vec<_> = self.storage.iter().map(|(k, v)| (*k, v.clone())).collect();
+        storage.sort_by_key(|(k, _)| *k);
+        let mut accounts: Vec<_> = self.accounts.iter().map(|(k, v)| (*k, v.clone())).collect();
+        accounts.sort_by_key(|(k, _)| *k);
+
+        ExecutionSnapshot {
+            image_id,
+            env: self.env.clone(),
+            calldata: self.calldata.clone(),
+            program_counter: self.program_counter,
+            stack: self.stack.clone(),
+            memory,
+            storage,
+            accounts,
+            call_stack: self.call_stack.iter().cloned().collect(),
+            logs: self.logs.clone(),
+            gas_remaining: self.gas_remaining,
+            stack_top: self.stack_top,
+            heap_start: self.heap_start,
+            heap_break: self.heap_break,
+            journal: self.journal.clone(),
+            syscalls: self.syscalls.clone(),
+            public_input_read: self.public_input_read,
+            private_input_read: self.private_input_read,
+            exit_code: self.exit_code,
+            halted: self.halted,
+            static_mode: self.static_mode,
+            return_data: self.return_data.clone(),
+            created_address: self.created_address,
+            cycles: self.cycles,
+        }
+    }
+
+    fn from_snapshot(snapshot: ExecutionSnapshot) -> Self {
+        let mut context = Self::new(snapshot.gas_remaining);
+        context.env = snapshot.env;
+        context.calldata = snapshot.calldata;
+        context.program_counter = snapshot.program_counter;
+        context.stack = snapshot.stack;
+        for (address, value) in snapshot.memory {
+            context.memory.insert(address, value);
+        }
+        context.storage = snapshot.storage.into_iter().collect();
+        context.accounts = snapshot.accounts.into_iter().collect();
+        context.call_stack = snapshot.call_stack.into_iter().collect();
+        context.logs = snapshot.logs;
+        context.stack_top = snapshot.stack_top;
+        context.heap_start = snapshot.heap_start;
+        context.heap_break = snapshot.heap_break;
+        context.journal = snapshot.journal;
+        context.syscalls = snapshot.syscalls;
+        context.public_input_read = snapshot.public_input_read;
+        context.private_input_read = snapshot.private_input_read;
+        context.exit_code = snapshot.exit_code;
+        context.halted = snapshot.halted;
+        context.static_mode = snapshot.static_mode;
+        context.return_data = snapshot.return_data;
+        context.created_address = snapshot.created_address;
+        context.cycles = snapshot.cycles;
+        context
     }
 }
 
 pub struct VM {
     context: Arc<RwLock<ExecutionContext>>,
     program: Vec<u8>,
+    code: Vec<Instruction>,
+    /// Virtual address of pc 0.
+    code_base: usize,
+    address_space: MemoryManager,
+    image_id: [u8; 32],
+    syscall_handler: Mutex<Box<dyn SyscallHandler>>,
+    inspector: Option<Mutex<Box<dyn Inspector>>>,
 }
 
 impl VM {
+    /// Creates a VM for raw bytecode. Its address space holds a data segment
+    /// below the default heap start, the heap, and the read-only code above
+    /// both at `RAW_CODE_BASE`.
     pub fn new(program: Vec<u8>) -> Self {
-        Self {
-            context: Arc::new(RwLock::new(ExecutionContext::new(1_000_000))),
-            program,
+        let mut vm = Self {
+            context: Arc::new(RwLock::new(ExecutionContext::new(DEFAULT_GAS_LIMIT))),
+            program: Vec::new(),
+            code: Vec::new(),
+            code_base: RAW_CODE_BASE,
+            address_space: MemoryManager::new(MemoryConfig::default()),
+            image_id: [0; 32],
+            syscall_handler: Mutex::new(Box::new(HostIo::default())),
+            inspector: None,
+        };
+        vm.load_raw(program);
+        vm
+    }
+
+    /// Replaces the program and address space with raw bytecode.
+    fn load_raw(&mut self, program: Vec<u8>) {
+        let image_id = raw_image_id(&program);
+
+        // The layout is fixed and non-overlapping, so mapping cannot fail
+        let address_space = MemoryManager::new(MemoryConfig::default());
+        let layout = [
+            (0, syscall::DEFAULT_HEAP_START, SegmentType::Data),
+            (syscall::DEFAULT_HEAP_START, syscall::MAX_HEAP_SIZE, SegmentType::Heap),
+        ];
+        for (base, size, segment_type) in layout {
+            address_space.map(base, size, &[], segment_type, AccessPermissions::READ_WRITE)
+                .expect("raw data layout");
         }
+        let code = if program.is_empty() {
+            Vec::new()
+        } else {
+            address_space.map(RAW_CODE_BASE, program.len(), &program, SegmentType::Code, AccessPermissions::READ_EXECUTE)
+                .and_then(|_| address_space.fetch(RAW_CODE_BASE, program.len()))
+                .expect("raw code layout")
+        };
+
+        self.code = decode(&code, &GasTable::default());
+        self.code_base = RAW_CODE_BASE;
+        self.address_space = address_space;
+        self.program = program;
+        self.image_id = image_id;
     }
 
-    pub fn execute(&self) -> Result<(), VMError> {
+    /// Runs one transaction against the storage and contracts left by the
+    /// previous ones. Stack, memory, gas and logs start fresh; a failed
+    /// transaction leaves storage and contracts as they were and is reported
+    /// in its receipt rather than as an error.
+    pub fn execute_transaction(&mut self, tx: &Transaction) -> Receipt {
+        let (storage, accounts) = {
+            let mut context = self.context.write();
+            (std::mem::take(&mut context.storage), std::mem::take(&mut context.accounts))
+        };
+        self.load_raw(tx.code.clone());
+        {
+            let mut context = self.context.write();
+            *context = ExecutionContext::new(tx.gas_limit);
+            context.env = Env {
+                caller: tx.sender,
+                value: tx.value,
+                gas_limit: tx.gas_limit,
+                ..Env::default()
+            };
+            context.calldata = tx.calldata.clone();
+            context.storage = storage.clone();
+            context.accounts = accounts.clone();
+        }
+
+        let result = self.execute();
+
         let mut context = self.context.write();
-        
-        while context.program_counter < self.program.len() {
-            let opcode = self.program[context.program_counter];
-            
-            // Use gas for operation
-            let gas_cost = context.gas_config.op_cost.get(&opcode)
-                .copied()
-                .unwrap_or(context.gas_config.base);
-            context.use_gas(gas_cost)?;
-
-            match opcode {
-                // Existing opcodes
-                0x01 => { // PUSH
-                    let value = self.program[context.program_counter + 1];
-                    if context.stack.len() >= 1024 {
-                        return Err(VMError::StackOverflow);
-                    }
-                    context.stack.push(Value::Int(value as i64));
-                    context.program_counter += 2;
+        let gas_used = context.env.gas_limit - context.gas_remaining;
+        match result {
+            Ok(()) => Receipt {
+                status: ReceiptStatus::Success,
+                cumulative_gas_used: gas_used,
+                gas_used,
+                logs: context.logs.clone(),
+                return_data: context.return_data.clone(),
+                contract_address: context.created_address,
+                error: None,
+            },
+            Err(error) => {
+                context.storage = storage;
+                context.accounts = accounts;
+                Receipt {
+                    status: ReceiptStatus::Revert,
+                    cumulative_gas_used: gas_used,
+                    gas_used,
+                    logs: Vec::new(),
+                    return_data: Vec::new(),
+                    contract_address: None,
+                    error: Some(error.to_string()),
                 }
-                0x02 => { // ADD
-                    let b = match context.stack.pop() {
-                        Some(Value::Int(v)) => v,
-                        _ => return Err(VMError::StackUnderflow),
-                    };
-                    let a = match context.stack.pop() {
-                        Some(Value::Int(v)) => v,
-                        _ => return Err(VMError::StackUnderflow),
+            }
+        }
+    }
+
+    /// Executes the transactions of one block in order. Cumulative gas is
+    /// counted from the first of them.
+    pub fn execute_transactions(&mut self, txs: &[Transaction]) -> Vec<Receipt> {
+        let mut cumulative_gas_used = 0;
+        txs.iter().map(|tx| {
+            let mut receipt = self.execute_transaction(tx);
+            cumulative_gas_used += receipt.gas_used;
+            receipt.cumulative_gas_used = cumulative_gas_used;
+            receipt
+        }).collect()
+    }
+
+    pub fn with_syscall_handler<H: SyscallHandler + 'static>(mut self, handler: H) -> Self {
+        self.syscall_handler = Mutex::new(Box::new(handler));
+        self
+    }
+
+    /// Sets the transaction environment; the gas limit replaces the remaining gas.
+    pub fn with_env(self, env: Env) -> Self {
+        {
+            let mut context = self.context.write();
+            context.gas_remaining = env.gas_limit;
+            context.env = env;
+        }
+        self
+    }
+
+    pub fn with_calldata(self, calldata: Vec<u8>) -> Self {
+        self.context.write().calldata = calldata;
+        self
+    }
+
+    pub fn with_inspector<I: Inspector + 'static>(mut self, inspector: I) -> Self {
+        self.set_inspector(inspector);
+        self
+    }
+
+    pub fn set_inspector<I: Inspector + 'static>(&mut self, inspector: I) {
+        self.inspector = Some(Mutex::new(Box::new(inspector)));
+    }
+
+    pub fn take_inspector(&mut self) -> Option<Box<dyn Inspector>> {
+        self.inspector.take().map(Mutex::into_inner)
+    }
+
+    /// Creates a VM from a statically linked ELF32 executable. Data segments are
+    /// mapped into memory one byte per cell and execution starts at the entry point.
+    pub fn from_elf(elf: &[u8]) -> Result<Self, VMError> {
+        let program = loader::load_elf(elf)?;
+        Self::from_program(&program)
+    }
+
+    /// Maps every program segment with its permissions, plus a heap above the
+    /// stack. Code is decoded from what the executable segments hold; any
+    /// other offset faults when the pc reaches it.
+    pub fn from_program(program: &Program) -> Result<Self, VMError> {
+        let mut context = ExecutionContext::new(DEFAULT_GAS_LIMIT);
+        context.program_counter = program.entry;
+        context.stack_top = program.stack_top;
+        context.heap_start = program.stack_top;
+        context.heap_break = program.stack_top;
+
+        let address_space = MemoryManager::new(MemoryConfig::default());
+        for segment in &program.segments {
+            address_space.map(segment.vaddr, segment.size, &segment.data, segment.segment_type, segment.permissions)?;
+        }
+        address_space.map(program.stack_top, syscall::MAX_HEAP_SIZE, &[], SegmentType::Heap, AccessPermissions::READ_WRITE)?;
+
+        for segment in program.data_segments() {
+            for (i, byte) in address_space.read_at(segment.vaddr, segment.size)?.into_iter().enumerate() {
+                context.memory.insert(segment.vaddr + i, Value::Int(byte as i64));
+            }
+        }
+
+        let code_segments: Vec<&LoadedSegment> = program.segments.iter()
+            .filter(|s| s.permissions.can_execute())
+            .collect();
+        let mut image = vec![0u8; code_segments.iter().map(|s| s.end()).max().unwrap_or(0)];
+        for segment in &code_segments {
+            image[segment.vaddr..segment.end()].copy_from_slice(&address_space.fetch(segment.vaddr, segment.size)?);
+        }
+        let mut code = decode(&image, &GasTable::default());
+        for (pc, instruction) in code.iter_mut().enumerate() {
+            instruction.executable = code_segments.iter().any(|s| s.contains(pc));
+        }
+
+        Ok(Self {
+            context: Arc::new(RwLock::new(context)),
+            code,
+            code_base: 0,
+            address_space,
+            program: image,
+            image_id: program.image_id,
+            syscall_handler: Mutex::new(Box::new(HostIo::default())),
+            inspector: None,
+        })
+    }
+
+    /// Error for fetching at `pc` when it is not executable code.
+    fn fetch_fault(&self, pc: usize) -> VMError {
+        let address = self.code_base + pc;
+        match self.address_space.check_access(address, Permission::Execute) {
+            Err(error) => error.into(),
+            Ok(()) => MemoryError::PermissionDenied(address).into(),
+        }
+    }
+
+    /// Checks that `addr` grants `required` in the address space and, if it
+    /// falls in the heap, that it lies below the current break. The whole
+    /// heap is mapped up front; `brk` and `sbrk` move the break within it.
+    fn check_memory(&self, context: &ExecutionContext, addr: usize, required: Permission) -> Result<(), VMError> {
+        self.address_space.check_access(addr, required)?;
+        if (context.heap_break..context.heap_start + syscall::MAX_HEAP_SIZE).contains(&addr) {
+            return Err(MemoryError::PageFault(addr).into());
+        }
+        Ok(())
+    }
+
+    pub fn execute(&self) -> Result<(), VMError> {
+        self.run(usize::MAX).map(|_| ())
+    }
+
+    /// Runs to completion in read-only mode: SSTORE, LOG, CREATE and
+    /// value-bearing CALLs fail with `VMError::StaticCallViolation`. The mode
+    /// is cleared again when the run ends, whether or not it succeeded.
+    pub fn call_static(&self) -> Result<(), VMError> {
+        self.context.write().static_mode = true;
+        let result = self.execute();
+        self.context.write().static_mode = false;
+        result
+    }
+
+    /// Runs to completion while writing an EIP-3155 style JSON trace to
+    /// `writer`, one line per instruction followed by a summary line. Any
+    /// attached inspector is set aside for the duration of the run.
+    ///
+    /// The writer is handed back together with the execution result, so a
+    /// failing run still yields its trace; only write errors fail the call.
+    pub fn execute_with_trace<W: std::io::Write + Send + 'static>(
+        &mut self,
+        writer: W,
+    ) -> std::io::Result<(W, Result<(), VMError>)> {
+        let previous = self.inspector.take();
+        let tracer = Arc::new(Mutex::new(JsonTracer::new(writer)));
+        self.set_inspector(tracer.clone());
+        let result = self.execute();
+        self.inspector = previous;
+
+        let summary = TraceSummary {
+            state_root: tracer::to_hex(&self.get_state_root()),
+            output: tracer::to_hex(&self.get_journal()),
+            gas_used: format!("{:#x}", self.get_gas_used()),
+            pass: result.is_ok(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+
+        // The VM dropped its handle when the previous inspector was restored
+        let mut tracer = match Arc::try_unwrap(tracer) {
+            Ok(tracer) => tracer.into_inner(),
+            Err(_) => unreachable!("tracer handle still shared"),
+        };
+        tracer.write_summary(&summary);
+        Ok((tracer.finish()?, result))
+    }
+
+    /// Executes at most `max_steps` instructions. Returns `Paused` when the
+    /// program can still continue, e.g. to take a snapshot and resume later.
+    pub fn run(&self, max_steps: usize) -> Result<ExecutionStatus, VMError> {
+        // The lock is taken once per run; the loop itself works on a plain
+        // `&mut ExecutionContext` and the pre-decoded instruction stream.
+        let mut guard = self.context.write();
+        let context = &mut *guard;
+        let mut inspector: Option<MutexGuard<'_, Box<dyn Inspector>>> =
+            self.inspector.as_ref().map(|i| i.lock());
+
+        let mut steps = 0;
+        while steps < max_steps && !context.halted {
+            let pc = context.program_counter;
+            let instruction = match self.code.get(pc) {
+                Some(instruction) if instruction.executable => *instruction,
+                // Running off the end of the code halts
+                None if !self.address_space.is_mapped(self.code_base + pc) => break,
+                fetched => {
+                    let opcode = fetched.map_or(0, |i| i.opcode);
+                    let error = VMError::Fault {
+                        context: context.error_context(pc, opcode),
+                        source: Box::new(self.fetch_fault(pc)),
                     };
-                    context.stack.push(Value::Int(a + b));
-                    context.program_counter += 1;
+                    if let Some(inspector) = inspector.as_mut() {
+                        inspector.error(pc, opcode, &error);
+                    }
+                    return Err(error);
                 }
-                0x03 => { // MUL
-                    let b = match context.stack.pop() {
-                        Some(Value::Int(v)) => v,
-                        _ => return Err(VMError::StackUnderflow),
-                    };
-                    let a = match context.stack.pop() {
-                        Some(Value::Int(v)) => v,
-                        _ => return Err(VMError::StackUnderflow),
-                    };
-                    context.stack.push(Value::Int(a * b));
-                    context.program_counter += 1;
+            };
+
+            if let Some(inspector) = inspector.as_mut() {
+                inspector.step(&StepInfo::new(context, pc, instruction));
+            }
+
+            let result = context.use_gas(instruction.gas).and_then(|_| {
+                let hooks = inspector.as_mut().map(|i| &mut ***i as &mut dyn Inspector);
+                self.execute_instruction(context, instruction, hooks)
+            });
+            if let Err(error) = result {
+                let error = VMError::Fault {
+                    context: context.error_context(pc, instruction.opcode),
+                    source: Box::new(error),
+                };
+                if let Some(inspector) = inspector.as_mut() {
+                    inspector.error(pc, instruction.opcode, &error);
                 }
-                0x04 => { // STORE
-                    let addr = match context.stack.pop() {
-                        Some(Value::Int(v)) => v as usize,
-                        _ => return Err(VMError::StackUnderflow),
-                    };
-                    let value = context.stack.pop()
-                        .ok_or(VMError::StackUnderflow)?;
-                    context.memory.insert(addr, value);
-                    context.program_counter += 1;
+                return Err(error);
+            }
+
+            if let Some(inspector) = inspector.as_mut() {
+                inspector.step_end(&StepInfo::new(context, pc, instruction));
+            }
+
+            context.cycles += 1;
+            steps += 1;
+        }
+
+        let ran_off_end = context.program_counter >= self.code.len()
+            && !self.address_space.is_mapped(self.code_base + context.program_counter);
+        if context.halted || ran_off_end {
+            context.halted = true;
+            context.compute_state_root();
+            Ok(ExecutionStatus::Halted)
+        } else {
+            Ok(ExecutionStatus::Paused)
+        }
+    }
+
+    pub fn step(&self) -> Result<ExecutionStatus, VMError> {
+        self.run(1)
+    }
+
+    /// CALL and STATICCALL: runs the callee in a frame of its own and pushes
+    /// its return data. The frame is popped again whether or not the callee
+    /// succeeded, which also restores the caller's static mode.
+    fn call_contract(
+        &self,
+        context: &mut ExecutionContext,
+        address: [u8; 32],
+        value: u64,
+        gas_limit: u64,
+        is_static: bool,
+        mut inspector: Option<&mut (dyn Inspector + '_)>,
+    ) -> Result<(), VMError> {
+        context.enter_call(address, value, gas_limit, is_static)?;
+        let depth = context.call_depth();
+        if let (Some(inspector), Some(frame)) = (inspector.as_deref_mut(), context.call_stack.back()) {
+            inspector.call(frame, depth);
+        }
+
+        // Every callee runs on the native stack of its caller, so deep
+        // call chains grow it on demand
+        let result = stacker::maybe_grow(CALL_STACK_RED_ZONE, CALL_STACK_GROWTH, || Self::run_frame(context));
+        let mut frame = context.call_stack.pop_back().expect("frame pushed by enter_call");
+        let return_data = result?;
+        frame.return_data = return_data.clone();
+        if let Some(inspector) = inspector {
+            inspector.call_end(&frame, depth);
+        }
+
+        if context.stack.len() >= 1024 {
+            return Err(VMError::StackOverflow);
+        }
+        context.stack.push(Value::Bytes(return_data));
+        Ok(())
+    }
+
+    /// Runs the code of the innermost frame to completion on a VM of its
+    /// own, against the callee account's storage. Memory and the heap break
+    /// are lent to the callee and the gas it used is charged either way; storage, accounts
+    /// and logs are only taken over when it succeeds.
+    fn run_frame(context: &mut ExecutionContext) -> Result<Vec<u8>, VMError> {
+        let frame = context.call_stack.back().expect("frame pushed by enter_call");
+        let (caller, address) = (frame.caller, frame.address);
+        let callee = VM::new(frame.code.clone()).with_env(Env {
+            caller,
+            address,
+            value: frame.value,
+            gas_limit: frame.gas_limit,
+        });
+        {
+            let mut callee_context = callee.context.write();
+            callee_context.static_mode = frame.is_static;
+            callee_context.depth = context.call_depth();
+            callee_context.memory = std::mem::take(&mut context.memory);
+            callee_context.heap_break = context.heap_break;
+            callee_context.accounts = context.accounts.clone();
+            // A contract caller's storage lives in its context until it
+            // returns, so a callee calling back into it must see it there
+            if let Some(account) = callee_context.accounts.get_mut(&caller) {
+                account.storage = context.storage.clone();
+            }
+            callee_context.storage = callee_context.accounts.get(&address)
+                .map(|account| account.storage.clone())
+                .unwrap_or_default();
+        }
+
+        let result = callee.execute();
+        let mut callee_context = callee.context.write();
+        context.memory = std::mem::take(&mut callee_context.memory);
+        context.heap_break = callee_context.heap_break;
+        context.use_gas(callee_context.env.gas_limit - callee_context.gas_remaining)?;
+        result?;
+
+        let mut accounts = std::mem::take(&mut callee_context.accounts);
+        if let Some(account) = accounts.get_mut(&address) {
+            account.storage = std::mem::take(&mut callee_context.storage);
+        }
+        if let Some(account) = accounts.get(&caller) {
+            context.storage = account.storage.clone();
+        }
+        context.accounts = accounts;
+        context.logs.append(&mut callee_context.logs);
+        Ok(std::mem::take(&mut callee_context.return_data))
+    }
+
+    fn execute_instruction(
+        &self,
+        context: &mut ExecutionContext,
+        instruction: Instruction,
+        mut inspector: Option<&mut dyn Inspector>,
+    ) -> Result<(), VMError> {
+        let opcode = instruction.opcode;
+
+        match opcode {
+            // Existing opcodes
+            0x01 => { // PUSH
+                let value = instruction.immediate
+                    .ok_or_else(|| VMError::ExecutionError("Truncated PUSH immediate".to_string()))?;
+                if context.stack.len() >= 1024 {
+                    return Err(VMError::StackOverflow);
                 }
-                0x05 => { // LOAD
-                    let addr = match context.stack.pop() {
-                        Some(Value::Int(v)) => v as usize,
-                        _ => return Err(VMError::StackUnderflow),
-                    };
-                    let value = context.memory.get(&addr)
-                        .ok_or_else(|| VMError::MemoryError(format!("Address not found: {}", addr)))?
-                        .clone();
-                    context.stack.push(value);
+                context.stack.push(Value::Int(value as i64));
+                context.program_counter += 2;
+            }
+            0x02 => { // ADD
+                let b = context.pop_int()?;
+                let a = context.pop_int()?;
+                context.stack.push(Value::Int(a.wrapping_add(b)));
+                context.program_counter += 1;
+            }
+            0x03 => { // MUL
+                let b = context.pop_int()?;
+                let a = context.pop_int()?;
+                context.stack.push(Value::Int(a.wrapping_mul(b)));
+                context.program_counter += 1;
+            }
+            0x04 => { // STORE
+                let addr = context.pop_int()? as usize;
+                let value = context.pop()?;
+                self.check_memory(context, addr, Permission::Write)?;
+                context.memory.insert(addr, value);
+                context.program_counter += 1;
+            }
+            0x05 => { // LOAD
+                let addr = context.pop_int()? as usize;
+                self.check_memory(context, addr, Permission::Read)?;
+                let value = context.memory.get(addr)
+                    .ok_or_else(|| VMError::MemoryError(format!("Address not found: {}", addr)))?
+                    .clone();
+                context.stack.push(value);
+                context.program_counter += 1;
+            }
+            0x06 => { // JUMP
+                let target = context.pop_int()?;
+                context.program_counter = jump_target(target)?;
+            }
+            0x07 => { // JUMPI
+                let target = context.pop_int()?;
+                let condition = context.pop_int()?;
+                if condition != 0 {
+                    context.program_counter = jump_target(target)?;
+                } else {
                     context.program_counter += 1;
                 }
-                // New advanced opcodes
-                0x0B => { // CREATE
-                    let value = match context.stack.pop() {
-                        Some(Value::Int(v)) => v as u64,
-                        _ => return Err(VMError::StackUnderflow),
-                    };
-                    let code_size = match context.stack.pop() {
-                        Some(Value::Int(v)) => v as usize,
-                        _ => return Err(VMError::StackUnderflow),
-                    };
-                    
-                    context.use_gas(context.gas_config.contract_creation)?;
-                    
-                    let code: Vec<u8> = self.program[context.program_counter + 1..
-                                                   context.program_counter + 1 + code_size]
-                        .to_vec();
-                    
-                    let contract = ContractData {
-                        code,
-                        storage: HashMap::new(),
-                        balance: value,
-                    };
-                    
-                    let mut hasher = Blake2b512::new();
-                    hasher.update(&contract.code);
-                    let mut address = [0u8; 32];
-                    address.copy_from_slice(&hasher.finalize()[..32]);
-                    
-                    context.stack.push(Value::Address(address));
-                    context.stack.push(Value::Contract(contract));
-                    
-                    context.program_counter += 1 + code_size;
-                }
-                0x0C => { // CALL
-                    let address = match context.stack.pop() {
-                        Some(Value::Address(addr)) => addr,
-                        _ => return Err(VMError::StackUnderflow),
-                    };
-                    let value = match context.stack.pop() {
-                        Some(Value::Int(v)) => v as u64,
-                        _ => return Err(VMError::StackUnderflow),
-                    };
-                    let gas_limit = match context.stack.pop() {
-                        Some(Value::Int(v)) => v as u64,
-                        _ => return Err(VMError::StackUnderflow),
-                    };
-                    
-                    let contract = match context.memory.get(&(address[0] as usize)) {
-                        Some(Value::Contract(c)) => c.clone(),
-                        _ => return Err(VMError::ExecutionError("Contract not found".to_string())),
-                    };
-                    
-                    let caller = [0u8; 32]; // Current context address
-                    let frame = CallFrame {
-                        caller,
-                        address,
-                        value,
-                        gas_limit,
-                        code: contract.code,
-                        return_data: Vec::new(),
-                    };
-                    
-                    context.call_stack.push_back(frame);
-                    context.program_counter += 1;
+            }
+            0x08 => { // EQ
+                let b = context.pop_int()?;
+                let a = context.pop_int()?;
+                context.stack.push(Value::Int((a == b) as i64));
+                context.program_counter += 1;
+            }
+            0x09 => { // LT
+                let b = context.pop_int()?;
+                let a = context.pop_int()?;
+                context.stack.push(Value::Int((a < b) as i64));
+                context.program_counter += 1;
+            }
+            0x0A => { // GT
+                let b = context.pop_int()?;
+                let a = context.pop_int()?;
+                context.stack.push(Value::Int((a > b) as i64));
+                context.program_counter += 1;
+            }
+            // New advanced opcodes
+            0x0B => { // CREATE
+                context.require_mutable("CREATE")?;
+                let value = context.pop_int()? as u64;
+                let code_size = context.pop_int()?;
+                
+                context.use_gas(context.gas_config.contract_creation)?;
+                
+                let start = context.program_counter + 1;
+                let code = usize::try_from(code_size).ok()
+                    .and_then(|size| start.checked_add(size))
+                    .and_then(|end| self.program.get(start..end))
+                    .ok_or_else(|| VMError::MemoryError(format!("Contract code out of range: {:#x}+{}", start, code_size)))?
+                    .to_vec();
+                let next_pc = start + code.len();
+                
+                let contract = ContractData {
+                    code,
+                    storage: HashMap::new(),
+                    balance: value,
+                };
+                
+                let address = contract_address(&context.current_address(), context.accounts.len() as u64);
+                context.created_address.get_or_insert(address);
+                context.accounts.insert(address, contract.clone());
+                
+                context.stack.push(Value::Address(address));
+                context.stack.push(Value::Contract(contract));
+                
+                context.program_counter = next_pc;
+            }
+            0x0C => { // CALL
+                let address = context.pop_address()?;
+                let value = context.pop_int()? as u64;
+                let gas_limit = context.pop_int()? as u64;
+                
+                if value > 0 {
+                    context.require_mutable("CALL with value")?;
                 }
-                0x0D => { // RETURN
-                    let size = match context.stack.pop() {
-                        Some(Value::Int(v)) => v as usize,
-                        _ => return Err(VMError::StackUnderflow),
-                    };
-                    let offset = match context.stack.pop() {
-                        Some(Value::Int(v)) => v as usize,
-                        _ => return Err(VMError::StackUnderflow),
-                    };
-                    
-                    if let Some(frame) = context.call_stack.back_mut() {
-                        frame.return_data = self.program[offset..offset + size].to_vec();
+                
+                self.call_contract(context, address, value, gas_limit, false, inspector.as_deref_mut())?;
+                context.program_counter += 1;
+            }
+            0x0D => { // RETURN
+                let size = context.pop_int()? as usize;
+                let offset = context.pop_int()? as usize;
+                
+                let data = offset.checked_add(size)
+                    .and_then(|end| self.program.get(offset..end))
+                    .ok_or_else(|| VMError::MemoryError(format!("Return data out of range: {:#x}+{}", offset, size)))?
+                    .to_vec();
+                
+                // Callees run on a VM of their own, so this always ends
+                // the current execution
+                context.return_data = data;
+                context.halted = true;
+                
+                context.program_counter += 1;
+            }
+            0x0E => { // SHA3
+                let size = context.pop_int()? as usize;
+                let offset = context.pop_int()? as usize;
+                
+                let data = offset.checked_add(size)
+                    .and_then(|end| self.program.get(offset..end))
+                    .ok_or_else(|| VMError::MemoryError(format!("Hash input out of range: {:#x}+{}", offset, size)))?;
+                
+                // Poseidon over the proof field, so the circuit can prove
+                // the digest
+                let digest = poseidon::hash_bytes::<Scalar>(data);
+                
+                context.stack.push(Value::Bytes(digest.to_repr().to_vec()));
+                context.program_counter += 1;
+            }
+            0x0F => { // BALANCE
+                let address = context.pop_address()?;
+                
+                let balance = match context.accounts.get(&address) {
+                    Some(c) => c.balance,
+                    None => return Err(VMError::ExecutionError("Contract not found".to_string())),
+                };
+                
+                context.stack.push(Value::Int(balance as i64));
+                context.program_counter += 1;
+            }
+            0x10 => { // SYSCALL
+                let number = context.pop_int()?;
+                let syscall = Syscall::from_number(number)?;
+                let pc = context.program_counter;
+                let mut handler = self.syscall_handler.lock();
+
+                let (args, result) = match syscall {
+                    Syscall::Read | Syscall::ReadPublic => {
+                        let len = context.pop_int()?.max(0);
+                        let data = if syscall == Syscall::Read {
+                            let data = handler.read_input(len as usize);
+                            context.private_input_read += data.len();
+                            data
+                        } else {
+                            let data = handler.read_public_input(len as usize);
+                            context.public_input_read += data.len();
+                            data
+                        };
+                        (vec![Value::Int(len)], Some(Value::Bytes(data)))
                     }
-                    
-                    context.program_counter += 1;
+                    Syscall::Write => {
+                        let value = context.pop()?;
+                        let data = match &value {
+                            Value::Bytes(b) => b.clone(),
+                            Value::Int(i) => i.to_le_bytes().to_vec(),
+                            other => return Err(type_mismatch("bytes or int", other)),
+                        };
+                        handler.write_output(&data);
+                        context.journal.extend_from_slice(&data);
+                        (vec![value], None)
+                    }
+                    Syscall::Brk => {
+                        let requested = context.pop_int()?;
+                        if requested != 0 {
+                            let requested = usize::try_from(requested)
+                                .map_err(|_| VMError::MemoryError(format!("Invalid heap break: {}", requested)))?;
+                            context.set_heap_break(requested)?;
+                        }
+                        (vec![Value::Int(requested)], Some(Value::Int(context.heap_break as i64)))
+                    }
+                    Syscall::Sbrk => {
+                        let increment = context.pop_int()?;
+                        let old_break = context.heap_break;
+                        let new_break = (old_break as i64).checked_add(increment)
+                            .and_then(|b| usize::try_from(b).ok())
+                            .ok_or_else(|| VMError::MemoryError(format!("Invalid heap increment: {}", increment)))?;
+                        context.set_heap_break(new_break)?;
+                        (vec![Value::Int(increment)], Some(Value::Int(old_break as i64)))
+                    }
+                    Syscall::Exit => {
+                        let code = context.pop_int()?;
+                        handler.exit(code);
+                        context.exit_code = Some(code);
+                        context.halted = true;
+                        (vec![Value::Int(code)], None)
+                    }
+                    Syscall::Hash => {
+                        let data = context.pop_bytes()?;
+                        let hash = handler.hash(&data);
+                        (vec![Value::Bytes(data)], Some(Value::Bytes(hash.to_vec())))
+                    }
+                };
+
+                if let Some(result) = &result {
+                    if context.stack.len() >= 1024 {
+                        return Err(VMError::StackOverflow);
+                    }
+                    context.stack.push(result.clone());
                 }
-                0x0E => { // SHA3
-                    let size = match context.stack.pop() {
-                        Some(Value::Int(v)) => v as usize,
-                        _ => return Err(VMError::StackUnderflow),
-                    };
-                    let offset = match context.stack.pop() {
-                        Some(Value::Int(v)) => v as usize,
-                        _ => return Err(VMError::StackUnderflow),
-                    };
-                    
-                    let mut hasher = Blake2b512::new();
-                    hasher.update(&self.program[offset..offset + size]);
-                    let mut hash = [0u8; 32];
-                    hash.copy_from_slice(&hasher.finalize()[..32]);
-                    
-                    context.stack.push(Value::Bytes(hash.to_vec()));
-                    context.program_counter += 1;
+                context.syscalls.push(SyscallRecord { pc, syscall, args, result });
+                context.program_counter += 1;
+            }
+            0x11 => { // SSTORE
+                context.require_mutable("SSTORE")?;
+                let key = context.pop_word()?;
+                let value = context.pop()?;
+                if let Some(inspector) = inspector.as_deref_mut() {
+                    inspector.storage_write(&context.current_address(), &key, &value);
                 }
-                0x0F => { // BALANCE
-                    let address = match context.stack.pop() {
-                        Some(Value::Address(addr)) => addr,
-                        _ => return Err(VMError::StackUnderflow),
-                    };
-                    
-                    let balance = match context.memory.get(&(address[0] as usize)) {
-                        Some(Value::Contract(c)) => c.balance,
-                        _ => return Err(VMError::ExecutionError("Contract not found".to_string())),
-                    };
-                    
-                    context.stack.push(Value::Int(balance as i64));
-                    context.program_counter += 1;
+                context.storage.insert(key, value);
+                context.program_counter += 1;
+            }
+            0x12 => { // SLOAD
+                let key = context.pop_word()?;
+                let value = context.storage.get(&key)
+                    .cloned()
+                    .unwrap_or(Value::Int(0));
+                context.stack.push(value);
+                context.program_counter += 1;
+            }
+            0x13 => { // LOG
+                context.require_mutable("LOG")?;
+                let topic_count = context.pop_int()?;
+                if !(0..=4).contains(&topic_count) {
+                    return Err(VMError::ExecutionError(format!("Invalid topic count: {}", topic_count)));
+                }
+                let mut topics = Vec::with_capacity(topic_count as usize);
+                for _ in 0..topic_count {
+                    topics.push(context.pop_word()?);
+                }
+                let data = match context.pop()? {
+                    Value::Bytes(b) => b,
+                    Value::Int(i) => i.to_le_bytes().to_vec(),
+                    other => return Err(type_mismatch("bytes or int", &other)),
+                };
+
+                let log = Log {
+                    address: context.current_address(),
+                    topics,
+                    data,
+                };
+                if let Some(inspector) = inspector.as_deref_mut() {
+                    inspector.log(&log);
+                }
+                context.logs.push(log);
+                context.program_counter += 1;
+            }
+            0x14 => { // CALLDATA
+                if context.stack.len() >= 1024 {
+                    return Err(VMError::StackOverflow);
                 }
-                0xFF => break, // STOP
-                _ => return Err(VMError::InvalidOpcode(opcode)),
+                context.stack.push(Value::Bytes(context.calldata.clone()));
+                context.program_counter += 1;
+            }
+            0x15 => { // CALLER
+                if context.stack.len() >= 1024 {
+                    return Err(VMError::StackOverflow);
+                }
+                context.stack.push(Value::Address(context.env.caller));
+                context.program_counter += 1;
+            }
+            0x16 => { // STATICCALL
+                let address = context.pop_address()?;
+                let gas_limit = context.pop_int()? as u64;
+                
+                self.call_contract(context, address, 0, gas_limit, true, inspector)?;
+                context.program_counter += 1;
+            }
+            0x17 => { // ISZERO
+                let a = context.pop_int()?;
+                context.stack.push(Value::Int((a == 0) as i64));
+                context.program_counter += 1;
             }
+            0xFF => context.halted = true, // STOP
+            _ => return Err(VMError::InvalidOpcode(opcode)),
         }
 
-        // Compute final state root
-        context.compute_state_root();
         Ok(())
     }
 
@@ -374,13 +1706,18 @@ impl VM {
     }
 
     pub fn get_memory(&self) -> HashMap<usize, Value> {
-        self.context.read().memory.clone()
+        self.context.read().memory.to_map()
     }
 
     pub fn get_storage(&self) -> HashMap<[u8; 32], Value> {
         self.context.read().storage.clone()
     }
 
+    /// The contract deployed at `address`, with its storage.
+    pub fn get_account(&self, address: &[u8; 32]) -> Option<ContractData> {
+        self.context.read().accounts.get(address).cloned()
+    }
+
     pub fn get_state_root(&self) -> [u8; 32] {
         self.context.read().state_root
     }
@@ -392,4 +1729,119 @@ impl VM {
     pub fn get_gas_remaining(&self) -> u64 {
         self.context.read().gas_remaining
     }
+
+    pub fn get_gas_used(&self) -> u64 {
+        let context = self.context.read();
+        context.env.gas_limit - context.gas_remaining
+    }
+
+    /// Finds the smallest gas limit, up to `env.gas_limit`, with which the
+    /// program runs to completion. Every probe is a full execution on a fresh
+    /// VM; gas forwarded by CALL counts towards the requirement even though
+    /// it is not consumed.
+    pub fn estimate_gas(program: Vec<u8>, calldata: Vec<u8>, env: &Env) -> Result<GasEstimate, GasEstimateError> {
+        let run = |gas_limit: u64| -> Result<(u64, u64), VMError> {
+            let tracker = Arc::new(Mutex::new(ForwardedGasTracker::new(gas_limit)));
+            let vm = VM::new(program.clone())
+                .with_env(Env { gas_limit, ..env.clone() })
+                .with_calldata(calldata.clone())
+                .with_inspector(tracker.clone());
+            vm.execute()?;
+            let required = tracker.lock().required;
+            Ok((vm.get_gas_used(), required))
+        };
+
+        let cap = env.gas_limit;
+        let (gas_used, required) = run(cap).map_err(|reason| GasEstimateError {
+            gas_limit: cap,
+            reason,
+        })?;
+
+        // Anything below the gas used or the largest forwarding requirement
+        // fails, so the search starts just under that bound.
+        let mut iterations = 1;
+        let mut hi = cap;
+        let mut lo = gas_used.max(required).saturating_sub(1);
+        while lo + 1 < hi {
+            let mid = if iterations == 1 { lo + 1 } else { lo + (hi - lo) / 2 };
+            iterations += 1;
+            if run(mid).is_ok() {
+                hi = mid;
+            } else {
+                lo = mid;
+            }
+        }
+
+        Ok(GasEstimate {
+            gas_limit: hi,
+            gas_used,
+            forwarded_gas: required.saturating_sub(gas_used),
+            iterations,
+        })
+    }
+
+    pub fn get_program_counter(&self) -> usize {
+        self.context.read().program_counter
+    }
+
+    pub fn get_stack_top(&self) -> usize {
+        self.context.read().stack_top
+    }
+
+    /// Serializes the current execution state, including how much input has
+    /// been read. The snapshot can be restored into any VM built from the
+    /// same program, on this or another machine.
+    pub fn snapshot(&self) -> Result<Vec<u8>, VMError> {
+        self.context.read().to_snapshot(self.image_id).encode()
+    }
+
+    /// Replaces the execution state with a snapshot and moves the syscall
+    /// handler's input positions back to where the snapshot was taken.
+    pub fn restore(&self, snapshot: &[u8]) -> Result<(), VMError> {
+        let snapshot = ExecutionSnapshot::decode(snapshot)?;
+        if snapshot.image_id != self.image_id {
+            return Err(VMError::SnapshotError("Snapshot was taken from a different program".to_string()));
+        }
+
+        self.syscall_handler.lock().seek_input(snapshot.public_input_read, snapshot.private_input_read);
+        *self.context.write() = ExecutionContext::from_snapshot(snapshot);
+        Ok(())
+    }
+
+    /// Poseidon commitment to the complete execution state.
+    pub fn state_commitment(&self) -> Result<[u8; 32], VMError> {
+        Ok(syscall::commit_bytes(&self.snapshot()?))
+    }
+
+    pub fn get_cycle_count(&self) -> u64 {
+        self.context.read().cycles
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.context.read().halted
+    }
+
+    pub fn get_journal(&self) -> Vec<u8> {
+        self.context.read().journal.clone()
+    }
+
+    pub fn get_return_data(&self) -> Vec<u8> {
+        self.context.read().return_data.clone()
+    }
+
+    pub fn get_syscall_trace(&self) -> Vec<SyscallRecord> {
+        self.context.read().syscalls.clone()
+    }
+
+    pub fn get_exit_code(&self) -> Option<i64> {
+        self.context.read().exit_code
+    }
+
+    pub fn get_heap_break(&self) -> usize {
+        self.context.read().heap_break
+    }
+
+    pub fn image_id(&self) -> [u8; 32] {
+        self.image_id
+    }
 }
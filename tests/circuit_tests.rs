@@ -0,0 +1,565 @@
+use std::sync::Arc;
+use bellman::{gadgets::test::TestConstraintSystem, Circuit};
+use bls12_381::Scalar;
+use parking_lot::Mutex;
+use zkvm::{
+    circuit::{
+        io_commitment, poseidon::{self, Poseidon}, program_commitment, PublicValues, VMCircuit, VMState,
+        DEFAULT_MAX_IO_LEN, DEFAULT_MEMORY_CELLS, DEFAULT_STACK_CELLS,
+    },
+    proof::PublicInputs,
+    syscall::{self, HostIo},
+    tracer::{StepRecorder, TraceStep},
+    vm::{VM, Value},
+};
+
+/// Runs `program` on the given inputs, returning the finished VM and the
+/// steps it ran.
+fn run(program: &[u8], public_input: &[u8], private_input: &[u8]) -> (VM, Vec<TraceStep>) {
+    let recorder = Arc::new(Mutex::new(StepRecorder::new()));
+    let vm = VM::new(program.to_vec())
+        .with_syscall_handler(HostIo::new(public_input.to_vec(), private_input.to_vec()))
+        .with_inspector(recorder.clone());
+    vm.execute().unwrap();
+    let steps = recorder.lock().take_steps();
+    (vm, steps)
+}
+
+fn record(program: &[u8]) -> Vec<TraceStep> {
+    run(program, &[], &[]).1
+}
+
+fn circuit(program: &[u8], max_steps: usize, trace: Vec<TraceStep>) -> VMCircuit<Scalar> {
+    VMCircuit::new(program.to_vec(), max_steps).with_trace(trace)
+}
+
+fn root(state: &VMState<Scalar>) -> Scalar {
+    state.root(DEFAULT_MEMORY_CELLS, DEFAULT_STACK_CELLS)
+}
+
+fn is_satisfied(program: &[u8], max_steps: usize, trace: Vec<TraceStep>) -> bool {
+    satisfies(circuit(program, max_steps, trace))
+}
+
+fn satisfies(circuit: VMCircuit<Scalar>) -> bool {
+    let mut cs = TestConstraintSystem::new();
+    circuit.synthesize(&mut cs).unwrap();
+    cs.is_satisfied()
+}
+
+fn program() -> Vec<u8> {
+    vec![
+        0x01, 0x05, // PUSH 5
+        0x01, 0x03, // PUSH 3
+        0x02,       // ADD
+        0x01, 0x02, // PUSH 2
+        0x03,       // MUL
+        0xFF,       // STOP
+    ]
+}
+
+#[test]
+fn test_trace_follows_pc() {
+    let trace = record(&program());
+
+    let pcs: Vec<_> = trace.iter().map(|s| (s.pc, s.next_pc)).collect();
+    assert_eq!(pcs, vec![(0, 2), (2, 4), (4, 5), (5, 7), (7, 8), (8, 8)]);
+    assert_eq!(trace[2].operands, vec![Value::Int(3), Value::Int(5)]);
+    assert_eq!(trace[2].result, Some(Value::Int(8)));
+    assert_eq!(trace[4].result, Some(Value::Int(16)));
+    assert_eq!(trace[0].gas_before - trace[0].gas_after, 3);
+}
+
+#[test]
+fn test_circuit_accepts_trace() {
+    let trace = record(&program());
+    // Steps past the STOP are padding
+    assert!(is_satisfied(&program(), 10, trace.clone()));
+    assert!(is_satisfied(&program(), trace.len(), trace));
+}
+
+#[test]
+fn test_circuit_accepts_program_running_off_the_end() {
+    let program = vec![0x01, 0x07, 0x01, 0x01, 0x02];
+    let trace = record(&program);
+    assert_eq!(trace.last().unwrap().next_pc, program.len());
+    assert!(is_satisfied(&program, 6, trace));
+}
+
+#[test]
+fn test_circuit_accepts_jump_past_the_end() {
+    let program = vec![
+        0x01, 0x02, // PUSH 2
+        0x01, 0x09, // PUSH 9
+        0x02,       // ADD
+        0x06,       // JUMP to 11
+    ];
+    let (vm, trace) = run(&program, &[], &[]);
+    assert!(vm.is_halted());
+    assert_eq!(trace.last().unwrap().next_pc, 11);
+    assert!(is_satisfied(&program, 6, trace));
+}
+
+#[test]
+fn test_circuit_rejects_forged_result() {
+    let mut trace = record(&program());
+    trace[2].result = Some(Value::Int(9));
+    assert!(!is_satisfied(&program(), 10, trace));
+
+    let mut trace = record(&program());
+    trace[4].result = Some(Value::Int(10));
+    assert!(!is_satisfied(&program(), 10, trace));
+}
+
+#[test]
+fn test_circuit_rejects_wrong_pc() {
+    // Pretend the immediate of the first PUSH was executed as an instruction
+    let mut trace = record(&program());
+    trace[0].next_pc = 1;
+    trace[1].pc = 1;
+    assert!(!is_satisfied(&program(), 10, trace));
+}
+
+#[test]
+fn test_public_values() {
+    let fresh = VMState::capture(&VM::new(program()), DEFAULT_MEMORY_CELLS).unwrap();
+    let (vm, trace) = run(&program(), &[], &[]);
+    let last = VMState::capture(&vm, DEFAULT_MEMORY_CELLS).unwrap();
+    assert_eq!(last.stack, vec![Scalar::from(16)]);
+
+    let circuit = circuit(&program(), 10, trace.clone());
+    let values = PublicValues::from_slice(&circuit.public_inputs()).unwrap();
+    assert_eq!(values, PublicValues {
+        public_input_hash: io_commitment(&[], DEFAULT_MAX_IO_LEN),
+        journal_hash: io_commitment(&[], DEFAULT_MAX_IO_LEN),
+        initial_root: root(&fresh),
+        final_root: root(&last),
+        halted: Scalar::one(),
+        gas_limit: Scalar::from(trace[0].gas_before),
+        gas_used: Scalar::from(vm.get_gas_used()),
+        program_hash: program_commitment(&program(), program().len()),
+    });
+    assert_eq!(values.initial_root, circuit.entry_root());
+    assert!(satisfies(circuit));
+
+    // Without a trace there is no statement
+    assert!(VMCircuit::<Scalar>::new(program(), 10).public_inputs().is_empty());
+}
+
+#[test]
+fn test_program_commitment() {
+    // Length, then 40 bytes packed into two words
+    let commitment = program_commitment::<Scalar>(&program(), 40);
+    let mut padded = program();
+    padded.resize(40, 0);
+    let mut inputs = vec![Scalar::from(program().len() as u64)];
+    inputs.extend(poseidon::pack_bytes::<Scalar>(&padded));
+    assert_eq!(inputs.len(), 3);
+    assert_eq!(commitment, Poseidon::new().hash(&inputs));
+
+    // A trailing zero byte changes the length, and with it the commitment
+    let mut longer = program();
+    longer.push(0);
+    assert_ne!(commitment, program_commitment::<Scalar>(&longer, 40));
+}
+
+#[test]
+fn test_state_commitment_is_poseidon() {
+    let vm = VM::new(program());
+    let snapshot = vm.snapshot().unwrap();
+    let expected = poseidon::hash_bytes::<Scalar>(&snapshot).to_bytes();
+    assert_eq!(syscall::commit_bytes(&snapshot), expected);
+    assert_eq!(vm.state_commitment().unwrap(), expected);
+}
+
+#[test]
+fn test_trace_must_match_program() {
+    // A trace of one program cannot be passed off as another's
+    let mut other = program();
+    other[1] = 0x06;
+    assert!(!is_satisfied(&other, 10, record(&program())));
+}
+
+fn shape(circuit: VMCircuit<Scalar>) -> (usize, String) {
+    let mut cs = TestConstraintSystem::new();
+    circuit.synthesize(&mut cs).unwrap();
+    assert!(cs.is_satisfied());
+    (cs.num_constraints(), cs.hash())
+}
+
+#[test]
+fn test_universal_circuit() {
+    let other = vec![
+        0x01, 0x02, // PUSH 2
+        0x01, 0x09, // PUSH 9
+        0x03,       // MUL
+    ];
+
+    // Both programs fit the same bound and produce the same constraint system
+    let bounded = |program: Vec<u8>| {
+        let trace = record(&program);
+        VMCircuit::<Scalar>::universal(16, 8)
+            .with_program(program)
+            .with_trace(trace)
+    };
+    let first = bounded(program());
+    let second = bounded(other.clone());
+    assert_ne!(first.public_inputs(), second.public_inputs());
+    assert_eq!(shape(first), shape(second));
+
+    // A program over the bound cannot be synthesized
+    let too_long = VMCircuit::<Scalar>::universal(4, 8)
+        .with_program(other.clone())
+        .with_trace(record(&other));
+    let mut cs = TestConstraintSystem::new();
+    assert!(too_long.synthesize(&mut cs).is_err());
+}
+
+#[test]
+fn test_circuit_accepts_exit() {
+    let program = vec![
+        0x01, 0x03, // PUSH 3 (exit code)
+        0x01, 0x05, // PUSH SYS_EXIT
+        0x10,       // SYSCALL
+        0x01, 0x01, // never reached
+        0xFF,
+    ];
+    let trace = record(&program);
+    assert_eq!(trace.len(), 3);
+    assert!(trace[2].halted);
+    assert!(is_satisfied(&program, 6, trace));
+}
+
+#[test]
+fn test_circuit_rejects_exit_on_other_syscalls() {
+    let program = vec![
+        0x01, 0x02, 0x01, 0x07, 0x10, // READ_PUBLIC 2
+        0x01, 0x02,                   // PUSH SYS_WRITE
+        0x10,                         // SYSCALL
+        0x01, 0x01,                   // PUSH 1
+        0xFF,
+    ];
+    let (_, trace) = run(&program, b"hi", &[]);
+    let with_input = |trace| circuit(&program, 8, trace).with_public_input(b"hi".to_vec());
+    assert!(satisfies(with_input(trace.clone())));
+
+    // Claim the machine halted on the write
+    let mut halted_early = trace[..5].to_vec();
+    halted_early[4].halted = true;
+    assert!(!satisfies(with_input(halted_early)));
+}
+
+/// Echoes three bytes of public input, then four of private input.
+fn echo_program() -> Vec<u8> {
+    vec![
+        0x01, 0x03, 0x01, 0x07, 0x10, // READ_PUBLIC 3
+        0x01, 0x02, 0x10,             // WRITE
+        0x01, 0x04, 0x01, 0x01, 0x10, // READ 4
+        0x01, 0x02, 0x10,             // WRITE
+        0xFF,
+    ]
+}
+
+#[test]
+fn test_circuit_commits_io() {
+    let (vm, trace) = run(&echo_program(), b"abcde", b"xy");
+    assert_eq!(vm.get_journal(), b"abcxy");
+
+    let circuit = circuit(&echo_program(), 12, trace).with_public_input(b"abcde".to_vec());
+    let values = circuit.public_values().unwrap();
+    assert_eq!(values.public_input_hash, io_commitment(b"abcde", DEFAULT_MAX_IO_LEN));
+    assert_eq!(values.journal_hash, io_commitment(b"abcxy", DEFAULT_MAX_IO_LEN));
+
+    // The final root records how far the public input was read and that
+    // the private input ran out
+    let last = VMState::capture(&vm, DEFAULT_MEMORY_CELLS).unwrap();
+    assert_eq!((last.input_cursor, last.private_input_exhausted), (3, true));
+    assert_eq!(values.final_root, root(&last));
+    assert!(satisfies(circuit));
+}
+
+#[test]
+fn test_circuit_rejects_forged_io() {
+    let (_, trace) = run(&echo_program(), b"abcde", b"xy");
+    let circuit = circuit(&echo_program(), 12, trace.clone()).with_public_input(b"abcde".to_vec());
+    for (input, forged) in [
+        ("public_input_hash", io_commitment(b"abcdf", DEFAULT_MAX_IO_LEN)),
+        ("journal_hash", io_commitment(b"abcxz", DEFAULT_MAX_IO_LEN)),
+        ("initial_root", Scalar::from(1)),
+        ("final_root", Scalar::from(1)),
+        ("halted", Scalar::zero()),
+    ] {
+        let mut cs = TestConstraintSystem::new();
+        circuit.clone().synthesize(&mut cs).unwrap();
+        assert!(cs.is_satisfied());
+        cs.set(input, forged);
+        assert!(!cs.is_satisfied(), "forged {}", input);
+    }
+
+    // Bytes the program did not read
+    let other_input = circuit.clone().with_public_input(b"abXde".to_vec());
+    assert!(!satisfies(other_input));
+
+    // A public read that came up short before the end of the input
+    let (_, short) = run(&echo_program(), b"ab", b"xy");
+    let longer_input = VMCircuit::new(echo_program(), 12).with_trace(short).with_public_input(b"abc".to_vec());
+    assert!(!satisfies(longer_input));
+}
+
+#[test]
+fn test_circuit_rejects_integer_writes() {
+    // The VM writes an integer as its bytes, which the circuit does not
+    // model
+    let program = vec![0x01, 0x09, 0x01, 0x02, 0x10, 0xFF];
+    let (vm, trace) = run(&program, &[], &[]);
+    assert_eq!(vm.get_journal(), 9i64.to_le_bytes());
+    assert!(!is_satisfied(&program, 4, trace));
+}
+
+#[test]
+fn test_circuit_rejects_read_results_of_other_types() {
+    let (_, mut trace) = run(&echo_program(), b"abcde", b"xy");
+    trace[2].result = Some(Value::Int(0x636261));
+    trace[4].operands[1] = Value::Int(0x636261);
+    let forged = circuit(&echo_program(), 12, trace).with_public_input(b"abcde".to_vec());
+    assert!(!satisfies(forged));
+}
+
+#[test]
+fn test_circuit_rejects_unmodeled_opcodes() {
+    // CALLER pushes an address the circuit knows nothing about
+    let program = vec![0x15, 0xFF];
+    let trace = record(&program);
+    assert_eq!(trace[0].opcode, 0x15);
+    assert!(!is_satisfied(&program, 4, trace));
+
+    // Nor does it commit to storage, so SSTORE cannot be proven either
+    let program = vec![0x01, 0x2A, 0x01, 0x01, 0x11, 0xFF];
+    let trace = record(&program);
+    assert_eq!(trace[2].opcode, 0x11);
+    assert!(!is_satisfied(&program, 5, trace));
+}
+
+#[test]
+fn test_circuit_starts_at_entry_point() {
+    let program = vec![
+        0x01, 0x01, // PUSH 1
+        0x01, 0x02, // PUSH 2
+        0x01, 0x03, // PUSH 3
+        0xFF,
+    ];
+    let trace = record(&program);
+    assert!(is_satisfied(&program, 6, trace.clone()));
+
+    // Skipping the first PUSH leaves a consistent trace, but one starting
+    // from a root other than a fresh machine's, and which cannot be proven
+    // from that root
+    let mut skipped = trace[1..].to_vec();
+    for step in skipped.iter_mut() {
+        step.stack_height -= 1;
+    }
+    let mut from_pc_2 = circuit(&program, 6, skipped);
+    assert_ne!(from_pc_2.public_values().unwrap().initial_root, from_pc_2.entry_root());
+    from_pc_2.initial_state.as_mut().unwrap().program_counter = Scalar::from(0);
+    assert!(!satisfies(from_pc_2));
+}
+
+fn memory_program() -> Vec<u8> {
+    vec![
+        0x01, 0x07, 0x01, 0x01, 0x04, // STORE 7 at 1
+        0x01, 0x09, 0x01, 0x01, 0x04, // STORE 9 at 1
+        0x01, 0x04, 0x01, 0x00, 0x04, // STORE 4 at 0
+        0x01, 0x01, 0x05,             // LOAD 1
+        0x01, 0x00, 0x05,             // LOAD 0
+        0x02,                         // ADD
+        0xFF,
+    ]
+}
+
+#[test]
+fn test_circuit_checks_memory() {
+    let (vm, trace) = run(&memory_program(), &[], &[]);
+    assert_eq!(trace[10].opcode, 0x05);
+    assert_eq!(trace[10].result, Some(Value::Int(9)));
+
+    let circuit = circuit(&memory_program(), 20, trace);
+    let last = VMState::capture(&vm, DEFAULT_MEMORY_CELLS).unwrap();
+    assert_eq!(last.memory, vec![Scalar::from(4), Scalar::from(9)]);
+    assert_eq!(circuit.public_values().unwrap().final_root, root(&last));
+    assert!(satisfies(circuit));
+}
+
+#[test]
+fn test_circuit_rejects_stale_load() {
+    let mut trace = record(&memory_program());
+    trace[10].result = Some(Value::Int(7));
+    assert!(!is_satisfied(&memory_program(), 20, trace));
+}
+
+#[test]
+fn test_circuit_rejects_load_of_unwritten_address() {
+    let mut trace = record(&memory_program());
+    trace[10].operands[0] = Value::Int(5);
+    trace[10].result = Some(Value::Int(0));
+    assert!(!is_satisfied(&memory_program(), 20, trace));
+}
+
+#[test]
+fn test_circuit_rejects_invented_operands() {
+    let mut trace = record(&program());
+    trace[2].operands = vec![Value::Int(4), Value::Int(4)];
+    assert!(!is_satisfied(&program(), 10, trace));
+}
+
+#[test]
+fn test_circuit_rejects_stack_underflow() {
+    // The ADD and STOP of PUSH 3 PUSH 5 ADD STOP, replayed on an empty stack
+    let program = vec![0x02, 0xFF];
+    let mut trace = record(&[0x01, 0x03, 0x01, 0x05, 0x02, 0xFF]).split_off(2);
+    for step in trace.iter_mut() {
+        step.pc -= 4;
+        step.next_pc -= 4;
+        step.stack_height = step.stack_height.saturating_sub(2);
+    }
+    assert!(!is_satisfied(&program, 4, trace));
+}
+
+#[test]
+fn test_circuit_charges_gas() {
+    let trace = record(&program());
+    let charged: Vec<_> = trace.iter().map(|s| s.gas_before - s.gas_after).collect();
+    assert_eq!(charged, vec![3, 3, 5, 3, 5, 2]);
+
+    // ADD charged at 1 instead of 5, so less gas appears used
+    let mut cheap = trace.clone();
+    cheap[2].gas_after += 4;
+    for step in cheap.iter_mut().skip(3) {
+        step.gas_before += 4;
+        step.gas_after += 4;
+    }
+    assert!(!is_satisfied(&program(), 10, cheap));
+
+    // Halted padding is free
+    assert!(is_satisfied(&program(), 12, trace));
+}
+
+#[test]
+fn test_circuit_accepts_wrapping_arithmetic() {
+    // 255^9 overflows an i64
+    let mut program = vec![0x01, 0xFF];
+    for _ in 0..8 {
+        program.extend_from_slice(&[0x01, 0xFF, 0x03]);
+    }
+    program.push(0xFF);
+
+    let trace = record(&program);
+    let last_mul = &trace[trace.len() - 2];
+    assert_eq!(last_mul.result, Some(Value::Int(255i64.wrapping_pow(9))));
+    assert!(is_satisfied(&program, trace.len(), trace));
+}
+
+fn counting_loop() -> Vec<u8> {
+    vec![
+        0x01, 0x00, 0x01, 0x00, 0x04, // mem[0] = 0
+        0x01, 0x00, 0x05,             // 5: LOAD 0
+        0x01, 0x01, 0x02,             // ADD 1
+        0x01, 0x00, 0x04,             // STORE at 0
+        0x01, 0x00, 0x05,             // LOAD 0
+        0x01, 0x03, 0x09,             // LT 3
+        0x01, 0x05, 0x07,             // JUMPI 5
+        0xFF,
+    ]
+}
+
+#[test]
+fn test_circuit_accepts_comparisons() {
+    let program = vec![
+        0x01, 0x04, 0x01, 0x04, 0x08, // EQ -> 1
+        0x01, 0x07, 0x01, 0x02, 0x0A, // 7 GT 2 -> 1
+        0x02,                         // ADD -> 2
+        0x17,                         // ISZERO -> 0
+        0xFF,
+    ];
+    let trace = record(&program);
+    let results: Vec<_> = trace.iter()
+        .filter(|s| [0x08, 0x0A, 0x02, 0x17].contains(&s.opcode))
+        .map(|s| s.result.clone())
+        .collect();
+    assert_eq!(results, vec![Some(Value::Int(1)), Some(Value::Int(1)), Some(Value::Int(2)), Some(Value::Int(0))]);
+    assert!(is_satisfied(&program, trace.len(), trace));
+}
+
+#[test]
+fn test_circuit_accepts_conditional_jumps() {
+    let trace = record(&counting_loop());
+    let jumps: Vec<_> = trace.iter().filter(|s| s.opcode == 0x07).map(|s| s.next_pc).collect();
+    assert_eq!(jumps, vec![5, 5, 23]);
+    assert!(is_satisfied(&counting_loop(), trace.len(), trace));
+}
+
+#[test]
+fn test_circuit_rejects_forged_comparison() {
+    let mut trace = record(&counting_loop());
+    let last_lt = trace.iter().rposition(|s| s.opcode == 0x09).unwrap();
+    trace[last_lt].result = Some(Value::Int(1));
+    assert!(!is_satisfied(&counting_loop(), trace.len(), trace));
+}
+
+fn hashing_program() -> Vec<u8> {
+    vec![
+        0x01, 0x00, // PUSH 0 (offset)
+        0x01, 0x05, // PUSH 5 (size)
+        0x0E,       // SHA3
+        0xFF,       // STOP
+    ]
+}
+
+#[test]
+fn test_circuit_proves_sha3() {
+    let trace = record(&hashing_program());
+    let digest = poseidon::hash_bytes::<Scalar>(&hashing_program()[..5]);
+    assert_eq!(trace[2].result, Some(Value::Bytes(digest.to_bytes().to_vec())));
+    assert!(is_satisfied(&hashing_program(), 6, trace));
+}
+
+#[test]
+fn test_circuit_rejects_forged_hash() {
+    let mut trace = record(&hashing_program());
+    trace[2].result = Some(Value::Bytes(vec![0; 32]));
+    assert!(!is_satisfied(&hashing_program(), 6, trace));
+}
+
+#[test]
+fn test_circuit_stats() {
+    let stats = VMCircuit::<Scalar>::universal(16, 8).stats().unwrap();
+
+    // Counting without a witness matches a satisfied synthesis
+    let witnessed = VMCircuit::<Scalar>::universal(16, 8)
+        .with_program(program())
+        .with_trace(record(&program()));
+    let public_inputs = witnessed.public_inputs().len();
+    let mut cs = TestConstraintSystem::new();
+    witnessed.synthesize(&mut cs).unwrap();
+    assert!(cs.is_satisfied());
+    assert_eq!(stats.constraints, cs.num_constraints());
+    assert_eq!(stats.public_inputs, public_inputs);
+
+    // The gadget breakdown covers everything
+    let constraints: usize = stats.gadgets.values().map(|c| c.constraints).sum();
+    let variables: usize = stats.gadgets.values().map(|c| c.variables).sum();
+    assert_eq!((constraints, variables), (stats.constraints, stats.variables));
+    assert!(stats.gadgets["step_*/sha3"].constraints > 0);
+
+    // Each further step costs the same, as long as the access logs pad to
+    // the same power of two
+    let shorter = VMCircuit::<Scalar>::universal(16, 6).stats().unwrap();
+    let longer = VMCircuit::<Scalar>::universal(16, 7).stats().unwrap();
+    assert_eq!(longer.per_step, stats.per_step);
+    assert_eq!(longer.constraints - shorter.constraints, stats.per_step.constraints);
+
+    // Every opcode is accounted for, SHA3 above all
+    assert_eq!(stats.opcodes.len(), 16);
+    let sha3 = stats.opcodes[&0x0E].constraints;
+    assert!(stats.opcodes.values().all(|c| c.constraints <= sha3));
+    assert!(stats.opcodes[&0x02].constraints > stats.opcodes[&0x01].constraints);
+}
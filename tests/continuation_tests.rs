@@ -0,0 +1,62 @@
+use bls12_381::Scalar;
+use zkvm::{
+    circuit::VMCircuit,
+    continuation::{self, SegmentProver},
+    syscall::HostIo,
+    vm::VM,
+};
+
+const CYCLES_PER_SEGMENT: usize = 5;
+
+/// Stores a value, echoes part of the public input and the private input
+/// to the journal, then loads the value back, so that the stack, memory and
+/// input cursors all carry across segment boundaries.
+fn program() -> Vec<u8> {
+    vec![
+        0x01, 0x07, 0x01, 0x01, 0x04, // STORE 7 at 1
+        0x01, 0x03, 0x01, 0x07, 0x10, // READ_PUBLIC 3
+        0x01, 0x02, 0x10,             // WRITE
+        0x01, 0x04, 0x01, 0x01, 0x10, // READ 4
+        0x01, 0x02, 0x10,             // WRITE
+        0x01, 0x01, 0x05,             // LOAD 1
+        0xFF,
+    ]
+}
+
+fn segments() -> Vec<continuation::Segment> {
+    let vm = VM::new(program())
+        .with_syscall_handler(HostIo::new(b"abcde".to_vec(), b"xy".to_vec()));
+    continuation::split(&vm, CYCLES_PER_SEGMENT).unwrap()
+}
+
+#[test]
+fn test_segments_prove_and_verify() {
+    let segments = segments();
+    assert_eq!(segments.len(), 4);
+    let journal: Vec<u8> = segments.iter().flat_map(|s| s.journal.clone()).collect();
+    assert_eq!(journal, b"abcxy");
+
+    let prover = SegmentProver::<Scalar>::setup(program(), CYCLES_PER_SEGMENT).unwrap();
+    let proofs = prover.prove_all(&segments, b"abcde").unwrap();
+    let entry_root = VMCircuit::<Scalar>::new(program(), CYCLES_PER_SEGMENT).entry_root();
+    assert_eq!(proofs[0].public_values().unwrap().initial_root, entry_root);
+    assert!(proofs.iter().rev().skip(1).all(|proof| !proof.halted));
+    assert!(proofs.last().unwrap().halted);
+    assert!(prover.verify(&proofs, entry_root).unwrap());
+
+    // The chain must start at the given root, keep every segment in order
+    // and run to the end
+    assert!(!prover.verify(&proofs, Scalar::from(1)).unwrap());
+    let mut skipped = proofs.clone();
+    skipped.remove(1);
+    assert!(!prover.verify(&skipped, entry_root).unwrap());
+    let mut swapped = proofs.clone();
+    swapped.swap(1, 2);
+    assert!(!prover.verify(&swapped, entry_root).unwrap());
+    assert!(!prover.verify(&proofs[..3], entry_root).unwrap());
+
+    // Every segment must read the same public input
+    let mut mixed = proofs.clone();
+    mixed[2] = prover.prove(&segments[2], b"abcdf").unwrap();
+    assert!(!prover.verify(&mixed, entry_root).unwrap());
+}
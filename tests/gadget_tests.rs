@@ -0,0 +1,212 @@
+use bellman::{gadgets::test::TestConstraintSystem, ConstraintSystem, LinearCombination};
+use bls12_381::Scalar;
+use ff::{Field, PrimeField};
+use zkvm::circuit::{
+    gadgets::{
+        add_with_carry, alloc_word, decompose, decompose_field, decompose_signed, enforce_range,
+        less_than, mul_with_carry, select, signed_less_than,
+    },
+    poseidon::Poseidon,
+};
+
+fn signed(value: i64) -> Scalar {
+    if value >= 0 {
+        Scalar::from(value as u64)
+    } else {
+        -Scalar::from(value.unsigned_abs())
+    }
+}
+
+/// Whether `value` passes a `width`-bit range check.
+fn in_range(value: Scalar, width: usize) -> bool {
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let var = cs.alloc(|| "value", || Ok(value)).unwrap();
+    enforce_range(&mut cs, LinearCombination::zero() + var, Some(value), width).unwrap();
+    cs.is_satisfied()
+}
+
+#[test]
+fn test_range_check() {
+    assert!(in_range(Scalar::zero(), 8));
+    assert!(in_range(Scalar::from(255), 8));
+    assert!(!in_range(Scalar::from(256), 8));
+    assert!(in_range(Scalar::from(u64::MAX), 64));
+    // Field wraparound does not make a negative value small
+    assert!(!in_range(-Scalar::one(), 64));
+}
+
+#[test]
+fn test_decompose_bits() {
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let value = Scalar::from(0b1011);
+    let var = cs.alloc(|| "value", || Ok(value)).unwrap();
+    let word = decompose(&mut cs, LinearCombination::zero() + var, Some(value), 4).unwrap();
+    assert!(cs.is_satisfied());
+    assert_eq!(word.width(), 4);
+    assert_eq!(word.value(), Some(0b1011));
+}
+
+#[test]
+fn test_signed_words() {
+    for value in [0, 1, -1, i64::MAX, i64::MIN] {
+        let mut cs = TestConstraintSystem::<Scalar>::new();
+        let var = cs.alloc(|| "value", || Ok(signed(value))).unwrap();
+        let word = decompose_signed(&mut cs, LinearCombination::zero() + var, Some(signed(value)), 64).unwrap();
+        assert!(cs.is_satisfied(), "{}", value);
+        assert_eq!(word.value(), Some(value as u64));
+    }
+
+    // One past the largest 8-bit signed value has no 8-bit word
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let var = cs.alloc(|| "value", || Ok(Scalar::from(128))).unwrap();
+    decompose_signed(&mut cs, LinearCombination::zero() + var, Some(Scalar::from(128)), 8).unwrap();
+    assert!(!cs.is_satisfied());
+}
+
+#[test]
+fn test_add_with_carry() {
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let a = alloc_word(&mut cs.namespace(|| "a"), Some(u64::MAX), 64).unwrap();
+    let b = alloc_word(&mut cs.namespace(|| "b"), Some(2), 64).unwrap();
+    let (sum, _) = add_with_carry(&mut cs.namespace(|| "add"), &a, &b).unwrap();
+    assert!(cs.is_satisfied());
+    assert_eq!(sum.value(), Some(1));
+    assert_eq!(cs.get("add/carry"), Scalar::one());
+
+    // Flipping a bit of the sum breaks the carry equation
+    cs.set("add/sum/bit_1", Scalar::one());
+    assert!(!cs.is_satisfied());
+}
+
+#[test]
+fn test_mul_with_carry() {
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let a = alloc_word(&mut cs.namespace(|| "a"), Some(1 << 40), 64).unwrap();
+    let b = alloc_word(&mut cs.namespace(|| "b"), Some(1 << 30), 64).unwrap();
+    let (low, high) = mul_with_carry(&mut cs.namespace(|| "mul"), &a, &b).unwrap();
+    assert!(cs.is_satisfied());
+    assert_eq!(low.value(), Some(0));
+    assert_eq!(high.value(), Some(1 << 6));
+}
+
+#[test]
+fn test_shifts() {
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let word = alloc_word(&mut cs.namespace(|| "word"), Some(0b1101), 4).unwrap();
+    let shifted = cs.alloc(|| "shifted", || Ok(Scalar::from(0b1010))).unwrap();
+    cs.enforce(
+        || "shl",
+        |_| word.shl_lc::<Scalar>(1),
+        |lc| lc + TestConstraintSystem::<Scalar>::one(),
+        |lc| lc + shifted,
+    );
+    let halved = cs.alloc(|| "halved", || Ok(Scalar::from(0b0011))).unwrap();
+    cs.enforce(
+        || "shr",
+        |_| word.shr_lc::<Scalar>(2),
+        |lc| lc + TestConstraintSystem::<Scalar>::one(),
+        |lc| lc + halved,
+    );
+    assert!(cs.is_satisfied());
+}
+
+fn compare(a: i64, b: i64, signed: bool) -> Scalar {
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let a = alloc_word(&mut cs.namespace(|| "a"), Some(a as u64), 64).unwrap();
+    let b = alloc_word(&mut cs.namespace(|| "b"), Some(b as u64), 64).unwrap();
+    if signed {
+        signed_less_than(&mut cs.namespace(|| "lt"), &a, &b).unwrap();
+    } else {
+        less_than(&mut cs.namespace(|| "lt"), &a, &b).unwrap();
+    }
+    assert!(cs.is_satisfied());
+    cs.get("lt/less")
+}
+
+#[test]
+fn test_less_than() {
+    assert_eq!(compare(2, 3, false), Scalar::one());
+    assert_eq!(compare(3, 3, false), Scalar::zero());
+    assert_eq!(compare(-1, 0, false), Scalar::zero());
+    assert_eq!(compare(-1, 0, true), Scalar::one());
+    assert_eq!(compare(i64::MIN, i64::MAX, true), Scalar::one());
+    assert_eq!(compare(i64::MAX, i64::MIN, true), Scalar::zero());
+
+    // The opposite answer fails the range check
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let a = alloc_word(&mut cs.namespace(|| "a"), Some(2), 8).unwrap();
+    let b = alloc_word(&mut cs.namespace(|| "b"), Some(3), 8).unwrap();
+    less_than(&mut cs.namespace(|| "lt"), &a, &b).unwrap();
+    cs.set("lt/less", Scalar::zero());
+    assert!(!cs.is_satisfied());
+}
+
+#[test]
+fn test_select() {
+    for (condition, expected) in [(Scalar::one(), 7u64), (Scalar::zero(), 9u64)] {
+        let mut cs = TestConstraintSystem::<Scalar>::new();
+        let flag = cs.alloc(|| "flag", || Ok(condition)).unwrap();
+        select(
+            &mut cs.namespace(|| "select"),
+            LinearCombination::zero() + flag,
+            LinearCombination::zero() + (Scalar::from(7), TestConstraintSystem::<Scalar>::one()),
+            LinearCombination::zero() + (Scalar::from(9), TestConstraintSystem::<Scalar>::one()),
+            Some(Scalar::from(expected)),
+        ).unwrap();
+        assert!(cs.is_satisfied());
+
+        cs.set("select/selected", Scalar::from(8));
+        assert!(!cs.is_satisfied());
+    }
+}
+
+#[test]
+fn test_decompose_field() {
+    let largest = -Scalar::one();
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let var = cs.alloc(|| "value", || Ok(largest)).unwrap();
+    let bits = decompose_field(&mut cs, LinearCombination::zero() + var, Some(largest)).unwrap();
+    assert!(cs.is_satisfied());
+    assert_eq!(bits.len(), Scalar::NUM_BITS as usize);
+
+    // The modulus packs to zero but is not a canonical encoding of it
+    let modulus_bits = largest.to_bytes();
+    let is_set = |i: usize| (modulus_bits[i / 8] >> (i % 8)) & 1 == 1;
+    let mut cs = TestConstraintSystem::<Scalar>::new();
+    let var = cs.alloc(|| "value", || Ok(Scalar::zero())).unwrap();
+    decompose_field(&mut cs, LinearCombination::zero() + var, Some(Scalar::zero())).unwrap();
+    assert!(cs.is_satisfied());
+    let top = Scalar::NUM_BITS as usize - 1;
+    for i in 0..=top {
+        if is_set(i) {
+            cs.set(&format!("bit_{}", i), Scalar::one());
+            if i < top {
+                cs.set(&format!("prefix_{}", i), Scalar::one());
+            }
+        }
+    }
+    cs.set("bit_0", Scalar::one());
+    assert_eq!(cs.which_is_unsatisfied(), Some("bit_0_below_modulus"));
+}
+
+#[test]
+fn test_poseidon_gadget() {
+    let poseidon = Poseidon::<Scalar>::new();
+    for len in 0..=3 {
+        let inputs: Vec<_> = (0..len).map(|i| Scalar::from(i as u64 + 7)).collect();
+        let mut cs = TestConstraintSystem::<Scalar>::new();
+        let vars: Vec<_> = inputs.iter().enumerate()
+            .map(|(i, &input)| LinearCombination::zero() + cs.alloc(|| format!("input_{}", i), || Ok(input)).unwrap())
+            .collect();
+        poseidon.alloc_hash(&mut cs.namespace(|| "hash"), &vars, Some(inputs.as_slice())).unwrap();
+        assert!(cs.is_satisfied());
+        assert_eq!(cs.get("hash/digest"), poseidon.hash(&inputs));
+
+        cs.set("hash/digest", poseidon.hash(&inputs) + Scalar::one());
+        assert!(!cs.is_satisfied());
+    }
+
+    // Zero padding is absorbed like explicit zeros, hence the length prefixes
+    assert_eq!(poseidon.hash(&[Scalar::one()]), poseidon.hash(&[Scalar::one(), Scalar::zero()]));
+    assert_ne!(poseidon.hash(&[Scalar::one()]), poseidon.hash(&[Scalar::zero(), Scalar::one()]));
+}
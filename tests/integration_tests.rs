@@ -1,4 +1,4 @@
-use zkvm::{ZKVM, vm::{Value, VMError}};
+use zkvm::{ZKVM, continuation, vm::{VM, Value, VMError}};
 use proptest::prelude::*;
 
 fn create_valid_program(operations: Vec<(u8, u8)>) -> Vec<u8> {
@@ -122,3 +122,75 @@ fn test_arithmetic_operations() {
         panic!("Expected integer value");
     }
 }
+
+#[test]
+fn test_public_and_private_inputs() {
+    let program = vec![
+        0x01, 0x04, // PUSH 4 (len)
+        0x01, 0x07, // PUSH SYS_READ_PUBLIC
+        0x10,       // SYSCALL
+        0x01, 0x02, // PUSH SYS_WRITE
+        0x10,       // SYSCALL
+        0x01, 0x04, // PUSH 4 (len)
+        0x01, 0x01, // PUSH SYS_READ (private)
+        0x10,       // SYSCALL
+        0x01, 0x02, // PUSH SYS_WRITE
+        0x10,       // SYSCALL
+        0xFF,
+    ];
+
+    let public_input = vec![1, 2, 3, 4];
+    let private_input = vec![9, 9, 9, 9];
+    let mut zkvm = ZKVM::with_inputs(program, public_input.clone(), private_input.clone()).unwrap();
+    zkvm.execute().unwrap();
+
+    let journal = zkvm.get_journal();
+    assert_eq!(journal, [public_input.clone(), private_input.clone()].concat());
+
+    let proof_data = zkvm.generate_proof().unwrap();
+    assert!(zkvm.verify_with_io(&proof_data, &public_input, &journal).unwrap());
+
+    // A different claimed journal must not verify
+    let mut forged = journal.clone();
+    forged[0] ^= 1;
+    assert!(!zkvm.verify_with_io(&proof_data, &public_input, &forged).unwrap());
+}
+
+fn summation_program() -> Vec<u8> {
+    let mut program = Vec::new();
+    for i in 0..20u8 {
+        program.extend_from_slice(&[0x01, i]); // PUSH i
+    }
+    for _ in 0..19 {
+        program.push(0x02); // ADD
+    }
+    program.push(0xFF); // STOP
+    program
+}
+
+#[test]
+fn test_segmented_execution() {
+    let reference = VM::new(summation_program());
+    reference.execute().unwrap();
+
+    let vm = VM::new(summation_program());
+    let initial_snapshot = vm.snapshot().unwrap();
+    let segments = continuation::split(&vm, 8).unwrap();
+
+    // 20 PUSH + 19 ADD + STOP = 40 cycles
+    assert_eq!(segments.len(), 5);
+    assert_eq!(segments.iter().map(|s| s.cycles).sum::<u64>(), 40);
+    assert_eq!(segments[0].start_snapshot, initial_snapshot);
+    for pair in segments.windows(2) {
+        assert!(!pair[0].halted);
+    }
+    assert!(segments.last().unwrap().halted);
+    assert_eq!(vm.get_stack(), reference.get_stack());
+
+    // Each segment can be re-executed on its own from its start snapshot
+    let segment = &segments[2];
+    let replay = VM::new(summation_program());
+    replay.restore(&segment.start_snapshot).unwrap();
+    replay.run(segment.cycles as usize).unwrap();
+    assert_eq!(replay.snapshot().unwrap(), segments[3].start_snapshot);
+}
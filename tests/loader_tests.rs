@@ -0,0 +1,168 @@
+use zkvm::{
+    loader::{self, LoadedSegment, LoaderError, Program, STACK_SIZE},
+    memory::{AccessPermissions, MemoryConfig, MemoryManager, Permission, SegmentType},
+    vm::{VM, Value, VMError},
+};
+
+// add.elf: code at 0x20 (r-x) loading bytes 0x80 and 0x81 and adding them,
+// data at 0x80 (rw-) holding [42, 55] plus two bytes of .bss.
+const ADD_ELF: &[u8] = include_bytes!("fixtures/add.elf");
+const ADD_BE_ELF: &[u8] = include_bytes!("fixtures/add_be.elf");
+const DYNAMIC_ELF: &[u8] = include_bytes!("fixtures/dynamic.elf");
+
+#[test]
+fn test_load_segments() {
+    let program = loader::load_elf(ADD_ELF).unwrap();
+    assert_eq!(program.entry, 0x20);
+
+    let code = &program.segments[0];
+    assert_eq!(code.vaddr, 0x20);
+    assert_eq!(code.segment_type, SegmentType::Code);
+    assert_eq!(code.permissions, AccessPermissions::READ_EXECUTE);
+
+    let data = &program.segments[1];
+    assert_eq!(data.vaddr, 0x80);
+    assert_eq!(data.size, 4);
+    assert_eq!(data.segment_type, SegmentType::Data);
+    assert_eq!(data.permissions, AccessPermissions::READ_WRITE);
+    assert_eq!(data.bytes(), vec![0x2A, 0x37, 0x00, 0x00]);
+
+    let stack = program.stack_segment().unwrap();
+    assert_eq!(stack.vaddr, 0x1000);
+    assert_eq!(program.stack_top, 0x1000 + STACK_SIZE);
+}
+
+#[test]
+fn test_execute_elf() {
+    let mut vm = VM::from_elf(ADD_ELF).unwrap();
+    assert_eq!(vm.get_program_counter(), 0x20);
+    assert!(vm.execute().is_ok());
+
+    let stack = vm.get_stack();
+    assert_eq!(stack, vec![Value::Int(97)]); // 42 + 55 = 97
+}
+
+#[test]
+fn test_big_endian_elf() {
+    let le = loader::load_elf(ADD_ELF).unwrap();
+    let be = loader::load_elf(ADD_BE_ELF).unwrap();
+    assert_eq!(le.image_id, be.image_id);
+}
+
+#[test]
+fn test_image_id() {
+    let program = loader::load_elf(ADD_ELF).unwrap();
+    let vm = VM::from_elf(ADD_ELF).unwrap();
+    assert_eq!(vm.image_id(), program.image_id);
+
+    let mut patched = ADD_ELF.to_vec();
+    let last = patched.len() - 1;
+    patched[last] ^= 0xFF;
+    let other = loader::load_elf(&patched).unwrap();
+    assert_ne!(other.image_id, program.image_id);
+}
+
+#[test]
+fn test_reject_invalid_elf() {
+    let mut bad_magic = ADD_ELF.to_vec();
+    bad_magic[0] = 0;
+    assert!(matches!(loader::load_elf(&bad_magic), Err(LoaderError::InvalidMagic)));
+
+    assert!(matches!(loader::load_elf(&ADD_ELF[..40]), Err(LoaderError::Truncated(_))));
+    assert!(matches!(loader::load_elf(DYNAMIC_ELF), Err(LoaderError::DynamicallyLinked)));
+
+    match VM::from_elf(&bad_magic) {
+        Err(VMError::LoadError(LoaderError::InvalidMagic)) => (),
+        _ => panic!("Expected load error"),
+    }
+}
+
+#[test]
+fn test_reject_writable_code() {
+    let mut elf = ADD_ELF.to_vec();
+    // p_flags of the first program header: R | W | X
+    elf[52 + 24] = 0x7;
+    match loader::load_elf(&elf) {
+        Err(LoaderError::InvalidSegment { vaddr, .. }) => assert_eq!(vaddr, 0x20),
+        _ => panic!("Expected invalid segment error"),
+    }
+}
+
+/// Code at 0x20 and a four-byte data segment at 0x80 with the given permissions.
+fn program_with(code: Vec<u8>, entry: usize, data_permissions: AccessPermissions) -> Program {
+    let segments = vec![
+        LoadedSegment {
+            vaddr: 0x20,
+            size: code.len(),
+            data: code,
+            segment_type: SegmentType::Code,
+            permissions: AccessPermissions::READ_EXECUTE,
+        },
+        LoadedSegment {
+            vaddr: 0x80,
+            size: 4,
+            data: vec![0x2A],
+            segment_type: SegmentType::Data,
+            permissions: data_permissions,
+        },
+    ];
+    let image_id = loader::compute_image_id(entry, &segments);
+    Program { entry, segments, stack_top: 0x1000, image_id }
+}
+
+fn memory_error(vm: &VM) -> String {
+    match vm.execute().as_ref().map_err(VMError::kind) {
+        Err(VMError::MemoryError(message)) => message.clone(),
+        other => panic!("Expected memory error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_code_is_read_only() {
+    let store_to_code = vec![
+        0x01, 0x01, // PUSH 1
+        0x01, 0x20, // PUSH 0x20 (code)
+        0x04,       // STORE
+        0xFF,
+    ];
+    let vm = VM::from_program(&program_with(store_to_code, 0x20, AccessPermissions::READ_WRITE)).unwrap();
+    assert!(memory_error(&vm).contains("Permission denied"));
+}
+
+#[test]
+fn test_data_permissions_enforced() {
+    let load = vec![0x01, 0x80, 0x05, 0xFF]; // LOAD 0x80
+    let vm = VM::from_program(&program_with(load, 0x20, AccessPermissions::READ)).unwrap();
+    assert!(vm.execute().is_ok());
+    assert_eq!(vm.get_stack(), vec![Value::Int(42)]);
+
+    let store = vec![0x01, 0x01, 0x01, 0x80, 0x04, 0xFF]; // STORE 1 at 0x80
+    let vm = VM::from_program(&program_with(store, 0x20, AccessPermissions::READ)).unwrap();
+    assert!(memory_error(&vm).contains("Permission denied"));
+
+    let unmapped = vec![0x01, 0x01, 0x01, 0x50, 0x04, 0xFF]; // STORE 1 at 0x50
+    let vm = VM::from_program(&program_with(unmapped, 0x20, AccessPermissions::READ_WRITE)).unwrap();
+    assert!(memory_error(&vm).contains("Page fault"));
+}
+
+#[test]
+fn test_data_is_not_executable() {
+    let vm = VM::from_program(&program_with(vec![0xFF], 0x80, AccessPermissions::READ_WRITE)).unwrap();
+    let error = vm.execute().unwrap_err();
+    assert_eq!(error.context().unwrap().pc, 0x80);
+    assert!(matches!(error.kind(), VMError::MemoryError(message) if message.contains("Permission denied")));
+}
+
+#[test]
+fn test_permissions_survive_freeing_segments() {
+    let memory = MemoryManager::new(MemoryConfig::default());
+    let scratch = memory.allocate(16, SegmentType::Heap).unwrap();
+    memory.map(0x1000, 0x100, &[], SegmentType::Data, AccessPermissions::READ_WRITE).unwrap();
+    memory.map(0x2000, 0x100, &[0xFF], SegmentType::Code, AccessPermissions::READ_EXECUTE).unwrap();
+
+    // Freeing the first segment must not hand its successors' permissions around
+    memory.deallocate(scratch).unwrap();
+    assert!(memory.check_access(0x1000, Permission::Write).is_ok());
+    assert!(memory.check_access(0x2000, Permission::Write).is_err());
+    assert_eq!(memory.fetch(0x2000, 1).unwrap(), vec![0xFF]);
+}
@@ -0,0 +1,203 @@
+use zkvm::{
+    transaction::{receipts_root, ReceiptStatus, Transaction},
+    vm::{contract_address, VM, Value},
+};
+
+const SENDER: [u8; 32] = [0x11; 32];
+
+fn key(k: u8) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    key[31] = k;
+    key
+}
+
+#[test]
+fn test_storage_persists_across_transactions() {
+    let mut vm = VM::new(Vec::new());
+
+    let store = Transaction::new(SENDER, vec![
+        0x01, 0x2A, // PUSH 42 (value)
+        0x01, 0x01, // PUSH 1 (key)
+        0x11,       // SSTORE
+        0x01, 0x07, // PUSH 7
+        0x01, 0x00, // PUSH 0
+        0x04,       // STORE 7 at memory cell 0
+        0xFF,
+    ], Vec::new());
+    let increment = Transaction::new(SENDER, vec![
+        0x01, 0x01, // PUSH 1 (key)
+        0x12,       // SLOAD
+        0x01, 0x01, // PUSH 1
+        0x02,       // ADD
+        0x01, 0x02, // PUSH 2 (key)
+        0x11,       // SSTORE
+        0xFF,
+    ], Vec::new());
+
+    let receipts = vm.execute_transactions(&[store, increment]);
+    assert!(receipts.iter().all(|r| r.is_success()));
+    assert_eq!(receipts[0].gas_used, 3 + 3 + 100 + 3 + 3 + 20 + 2);
+
+    let storage = vm.get_storage();
+    assert_eq!(storage[&key(1)], Value::Int(42));
+    assert_eq!(storage[&key(2)], Value::Int(43));
+
+    // Stack and memory do not carry over
+    assert!(vm.get_stack().is_empty());
+    assert!(vm.get_memory().is_empty());
+}
+
+#[test]
+fn test_contracts_persist_across_transactions() {
+    let mut vm = VM::new(Vec::new());
+
+    let create = Transaction::new(SENDER, vec![
+        0x01, 0x05, // PUSH 5 (code size)
+        0x01, 0x00, // PUSH 0 (value)
+        0x0B,       // CREATE
+        0x01, 0x2A, // contract: PUSH 42 (value)
+        0x01, 0x01, //           PUSH 1 (key)
+        0x11,       //           SSTORE
+        0xFF,
+    ], Vec::new());
+    let receipt = vm.execute_transaction(&create);
+    let address = receipt.contract_address.unwrap();
+    assert!(vm.get_storage().is_empty());
+
+    let call = Transaction::new(SENDER, vec![
+        0x01, 0xC8, // PUSH 200 (gas limit)
+        0x01, 0x00, // PUSH 0 (value)
+        0x14,       // CALLDATA (address)
+        0x0C,       // CALL
+        0xFF,
+    ], address.to_vec());
+    let receipt = vm.execute_transaction(&call);
+    assert!(receipt.is_success(), "{:?}", receipt.error);
+
+    // The callee wrote to its own account's storage, not the caller's
+    assert!(vm.get_storage().is_empty());
+    assert_eq!(vm.get_account(&address).unwrap().storage[&key(1)], Value::Int(42));
+}
+
+#[test]
+fn test_failed_call_charges_callee_gas() {
+    let mut vm = VM::new(Vec::new());
+
+    let create = Transaction::new(SENDER, vec![
+        0x01, 0x03, // PUSH 3 (code size)
+        0x01, 0x00, // PUSH 0 (value)
+        0x0B,       // CREATE
+        0x01, 0x01, // contract: PUSH 1
+        0x02,       //           ADD with one operand
+        0xFF,
+    ], Vec::new());
+    let address = vm.execute_transaction(&create).contract_address.unwrap();
+
+    let call = Transaction::new(SENDER, vec![
+        0x01, 0xC8, // PUSH 200 (gas limit)
+        0x01, 0x00, // PUSH 0 (value)
+        0x14,       // CALLDATA (address)
+        0x0C,       // CALL
+        0xFF,
+    ], address.to_vec());
+    let receipt = vm.execute_transaction(&call);
+    assert!(!receipt.is_success());
+    // The caller's PUSH, PUSH, CALLDATA and CALL, then the callee's PUSH and ADD
+    assert_eq!(receipt.gas_used, 3 + 3 + 3 + 40 + 3 + 5);
+}
+
+#[test]
+fn test_failed_transaction_reverts_storage() {
+    let mut vm = VM::new(Vec::new());
+
+    let failing = Transaction::new(SENDER, vec![
+        0x01, 0x2A, // PUSH 42
+        0x01, 0x01, // PUSH 1
+        0x11,       // SSTORE
+        0x02,       // ADD on an empty stack
+        0xFF,
+    ], Vec::new());
+    let receipt = vm.execute_transaction(&failing);
+    assert!(!receipt.is_success());
+    assert!(receipt.error.is_some());
+    assert!(vm.get_storage().is_empty());
+
+    // Later transactions still run
+    let ok = Transaction::new(SENDER, vec![0x01, 0x01, 0x12, 0xFF], Vec::new());
+    assert!(vm.execute_transaction(&ok).is_success());
+    assert_eq!(vm.get_stack(), vec![Value::Int(0)]);
+}
+
+#[test]
+fn test_transaction_environment() {
+    let mut vm = VM::new(Vec::new());
+
+    let tx = Transaction::new(SENDER, vec![0x14, 0x15, 0xFF], vec![1, 2, 3]).with_gas_limit(10);
+    let receipt = vm.execute_transaction(&tx);
+    assert!(receipt.is_success());
+    assert_eq!(vm.get_stack(), vec![Value::Bytes(vec![1, 2, 3]), Value::Address(SENDER)]);
+    assert_eq!(vm.get_gas_remaining(), 2);
+
+    let out_of_gas = Transaction::new(SENDER, vec![0x14, 0x15, 0xFF], Vec::new()).with_gas_limit(5);
+    assert!(!vm.execute_transaction(&out_of_gas).is_success());
+}
+
+#[test]
+fn test_receipt_contents() {
+    let mut vm = VM::new(Vec::new());
+
+    let create = Transaction::new(SENDER, vec![
+        0x01, 0x02, // PUSH 2 (code size)
+        0x01, 0x00, // PUSH 0 (value)
+        0x0B,       // CREATE
+        0x01, 0x05, // contract code
+        0xFF,
+    ], Vec::new());
+    let returning = Transaction::new(SENDER, vec![
+        0x01, 0x00, // PUSH 0 (offset)
+        0x01, 0x02, // PUSH 2 (size)
+        0x0D,       // RETURN
+        0x01, 0x09, // never reached
+        0xFF,
+    ], Vec::new());
+    let failing = Transaction::new(SENDER, vec![0x02, 0xFF], Vec::new());
+
+    let receipts = vm.execute_transactions(&[create, returning, failing]);
+
+    assert_eq!(receipts[0].status, ReceiptStatus::Success);
+    assert_eq!(receipts[0].contract_address, Some(contract_address(&[0; 32], 0)));
+    assert!(receipts[0].return_data.is_empty());
+
+    assert_eq!(receipts[1].return_data, vec![0x01, 0x00]);
+    assert_eq!(receipts[1].contract_address, None);
+
+    assert_eq!(receipts[2].status, ReceiptStatus::Revert);
+    assert!(receipts[2].logs.is_empty());
+
+    let mut cumulative = 0;
+    for receipt in &receipts {
+        cumulative += receipt.gas_used;
+        assert_eq!(receipt.cumulative_gas_used, cumulative);
+    }
+}
+
+#[test]
+fn test_receipts_root() {
+    let mut vm = VM::new(Vec::new());
+    let first = Transaction::new(SENDER, vec![0x01, 0x01, 0xFF], Vec::new());
+    let second = Transaction::new(SENDER, vec![0x02, 0xFF], Vec::new());
+    let receipts = vm.execute_transactions(&[first, second]);
+
+    assert_eq!(receipts_root(&[]), [0u8; 32]);
+    let root = receipts_root(&receipts);
+    assert_ne!(root, [0u8; 32]);
+    assert_eq!(root, receipts_root(&receipts));
+
+    let mut reordered = receipts.clone();
+    reordered.reverse();
+    assert_ne!(root, receipts_root(&reordered));
+
+    let mut tampered = receipts.clone();
+    tampered[0].gas_used += 1;
+    assert_ne!(root, receipts_root(&tampered));
+}
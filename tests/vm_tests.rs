@@ -1,5 +1,6 @@
 use zkvm::{
-    vm::{VM, Value, VMError},
+    vm::{storage_root, Env, ExecutionStatus, Inspector, Log, RAW_CODE_BASE, StepInfo, VM, Value, VMError},
+    syscall::{HostIo, ReplayHandler, Syscall, DEFAULT_HEAP_START},
     circuit::VMCircuit,
     proof::{ProofSystem, ProofData},
     ZKVM,
@@ -7,6 +8,9 @@ use zkvm::{
 use bellman::groth16::*;
 use ff::{Field, PrimeField};
 use rand::thread_rng;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
 
 #[test]
 fn test_basic_arithmetic() {
@@ -81,7 +85,7 @@ fn test_stack_underflow() {
     ];
 
     let mut vm = VM::new(program);
-    match vm.execute() {
+    match vm.execute().as_ref().map_err(VMError::kind) {
         Err(VMError::StackUnderflow) => (),
         _ => panic!("Expected stack underflow error"),
     }
@@ -97,7 +101,7 @@ fn test_stack_overflow() {
     program.push(0xFF);
 
     let mut vm = VM::new(program);
-    match vm.execute() {
+    match vm.execute().as_ref().map_err(VMError::kind) {
         Err(VMError::StackOverflow) => (),
         _ => panic!("Expected stack overflow error"),
     }
@@ -111,7 +115,7 @@ fn test_invalid_opcode() {
     ];
 
     let mut vm = VM::new(program);
-    match vm.execute() {
+    match vm.execute().as_ref().map_err(VMError::kind) {
         Err(VMError::InvalidOpcode(_)) => (),
         _ => panic!("Expected invalid opcode error"),
     }
@@ -166,39 +170,177 @@ fn test_contract_creation() {
     }
 }
 
+#[test]
+fn test_create_rejects_code_out_of_range() {
+    let past_end = vec![
+        0x01, 0xC8, // PUSH 200 (code size)
+        0x01, 0x00, // PUSH 0 (value)
+        0x0B,       // CREATE
+        0xFF,
+    ];
+    let mut negative = push_int(-1);
+    negative.extend_from_slice(&[0x01, 0x00, 0x0B, 0xFF]);
+
+    for program in [past_end, negative] {
+        let vm = VM::new(program);
+        let error = vm.execute().unwrap_err();
+        assert!(matches!(error.kind(), VMError::MemoryError(_)), "{}", error);
+        assert_eq!(error.context().unwrap().opcode, 0x0B);
+    }
+}
+
+/// Deploys `contract_code`, leaving its address on the stack above `operands`.
+fn deploy(operands: &[u8], contract_code: &[u8]) -> Vec<u8> {
+    let mut program = Vec::new();
+    for &operand in operands {
+        program.extend_from_slice(&[0x01, operand]); // PUSH operand
+    }
+    program.extend_from_slice(&[
+        0x01, contract_code.len() as u8, // PUSH code size
+        0x01, 0x00,                      // PUSH 0 (initial balance)
+        0x0B,                            // CREATE
+    ]);
+    program.extend_from_slice(contract_code);
+    program.extend_from_slice(&[
+        0x01, 0x00, // PUSH 0 (address)
+        0x04,       // STORE the contract value, leaving its address
+    ]);
+    program
+}
+
 #[test]
 fn test_contract_call() {
     let contract_code = vec![
-        0x01, 0x05, // PUSH 5
-        0x01, 0x03, // PUSH 3
-        0x02,       // ADD
+        0x01, 0x00, // PUSH 0 (offset)
+        0x01, 0x02, // PUSH 2 (size)
         0x0D,       // RETURN
-        0xFF,       // STOP
     ];
 
-    let mut program = vec![
-        // First create the contract
-        0x01, contract_code.len() as u8, // PUSH code size
-        0x01, 0x64,                      // PUSH 100 (initial balance)
-        0x0B,                            // CREATE
+    // Gas limit and value to send, below the address
+    let mut program = deploy(&[0xC8, 0x00], &contract_code);
+    program.extend_from_slice(&[
+        0x0C, // CALL
+        0xFF, // STOP
+    ]);
+
+    let vm = VM::new(program);
+    assert!(vm.execute().is_ok());
+
+    // The callee returned its first two code bytes and its frame is gone
+    assert_eq!(vm.get_stack(), vec![Value::Bytes(vec![0x01, 0x00])]);
+    assert!(vm.get_return_data().is_empty());
+}
+
+#[test]
+fn test_staticcall_runs_callee_read_only() {
+    let reader = vec![
+        0x01, 0x01, // PUSH 1 (key)
+        0x12,       // SLOAD
+        0x01, 0x00, // PUSH 0 (offset)
+        0x01, 0x01, // PUSH 1 (size)
+        0x0D,       // RETURN
     ];
-    program.extend_from_slice(&contract_code);
-    
-    // Then call it
+
+    let mut program = deploy(&[0xC8], &reader);
     program.extend_from_slice(&[
-        0x01, 0x0A,  // PUSH 10 (gas limit)
-        0x01, 0x00,  // PUSH 0 (value to send)
-        0x0C,        // CALL
-        0xFF,        // STOP
+        0x16,       // STATICCALL
+        0x01, 0x2A, // PUSH 42 (value)
+        0x01, 0x01, // PUSH 1 (key)
+        0x11,       // SSTORE, allowed again once the callee returned
+        0xFF,
     ]);
 
-    let mut vm = VM::new(program);
+    let vm = VM::new(program);
     assert!(vm.execute().is_ok());
+    assert_eq!(vm.get_stack(), vec![Value::Bytes(vec![0x01])]);
+    assert_eq!(vm.get_storage().len(), 1);
+
+    let writer = vec![
+        0x01, 0x2A, // PUSH 42 (value)
+        0x01, 0x01, // PUSH 1 (key)
+        0x11,       // SSTORE
+        0xFF,
+    ];
+    let mut program = deploy(&[0xC8], &writer);
+    program.extend_from_slice(&[0x16, 0xFF]); // STATICCALL, STOP
+
+    let vm = VM::new(program);
+    assert!(matches!(vm.execute().as_ref().map_err(VMError::kind), Err(VMError::StaticCallViolation(_))));
+    assert!(vm.get_storage().is_empty());
+}
+
+/// Pushes `value` one byte at a time, wrapping like ADD and MUL do.
+fn push_int(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let first = bytes.iter().position(|&b| b != 0).unwrap_or(7);
+    let mut code = vec![0x01, bytes[first]];
+    for &byte in &bytes[first + 1..] {
+        code.extend_from_slice(&[
+            0x01, 0x10, 0x01, 0x10, 0x03, // PUSH 256
+            0x03,                         // MUL
+            0x01, byte, 0x02,             // ADD byte
+        ]);
+    }
+    code
+}
+
+#[test]
+fn test_call_depth_is_limited() {
+    // Calls itself with the gas in cell 1, less 200 each time, through the
+    // memory it shares with its caller
+    let recursive = vec![
+        0x01, 0x01, 0x05, // LOAD 1 (gas)
+        0x01, 0x02, 0x05, // LOAD 2 (-200)
+        0x02,             // ADD
+        0x01, 0x01, 0x04, // STORE at 1
+        0x01, 0x01, 0x05, // LOAD 1 (gas limit)
+        0x01, 0x00,       // PUSH 0 (value)
+        0x01, 0x00, 0x05, // LOAD 0 (address)
+        0x0C,             // CALL
+        0xFF,
+    ];
+
+    let mut program = push_int(-200);
+    program.extend_from_slice(&[0x01, 0x02, 0x04]); // STORE at 2
+    program.extend(push_int(900_000));
+    program.extend_from_slice(&[0x01, 0x01, 0x04]); // STORE at 1
+    program.extend(deploy(&[], &recursive));
+    program.extend_from_slice(&[0x01, 0x00, 0x04]); // STORE the address at 0
+    program.extend_from_slice(&recursive);
 
-    // Check call frame return data
-    let call_frames = vm.get_call_frames();
-    assert_eq!(call_frames.len(), 1);
-    assert_eq!(call_frames[0].return_data, vec![8]); // 5 + 3 = 8
+    let vm = VM::new(program);
+    let error = vm.execute().unwrap_err();
+    assert!(matches!(error.kind(), VMError::CallDepthExceeded), "{}", error);
+
+    // The CALL that failed ran in the deepest frame
+    let mut innermost = &error;
+    while let VMError::Fault { source, .. } = innermost {
+        match source.as_ref() {
+            inner @ VMError::Fault { .. } => innermost = inner,
+            _ => break,
+        }
+    }
+    assert_eq!(innermost.context().unwrap().depth, 1024);
+}
+
+#[test]
+fn test_callee_errors_report_their_depth() {
+    let mut program = deploy(&[0xC8, 0x00], &[0x01, 0x01, 0xEE]); // PUSH 1, invalid opcode
+    program.extend_from_slice(&[0x0C, 0xFF]); // CALL, STOP
+
+    let vm = VM::new(program);
+    let error = vm.execute().unwrap_err();
+    assert!(matches!(error.kind(), VMError::InvalidOpcode(0xEE)));
+
+    // The CALL runs at the top level and the fault one frame below it
+    let outer = error.context().unwrap();
+    assert_eq!((outer.opcode, outer.depth, outer.address), (0x0C, 0, [0; 32]));
+    let inner = match &error {
+        VMError::Fault { source, .. } => source.context().unwrap(),
+        _ => unreachable!(),
+    };
+    assert_eq!((inner.pc, inner.opcode, inner.depth), (2, 0xEE, 1));
+    assert_ne!(inner.address, [0; 32]);
 }
 
 #[test]
@@ -227,6 +369,20 @@ fn test_sha3_hash() {
     }
 }
 
+#[test]
+fn test_sha3_out_of_range_is_an_error() {
+    let program = vec![
+        0x01, 0x01, // PUSH 1 (offset)
+        0x01, 0xFF, // PUSH 255 (size)
+        0x0E,       // SHA3 past the end of the code
+        0xFF,
+    ];
+
+    let vm = VM::new(program);
+    let err = vm.execute().unwrap_err();
+    assert!(matches!(err.kind(), VMError::MemoryError(message) if message.contains("out of range")));
+}
+
 #[test]
 fn test_state_root() {
     let program = vec![
@@ -244,3 +400,538 @@ fn test_state_root() {
     assert_ne!(state_root, [0; 32]);
     // Add specific state root check
 }
+
+#[test]
+fn test_syscall_io() {
+    let program = vec![
+        0x01, 0x04, // PUSH 4 (len)
+        0x01, 0x01, // PUSH SYS_READ
+        0x10,       // SYSCALL
+        0x01, 0x06, // PUSH SYS_HASH
+        0x10,       // SYSCALL
+        0x01, 0x02, // PUSH SYS_WRITE
+        0x10,       // SYSCALL
+        0x01, 0x07, // PUSH 7 (exit code)
+        0x01, 0x05, // PUSH SYS_EXIT
+        0x10,       // SYSCALL
+        0x01, 0x01, // PUSH 1 (never executed)
+        0xFF,       // STOP
+    ];
+
+    let vm = VM::new(program.clone()).with_syscall_handler(HostIo::new(Vec::new(), vec![1, 2, 3, 4, 5]));
+    assert!(vm.execute().is_ok());
+
+    assert_eq!(vm.get_exit_code(), Some(7));
+    assert!(vm.get_stack().is_empty());
+    assert_eq!(vm.get_journal().len(), 32);
+
+    let trace = vm.get_syscall_trace();
+    let calls: Vec<Syscall> = trace.iter().map(|r| r.syscall).collect();
+    assert_eq!(calls, vec![Syscall::Read, Syscall::Hash, Syscall::Write, Syscall::Exit]);
+    assert_eq!(trace[0].result, Some(Value::Bytes(vec![1, 2, 3, 4])));
+
+    // Replaying the recorded trace reproduces the journal without the host input
+    let replay = VM::new(program).with_syscall_handler(ReplayHandler::new(&trace));
+    assert!(replay.execute().is_ok());
+    assert_eq!(replay.get_journal(), vm.get_journal());
+}
+
+#[test]
+fn test_syscall_heap_growth() {
+    let program = vec![
+        0x01, 0x40, // PUSH 64 (increment)
+        0x01, 0x04, // PUSH SYS_SBRK
+        0x10,       // SYSCALL
+        0x01, 0x00, // PUSH 0 (query)
+        0x01, 0x03, // PUSH SYS_BRK
+        0x10,       // SYSCALL
+        0xFF,       // STOP
+    ];
+
+    let vm = VM::new(program);
+    assert!(vm.execute().is_ok());
+
+    let start = DEFAULT_HEAP_START as i64;
+    assert_eq!(vm.get_stack(), vec![Value::Int(start), Value::Int(start + 64)]);
+    assert_eq!(vm.get_heap_break(), DEFAULT_HEAP_START + 64);
+}
+
+#[test]
+fn test_heap_is_usable_below_the_break_only() {
+    let mut above_break = vec![0x01, 0x07]; // PUSH 7
+    above_break.extend(push_int(130_050));
+    above_break.extend_from_slice(&[0x04, 0xFF]); // STORE
+    let mut load_above_break = push_int(DEFAULT_HEAP_START as i64);
+    load_above_break.extend_from_slice(&[0x05, 0xFF]); // LOAD
+
+    for program in [above_break, load_above_break] {
+        let vm = VM::new(program);
+        let error = vm.execute().unwrap_err();
+        assert!(matches!(error.kind(), VMError::MemoryError(message) if message.contains("Page fault")), "{}", error);
+    }
+
+    let grown = vec![
+        0x01, 0x07, // PUSH 7
+        0x01, 0x40, // PUSH 64 (increment)
+        0x01, 0x04, // PUSH SYS_SBRK
+        0x10,       // SYSCALL, leaving the old break
+        0x04,       // STORE 7 there
+        0xFF,
+    ];
+    let vm = VM::new(grown);
+    assert!(vm.execute().is_ok());
+    assert_eq!(vm.get_memory()[&DEFAULT_HEAP_START], Value::Int(7));
+}
+
+#[test]
+fn test_unknown_syscall() {
+    let program = vec![
+        0x01, 0x7F, // PUSH 127
+        0x10,       // SYSCALL
+        0xFF,
+    ];
+
+    let vm = VM::new(program);
+    match vm.execute().as_ref().map_err(VMError::kind) {
+        Err(VMError::ExecutionError(_)) => (),
+        _ => panic!("Expected execution error"),
+    }
+}
+
+#[test]
+fn test_snapshot_and_resume() {
+    let program = vec![
+        0x01, 0x2A, // PUSH 42
+        0x01, 0x00, // PUSH 0
+        0x04,       // STORE
+        0x01, 0x05, // PUSH 5
+        0x01, 0x03, // PUSH 3
+        0x02,       // ADD
+        0xFF,       // STOP
+    ];
+
+    let reference = VM::new(program.clone());
+    assert!(reference.execute().is_ok());
+
+    let vm = VM::new(program.clone());
+    assert_eq!(vm.run(4).unwrap(), ExecutionStatus::Paused);
+    let snapshot = vm.snapshot().unwrap();
+
+    // Encoding is deterministic
+    assert_eq!(snapshot, vm.snapshot().unwrap());
+
+    let resumed = VM::new(program);
+    resumed.restore(&snapshot).unwrap();
+    assert_eq!(resumed.run(usize::MAX).unwrap(), ExecutionStatus::Halted);
+
+    assert_eq!(resumed.get_stack(), reference.get_stack());
+    assert_eq!(resumed.get_memory(), reference.get_memory());
+    assert_eq!(resumed.get_gas_remaining(), reference.get_gas_remaining());
+    assert_eq!(resumed.get_state_root(), reference.get_state_root());
+}
+
+#[test]
+fn test_snapshot_resumes_input_where_it_left_off() {
+    let program = vec![
+        0x01, 0x02, 0x01, 0x07, 0x10, // READ_PUBLIC 2
+        0x01, 0x02, 0x10,             // WRITE
+        0x01, 0x01, 0x01, 0x01, 0x10, // READ 1
+        0x01, 0x02, 0x10,             // WRITE
+        0x01, 0x02, 0x01, 0x07, 0x10, // READ_PUBLIC 2
+        0x01, 0x02, 0x10,             // WRITE
+        0x01, 0x01, 0x01, 0x01, 0x10, // READ 1
+        0x01, 0x02, 0x10,             // WRITE
+        0xFF,
+    ];
+    let io = || HostIo::new(b"abcd".to_vec(), b"xy".to_vec());
+
+    let vm = VM::new(program.clone()).with_syscall_handler(io());
+    assert_eq!(vm.run(12).unwrap(), ExecutionStatus::Paused);
+    assert_eq!(vm.get_journal(), b"abx");
+    let snapshot = vm.snapshot().unwrap();
+
+    let resumed = VM::new(program).with_syscall_handler(io());
+    resumed.restore(&snapshot).unwrap();
+    assert_eq!(resumed.run(usize::MAX).unwrap(), ExecutionStatus::Halted);
+    assert_eq!(resumed.get_journal(), b"abxcdy");
+}
+
+#[test]
+fn test_snapshot_rejects_other_program() {
+    let vm = VM::new(vec![0x01, 0x01, 0xFF]);
+    let snapshot = vm.snapshot().unwrap();
+
+    let other = VM::new(vec![0x01, 0x02, 0xFF]);
+    match other.restore(&snapshot) {
+        Err(VMError::SnapshotError(_)) => (),
+        _ => panic!("Expected snapshot error"),
+    }
+
+    match vm.restore(&snapshot[..3]) {
+        Err(VMError::SnapshotError(_)) => (),
+        _ => panic!("Expected snapshot error"),
+    }
+}
+
+#[test]
+fn test_truncated_push() {
+    let program = vec![
+        0x01, 0x05, // PUSH 5
+        0x01,       // PUSH without immediate
+    ];
+
+    let vm = VM::new(program);
+    match vm.execute().as_ref().map_err(VMError::kind) {
+        Err(VMError::ExecutionError(_)) => (),
+        _ => panic!("Expected execution error"),
+    }
+}
+
+#[test]
+fn test_sparse_memory() {
+    let program = vec![
+        0x01, 0x07, // PUSH 7
+        0x01, 0xFF, // PUSH 255
+        0x04,       // STORE
+        0x01, 0xFF, // PUSH 255
+        0x05,       // LOAD
+        0xFF,
+    ];
+
+    let vm = VM::new(program);
+    assert!(vm.execute().is_ok());
+    assert_eq!(vm.get_stack(), vec![Value::Int(7)]);
+
+    let memory = vm.get_memory();
+    assert_eq!(memory.len(), 1);
+    assert_eq!(memory[&255], Value::Int(7));
+}
+
+#[derive(Default)]
+struct RecordingInspector {
+    steps: Vec<(usize, u8)>,
+    completed: usize,
+    storage_writes: Vec<([u8; 32], Value)>,
+    logs: Vec<Log>,
+    errors: Vec<(usize, u8)>,
+}
+
+impl Inspector for RecordingInspector {
+    fn step(&mut self, step: &StepInfo<'_>) {
+        self.steps.push((step.pc, step.opcode));
+    }
+
+    fn step_end(&mut self, _step: &StepInfo<'_>) {
+        self.completed += 1;
+    }
+
+    fn storage_write(&mut self, _address: &[u8; 32], key: &[u8; 32], value: &Value) {
+        self.storage_writes.push((*key, value.clone()));
+    }
+
+    fn log(&mut self, log: &Log) {
+        self.logs.push(log.clone());
+    }
+
+    fn error(&mut self, pc: usize, opcode: u8, _error: &VMError) {
+        self.errors.push((pc, opcode));
+    }
+}
+
+#[test]
+fn test_inspector_hooks() {
+    let program = vec![
+        0x01, 0x2A, // PUSH 42 (value)
+        0x01, 0x01, // PUSH 1 (key)
+        0x11,       // SSTORE
+        0x01, 0x63, // PUSH 99 (data)
+        0x01, 0x07, // PUSH 7 (topic)
+        0x01, 0x01, // PUSH 1 (topic count)
+        0x13,       // LOG
+        0xFF,       // STOP
+    ];
+
+    let inspector = Arc::new(Mutex::new(RecordingInspector::default()));
+    let vm = VM::new(program).with_inspector(inspector.clone());
+    assert!(vm.execute().is_ok());
+
+    let inspector = inspector.lock();
+    assert_eq!(inspector.steps.len(), 8);
+    assert_eq!(inspector.completed, 8);
+    assert_eq!(inspector.steps[2], (4, 0x11));
+
+    let mut key = [0u8; 32];
+    key[31] = 1;
+    assert_eq!(inspector.storage_writes, vec![(key, Value::Int(42))]);
+    assert_eq!(inspector.logs.len(), 1);
+    assert_eq!(inspector.logs[0].data, 99i64.to_le_bytes().to_vec());
+    assert!(inspector.errors.is_empty());
+    assert_eq!(vm.get_logs(), inspector.logs);
+}
+
+#[test]
+fn test_inspector_error_hook() {
+    let program = vec![
+        0x01, 0x05, // PUSH 5
+        0x02,       // ADD with one operand
+        0xFF,
+    ];
+
+    let inspector = Arc::new(Mutex::new(RecordingInspector::default()));
+    let vm = VM::new(program).with_inspector(inspector.clone());
+    assert!(vm.execute().is_err());
+
+    let inspector = inspector.lock();
+    assert_eq!(inspector.errors, vec![(2, 0x02)]);
+    assert_eq!(inspector.completed, 1);
+}
+
+#[test]
+fn test_estimate_gas() {
+    let program = vec![
+        0x01, 0x05, // PUSH 5 (3 gas)
+        0x01, 0x03, // PUSH 3 (3 gas)
+        0x02,       // ADD (5 gas)
+        0x01, 0x00, // PUSH 0 (3 gas)
+        0x04,       // STORE (20 gas)
+        0xFF,       // STOP (2 gas)
+    ];
+
+    let estimate = VM::estimate_gas(program.clone(), Vec::new(), &Env::default()).unwrap();
+    assert_eq!(estimate.gas_limit, 36);
+    assert_eq!(estimate.gas_used, 36);
+    assert_eq!(estimate.forwarded_gas, 0);
+
+    let env = Env { gas_limit: estimate.gas_limit - 1, ..Env::default() };
+    assert!(VM::new(program).with_env(env).execute().is_err());
+}
+
+#[test]
+fn test_estimate_gas_reports_failure() {
+    let program = vec![
+        0x01, 0x05, // PUSH 5
+        0x02,       // ADD with one operand
+        0xFF,
+    ];
+
+    let err = VM::estimate_gas(program, Vec::new(), &Env::default()).unwrap_err();
+    assert_eq!(err.gas_limit, 1_000_000);
+    assert!(matches!(err.reason.kind(), VMError::StackUnderflow));
+
+    let program = vec![0x01, 0x05, 0x01, 0x03, 0x02, 0xFF];
+    let env = Env { gas_limit: 5, ..Env::default() };
+    let err = VM::estimate_gas(program, Vec::new(), &env).unwrap_err();
+    assert!(matches!(err.reason.kind(), VMError::GasLimitExceeded));
+}
+
+#[test]
+fn test_calldata_and_caller() {
+    let env = Env { caller: [7; 32], ..Env::default() };
+    let vm = VM::new(vec![0x14, 0x15, 0xFF])
+        .with_env(env)
+        .with_calldata(vec![1, 2, 3]);
+    assert!(vm.execute().is_ok());
+    assert_eq!(vm.get_stack(), vec![Value::Bytes(vec![1, 2, 3]), Value::Address([7; 32])]);
+}
+
+#[test]
+fn test_json_trace() {
+    let program = vec![
+        0x01, 0x05, // PUSH 5
+        0x01, 0x03, // PUSH 3
+        0x02,       // ADD
+        0xFF,       // STOP
+    ];
+
+    let mut vm = VM::new(program);
+    let (trace, result) = vm.execute_with_trace(Vec::new()).unwrap();
+    assert!(result.is_ok());
+
+    let lines: Vec<serde_json::Value> = String::from_utf8(trace).unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 5);
+
+    assert_eq!(lines[2]["pc"], 4);
+    assert_eq!(lines[2]["op"], 2);
+    assert_eq!(lines[2]["opName"], "ADD");
+    assert_eq!(lines[2]["gas"], "0xf423a");
+    assert_eq!(lines[2]["gasCost"], "0x5");
+    assert_eq!(lines[2]["stack"], serde_json::json!(["0x5", "0x3"]));
+    assert_eq!(lines[2]["depth"], 1);
+    assert!(lines[2].get("error").is_none());
+
+    assert_eq!(lines[4]["pass"], true);
+    assert_eq!(lines[4]["gasUsed"], "0xd"); // 3 + 3 + 5 + 2
+}
+
+#[test]
+fn test_json_trace_records_error() {
+    let program = vec![
+        0x01, 0x05, // PUSH 5
+        0x02,       // ADD with one operand
+        0xFF,
+    ];
+
+    let mut vm = VM::new(program);
+    let (trace, result) = vm.execute_with_trace(Vec::new()).unwrap();
+    assert!(matches!(result.as_ref().map_err(VMError::kind), Err(VMError::StackUnderflow)));
+
+    let lines: Vec<serde_json::Value> = String::from_utf8(trace).unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[1]["opName"], "ADD");
+    assert_eq!(lines[1]["error"], "Stack underflow");
+    assert_eq!(lines[2]["pass"], false);
+}
+
+#[test]
+fn test_call_static_rejects_state_changes() {
+    let sstore = vec![
+        0x01, 0x2A, // PUSH 42 (value)
+        0x01, 0x01, // PUSH 1 (key)
+        0x11,       // SSTORE
+        0xFF,
+    ];
+
+    let vm = VM::new(sstore);
+    assert!(matches!(vm.call_static().as_ref().map_err(VMError::kind), Err(VMError::StaticCallViolation(_))));
+    assert!(vm.get_storage().is_empty());
+
+    let log = vec![
+        0x01, 0x63, // PUSH 99 (data)
+        0x01, 0x00, // PUSH 0 (topic count)
+        0x13,       // LOG
+        0xFF,
+    ];
+    let vm = VM::new(log);
+    assert!(matches!(vm.call_static().as_ref().map_err(VMError::kind), Err(VMError::StaticCallViolation(_))));
+    assert!(vm.get_logs().is_empty());
+}
+
+#[test]
+fn test_call_static_allows_reads() {
+    let program = vec![
+        0x01, 0x01, // PUSH 1 (key)
+        0x12,       // SLOAD
+        0x01, 0x05, // PUSH 5
+        0x02,       // ADD
+        0xFF,
+    ];
+
+    let vm = VM::new(program);
+    assert!(vm.call_static().is_ok());
+    assert_eq!(vm.get_stack(), vec![Value::Int(5)]);
+}
+
+#[test]
+fn test_error_context() {
+    let program = vec![
+        0x01, 0x05, // PUSH 5
+        0x14,       // CALLDATA
+        0x02,       // ADD on bytes
+        0xFF,
+    ];
+
+    let vm = VM::new(program).with_calldata(vec![1]);
+    let err = vm.execute().unwrap_err();
+    assert!(matches!(err.kind(), VMError::TypeMismatch { expected: "int", found: "bytes" }));
+
+    let context = err.context().unwrap();
+    assert_eq!(context.pc, 3);
+    assert_eq!(context.opcode, 0x02);
+    assert_eq!(context.depth, 0);
+    assert_eq!(context.address, [0; 32]);
+
+    let err = VM::new(vec![0x02, 0xFF]).execute().unwrap_err();
+    assert!(matches!(err.kind(), VMError::StackUnderflow));
+    assert_eq!(err.to_string(), "Stack underflow (pc 0x0, opcode 0x02, depth 0)");
+}
+
+#[test]
+fn test_raw_code_is_not_writable() {
+    // RAW_CODE_BASE = 0x1010000 = 257 * 256 * 256
+    assert_eq!(RAW_CODE_BASE, 257 * 256 * 256);
+    let program = vec![
+        0x01, 0x01,             // PUSH 1 (value)
+        0x01, 0xFF, 0x01, 0x02, // PUSH 255, PUSH 2
+        0x02,                   // ADD -> 257
+        0x01, 0xFF, 0x01, 0x01, // PUSH 255, PUSH 1
+        0x02,                   // ADD -> 256
+        0x03,                   // MUL
+        0x01, 0xFF, 0x01, 0x01, // PUSH 255, PUSH 1
+        0x02,                   // ADD -> 256
+        0x03,                   // MUL
+        0x04,                   // STORE into the code segment
+        0xFF,
+    ];
+
+    let vm = VM::new(program);
+    let err = vm.execute().unwrap_err();
+    assert!(matches!(err.kind(), VMError::MemoryError(message) if message.contains("Permission denied")));
+    assert!(vm.get_memory().is_empty());
+}
+
+#[test]
+fn test_canonical_encoding_is_unambiguous() {
+    let encode = |value: &Value| {
+        let mut out = Vec::new();
+        value.encode_canonical(&mut out);
+        out
+    };
+
+    // Same payload bytes, different types
+    assert_ne!(encode(&Value::Int(1)), encode(&Value::Bytes(1i64.to_be_bytes().to_vec())));
+    assert_ne!(encode(&Value::Bool(true)), encode(&Value::Int(1)));
+    assert_ne!(encode(&Value::Address([0; 32])), encode(&Value::Bytes(vec![0; 32])));
+
+    let mut word = [0u8; 32];
+    word[31] = 1;
+    let mut a = HashMap::new();
+    a.insert(word, Value::Int(1));
+    let mut b = HashMap::new();
+    b.insert(word, Value::Bytes(1i64.to_be_bytes().to_vec()));
+    assert_ne!(storage_root(&a), storage_root(&b));
+}
+
+#[test]
+fn test_state_root_covers_memory_and_storage() {
+    let root = |program: Vec<u8>| {
+        let vm = VM::new(program);
+        vm.execute().unwrap();
+        vm.get_state_root()
+    };
+
+    // Same storage write, different memory
+    let base = root(vec![0x01, 0x07, 0x01, 0x01, 0x11, 0xFF]);
+    let with_memory = root(vec![0x01, 0x07, 0x01, 0x01, 0x11, 0x01, 0x09, 0x01, 0x02, 0x04, 0xFF]);
+    assert_ne!(base, with_memory);
+
+    // Storage written in a different order commits to the same root
+    let ab = root(vec![0x01, 0x07, 0x01, 0x01, 0x11, 0x01, 0x08, 0x01, 0x02, 0x11, 0xFF]);
+    let ba = root(vec![0x01, 0x08, 0x01, 0x02, 0x11, 0x01, 0x07, 0x01, 0x01, 0x11, 0xFF]);
+    assert_eq!(ab, ba);
+    assert_eq!(base, root(vec![0x01, 0x07, 0x01, 0x01, 0x11, 0xFF]));
+}
+
+#[test]
+fn test_identical_contracts_get_distinct_accounts() {
+    let code = [0x01, 0x05]; // PUSH 5
+    let once = deploy(&[], &code);
+    let twice = [deploy(&[], &code), deploy(&[], &code)].concat();
+
+    let vm = VM::new(twice);
+    vm.execute().unwrap();
+    let stack = vm.get_stack();
+    assert_eq!(stack.len(), 2);
+    assert_ne!(stack[0], stack[1]);
+
+    // Memory ends up the same, so only the second account tells them apart
+    let single = VM::new(once);
+    single.execute().unwrap();
+    assert_eq!(single.get_memory(), vm.get_memory());
+    assert_ne!(single.get_state_root(), vm.get_state_root());
+}